@@ -0,0 +1,109 @@
+use std::path::Path;
+
+/// INI Configuration File
+///
+/// `[section]` headers followed by `key = value` lines, `#` and `;` start
+/// comments. Values may be quoted or written as TOML style arrays, so simple
+/// TOML files read the same way.
+#[derive(Debug, Default)]
+pub struct Ini {
+    sections: Vec<(String, Vec<(String, String)>)>,
+}
+
+impl Ini {
+    pub fn load<P: AsRef<Path>>(filename: P) -> std::io::Result<Self> {
+        let text = std::fs::read_to_string(filename)?;
+        Self::parse(&text).map_err(|error| std::io::Error::new(std::io::ErrorKind::InvalidData, error))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut ini = Ini::default();
+        ini.sections.push((String::new(), Vec::new()));
+
+        for (number, line) in text.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            if let Some(name) = line.strip_prefix('[') {
+                let name = name.strip_suffix(']').ok_or(format!("line {}: unterminated section", number + 1))?;
+                ini.sections.push((name.trim().to_lowercase(), Vec::new()));
+            } else if let Some((key, value)) = line.split_once('=') {
+                let entries = &mut ini.sections.last_mut().unwrap().1;
+                entries.push((key.trim().to_lowercase(), value.trim().to_string()));
+            } else {
+                return Err(format!("line {}: expected `key = value`", number + 1));
+            }
+        }
+
+        Ok(ini)
+    }
+
+    /// Entries of a section in file order, keys lowercased
+    pub fn entries<'a>(&'a self, section: &'a str) -> impl Iterator<Item = (&'a str, &'a str)> + 'a {
+        self.sections.iter()
+            .filter(move |(name, _)| name == section)
+            .flat_map(|(_, entries)| entries.iter())
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    /// Last value of a key, unquoted
+    pub fn get(&self, section: &str, key: &str) -> Option<&str> {
+        self.sections.iter()
+            .filter(|(name, _)| name == section)
+            .flat_map(|(_, entries)| entries.iter())
+            .rfind(|(name, _)| name == key)
+            .map(|(_, value)| unquote(value))
+    }
+}
+
+/// Split a `a, "b", c` or `["a", "b"]` value into its unquoted items
+pub fn split_list(value: &str) -> Vec<&str> {
+    let value = value.trim();
+    let value = value.strip_prefix('[').and_then(|value| value.strip_suffix(']')).unwrap_or(value);
+    value.split(',')
+        .map(|item| unquote(item.trim()))
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+fn unquote(value: &str) -> &str {
+    value.strip_prefix('"').and_then(|value| value.strip_suffix('"')).unwrap_or(value)
+}
+
+// Comment markers inside quotes are part of the value
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (index, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '#' | ';' if !quoted => return &line[..index],
+            _ => {}
+        }
+    }
+    line
+}
+
+#[test]
+fn ini_parse_test() {
+    let ini = Ini::parse("
+        top = 1
+        # comment
+        [Input]
+        A = Space, \"pad:a\" ; trailing comment
+        turbo_period = 4
+        [input]
+        b = [\"X\", \"#\"]
+    ").unwrap();
+
+    assert_eq!(Some("1"), ini.get("", "top"));
+    assert_eq!(Some("4"), ini.get("input", "turbo_period"));
+    assert_eq!(None, ini.get("input", "start"));
+    assert_eq!(vec!["Space", "pad:a"], split_list(ini.get("input", "a").unwrap()));
+    assert_eq!(vec!["X", "#"], split_list(ini.get("input", "b").unwrap()));
+    assert_eq!(3, ini.entries("input").count());
+
+    assert!(Ini::parse("[input").is_err());
+    assert!(Ini::parse("[input]\nno value").is_err());
+}
@@ -0,0 +1,404 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::emulator::cpu::asm::{instruction_size, INST_ASM, INST_ASM_CB};
+use crate::emulator::symbols::Symbols;
+
+const BANK_SIZE: usize = 0x4000;
+
+// Header entry point, RST and interrupt vectors
+const ENTRY_POINTS: [u16; 14] = [
+    0x0100,
+    0x0000, 0x0008, 0x0010, 0x0018, 0x0020, 0x0028, 0x0030, 0x0038,
+    0x0040, 0x0048, 0x0050, 0x0058, 0x0060,
+];
+
+const INVALID_OPCODES: [u8; 11] = [0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD];
+
+// Runs of a repeated byte at least this long become a `DS`
+const MIN_FILL_RUN: usize = 32;
+
+const DATA_BYTES_PER_LINE: usize = 8;
+
+// How an instruction affects the control flow
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Flow {
+    Next,
+    Branch(u16),
+    Jump(u16),
+    Stop,
+}
+
+fn flow(opcode: u8, addr: u16, bytes: &[u8]) -> Flow {
+    let immediate16 = || u16::from_le_bytes([bytes[1], bytes[2]]);
+    let relative = || addr.wrapping_add(2).wrapping_add(bytes[1] as i8 as u16);
+
+    match opcode {
+        0xC3 => Flow::Jump(immediate16()),
+        0x18 => Flow::Jump(relative()),
+        0xC2 | 0xCA | 0xD2 | 0xDA | 0xC4 | 0xCC | 0xD4 | 0xDC | 0xCD => Flow::Branch(immediate16()),
+        0x20 | 0x28 | 0x30 | 0x38 => Flow::Branch(relative()),
+        0xC7 | 0xCF | 0xD7 | 0xDF | 0xE7 | 0xEF | 0xF7 | 0xFF => Flow::Branch((opcode & 0x38) as u16),
+        0xC9 | 0xD9 | 0xE9 => Flow::Stop,
+        _ if INVALID_OPCODES.contains(&opcode) => Flow::Stop,
+        _ => Flow::Next,
+    }
+}
+
+/// ROM Disassembler
+///
+/// Follows the control flow from the entry point and the vectors (recursive
+/// descent), everything it doesn't reach is data. Jumps into the switchable
+/// bank are only followed when the bank is known: from the bank itself, or
+/// when the ROM has a single switchable bank.
+///
+/// The output is RGBDS source that assembles back to the same ROM, laid out
+/// as `res/DMG_ROM.asm`.
+pub struct Disassembler<'a> {
+    rom: &'a [u8],
+    symbols: &'a Symbols,
+
+    // ROM offsets where instructions begin, and their length
+    code: BTreeMap<usize, usize>,
+
+    // Label names by ROM offset
+    labels: BTreeMap<usize, String>,
+}
+
+impl<'a> Disassembler<'a> {
+    pub fn new(rom: &'a [u8], symbols: &'a Symbols) -> Self {
+        let mut disassembler = Self {
+            rom,
+            symbols,
+            code: BTreeMap::new(),
+            labels: BTreeMap::new(),
+        };
+
+        for (bank, addr, name) in symbols.iter() {
+            if let Some(offset) = disassembler.rom_offset(bank, addr) {
+                disassembler.labels.entry(offset).or_insert_with(|| name.to_string());
+            }
+        }
+        for addr in ENTRY_POINTS.iter() {
+            disassembler.trace(0, *addr);
+        }
+        disassembler
+    }
+
+    fn bank_count(&self) -> usize {
+        (self.rom.len() + BANK_SIZE - 1) / BANK_SIZE
+    }
+
+    fn rom_offset(&self, bank: u16, addr: u16) -> Option<usize> {
+        let offset = match addr {
+            0x0000..=0x3FFF if bank == 0 => addr as usize,
+            0x4000..=0x7FFF if bank > 0 => bank as usize * BANK_SIZE + (addr as usize - BANK_SIZE),
+            _ => return None,
+        };
+        Some(offset).filter(|offset| *offset < self.rom.len())
+    }
+
+    // Bank the CPU sees at `addr` while running from `bank`
+    fn target_bank(&self, bank: u16, addr: u16) -> Option<u16> {
+        match addr {
+            0x0000..=0x3FFF => Some(0),
+            0x4000..=0x7FFF if bank > 0 => Some(bank),
+            0x4000..=0x7FFF if self.bank_count() == 2 => Some(1),
+            _ => None,
+        }
+    }
+
+    fn trace(&mut self, bank: u16, addr: u16) {
+        let mut pending = vec![(bank, addr)];
+
+        while let Some((bank, mut addr)) = pending.pop() {
+            while let Some(offset) = self.rom_offset(bank, addr) {
+                if self.code.contains_key(&offset) || self.is_inside_instruction(offset) {
+                    break;
+                }
+
+                let opcode = self.rom[offset];
+                let size = instruction_size(opcode) as usize;
+                let bank_end = (offset / BANK_SIZE + 1) * BANK_SIZE;
+                if INVALID_OPCODES.contains(&opcode) || offset + size > bank_end.min(self.rom.len()) {
+                    break;
+                }
+                if (offset + 1..offset + size).any(|offset| self.code.contains_key(&offset)) {
+                    break;
+                }
+                self.code.insert(offset, size);
+
+                let (target, stop) = match flow(opcode, addr, &self.rom[offset..offset + size]) {
+                    Flow::Next => (None, false),
+                    Flow::Branch(target) => (Some(target), false),
+                    Flow::Jump(target) => (Some(target), true),
+                    Flow::Stop => (None, true),
+                };
+                if let Some(target) = target {
+                    if let Some(target_bank) = self.target_bank(bank, target) {
+                        if let Some(target_offset) = self.rom_offset(target_bank, target) {
+                            self.labels.entry(target_offset).or_insert_with(|| auto_label(target_bank, target));
+                            pending.push((target_bank, target));
+                        }
+                    }
+                }
+                if stop {
+                    break;
+                }
+                addr = addr.wrapping_add(size as u16);
+            }
+        }
+    }
+
+    fn is_inside_instruction(&self, offset: usize) -> bool {
+        self.code.range(..offset).next_back().is_some_and(|(start, size)| start + size > offset)
+    }
+
+    // Labels are only written where a line begins
+    fn label_at(&self, offset: usize) -> Option<&str> {
+        if self.is_inside_instruction(offset) {
+            return None;
+        }
+        self.labels.get(&offset).map(String::as_str)
+    }
+
+    // Name for an address used as an operand, `$XXXX` when it has none
+    fn address_name(&self, bank: u16, addr: u16) -> String {
+        let name = match addr {
+            0x0000..=0x7FFF => self.target_bank(bank, addr)
+                .and_then(|bank| self.rom_offset(bank, addr))
+                .and_then(|offset| self.label_at(offset)),
+            _ => self.ram_symbol(addr),
+        };
+        name.map_or_else(|| format!("${:04X}", addr), str::to_string)
+    }
+
+    fn ram_symbol(&self, addr: u16) -> Option<&str> {
+        self.symbols.iter().find(|(_, symbol_addr, _)| *symbol_addr == addr && addr >= 0x8000).map(|(_, _, name)| name)
+    }
+
+    fn instruction_text(&self, bank: u16, addr: u16, bytes: &[u8]) -> String {
+        let opcode = bytes[0];
+        let immediate8 = bytes.get(1).copied().unwrap_or(0);
+        let immediate16 = u16::from_le_bytes([immediate8, bytes.get(2).copied().unwrap_or(0)]);
+
+        match opcode {
+            0x08 => format!("LD [{}], SP", self.address_name(bank, immediate16)),
+            0x10 => format!("STOP ${:02X}", immediate8),
+            0x22 => "LD [HLI], A".to_string(),
+            0x2A => "LD A, [HLI]".to_string(),
+            0x32 => "LD [HLD], A".to_string(),
+            0x3A => "LD A, [HLD]".to_string(),
+            0x76 => "HALT".to_string(),
+            0xCB => brackets(INST_ASM_CB[immediate8 as usize]),
+            0xE0 => format!("LDH [{}], A", self.address_name(bank, 0xFF00 | immediate8 as u16)),
+            0xF0 => format!("LDH A, [{}]", self.address_name(bank, 0xFF00 | immediate8 as u16)),
+            0xE2 => "LDH [C], A".to_string(),
+            0xF2 => "LDH A, [C]".to_string(),
+            0xE8 => format!("ADD SP, {}", immediate8 as i8),
+            0xF8 => format!("LD HL, SP{:+}", immediate8 as i8),
+            _ => {
+                let template = INST_ASM[opcode as usize];
+                match flow(opcode, addr, bytes) {
+                    // `JR NZ $00` becomes `JR NZ, Label`
+                    Flow::Branch(target) | Flow::Jump(target) if !template.starts_with("RST") => {
+                        let mnemonic = template.split(" $").next().unwrap();
+                        let separator = if mnemonic.contains(' ') { ", " } else { " " };
+                        format!("{}{}{}", mnemonic, separator, self.address_name(bank, target))
+                    }
+                    _ => match instruction_size(opcode) {
+                        2 => brackets(template).replace("$00", &format!("${:02X}", immediate8)),
+                        3 => brackets(template).replace("$0000", &self.address_name(bank, immediate16)),
+                        _ => brackets(template),
+                    },
+                }
+            }
+        }
+    }
+
+    /// RGBDS source of the whole ROM
+    pub fn listing(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "; Disassembled by kiwi").unwrap();
+
+        let constants: Vec<(u16, &str)> = self.symbols.iter()
+            .filter(|(_, addr, _)| *addr >= 0x8000)
+            .map(|(_, addr, name)| (addr, name))
+            .collect();
+        if !constants.is_empty() {
+            writeln!(out).unwrap();
+            for (addr, name) in constants {
+                writeln!(out, "{} EQU ${:04X}", name, addr).unwrap();
+            }
+        }
+
+        for bank in 0..self.bank_count() {
+            writeln!(out).unwrap();
+            if bank == 0 {
+                writeln!(out, "SECTION \"ROM Bank $000\", ROM0[$0000]").unwrap();
+            } else {
+                writeln!(out, "SECTION \"ROM Bank ${:03X}\", ROMX[$4000], BANK[${:X}]", bank, bank).unwrap();
+            }
+            self.write_bank(&mut out, bank as u16);
+        }
+        out
+    }
+
+    fn write_bank(&self, out: &mut String, bank: u16) {
+        let begin = bank as usize * BANK_SIZE;
+        let end = (begin + BANK_SIZE).min(self.rom.len());
+        let base = if bank == 0 { 0 } else { BANK_SIZE };
+
+        let mut offset = begin;
+        while offset < end {
+            let addr = (offset - begin + base) as u16;
+            if let Some(label) = self.label_at(offset) {
+                writeln!(out, "{}:", label).unwrap();
+            }
+
+            if let Some(size) = self.code.get(&offset) {
+                let bytes = &self.rom[offset..offset + size];
+                let text = self.instruction_text(bank, addr, bytes);
+                writeln!(out, "\t{:<24}; ${:04x}", text, addr).unwrap();
+                if matches!(flow(bytes[0], addr, bytes), Flow::Jump(_) | Flow::Stop) {
+                    writeln!(out).unwrap();
+                }
+                offset += size;
+                continue;
+            }
+
+            // data up to the next line that needs its own start
+            let data_end = (offset + 1..end)
+                .find(|offset| self.code.contains_key(offset) || self.labels.contains_key(offset))
+                .unwrap_or(end);
+            let data = &self.rom[offset..data_end];
+
+            let run = data.iter().take_while(|byte| **byte == data[0]).count();
+            let len = if run >= MIN_FILL_RUN {
+                writeln!(out, "\t{:<24}; ${:04x}", format!("DS {}, ${:02X}", run, data[0]), addr).unwrap();
+                run
+            } else {
+                // stop before a fill run, it gets its own line
+                let len = (0..data.len().min(DATA_BYTES_PER_LINE))
+                    .find(|index| *index > 0 && data[*index..].iter().take_while(|byte| **byte == data[*index]).count() >= MIN_FILL_RUN)
+                    .unwrap_or_else(|| data.len().min(DATA_BYTES_PER_LINE));
+                let bytes: Vec<String> = data[..len].iter().map(|byte| format!("${:02X}", byte)).collect();
+                writeln!(out, "\t{:<24}; ${:04x}", format!("DB {}", bytes.join(", ")), addr).unwrap();
+                len
+            };
+            offset += len;
+        }
+    }
+}
+
+fn auto_label(bank: u16, addr: u16) -> String {
+    if bank == 0 {
+        format!("Addr_{:04X}", addr)
+    } else {
+        format!("Addr_{:02X}_{:04X}", bank, addr)
+    }
+}
+
+// RGBDS writes memory operands in brackets
+fn brackets(template: &str) -> String {
+    template.replace('(', "[").replace(')', "]")
+}
+
+/// `kiwi disasm <rom> [--sym <file>] [-o <file>]`
+/// - `<rom>.sym` is loaded when it exists and no symbol file is given
+pub fn run(args: &[String]) -> std::io::Result<()> {
+    let usage = || std::io::Error::new(std::io::ErrorKind::InvalidInput, "usage: kiwi disasm <rom> [--sym <file>] [-o <file>]");
+    let value = |name: &str| args.iter().position(|arg| arg == name).and_then(|index| args.get(index + 1));
+
+    let rom_filename = args.iter()
+        .enumerate()
+        .find(|(index, arg)| !arg.starts_with('-') && (*index == 0 || !args[index - 1].starts_with('-')))
+        .map(|(_, arg)| arg)
+        .ok_or_else(usage)?;
+    let rom = std::fs::read(rom_filename)?;
+
+    let default_sym = std::path::Path::new(rom_filename).with_extension("sym");
+    let symbols = match value("--sym") {
+        Some(filename) => Symbols::load(filename)?,
+        None if default_sym.exists() => Symbols::load(&default_sym)?,
+        None => Symbols::default(),
+    };
+
+    let listing = Disassembler::new(&rom, &symbols).listing();
+    match value("-o") {
+        Some(filename) => std::fs::write(filename, listing),
+        None => {
+            print!("{}", listing);
+            Ok(())
+        }
+    }
+}
+
+#[test]
+fn disassembler_test() {
+    let mut rom = vec![0xFF; 0x8000];
+    // $0000-$0067: RST $38 loops in the vectors
+    rom[0x0100..0x0104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);  // NOP; JP $0150
+    rom[0x0104..0x0150].iter_mut().for_each(|byte| *byte = 0x00);    // header
+    rom[0x0150..0x0163].copy_from_slice(&[
+        0x21, 0x00, 0xC0,       // $0150 LD HL, wBuffer
+        0xE0, 0x80,             // $0153 LDH [$FF80], A
+        0x20, 0x02,             // $0155 JR NZ, Addr_0159
+        0xCB, 0x7C,             // $0157 BIT 7, H
+        0xCD, 0x00, 0x40,       // $0159 CALL Bank1
+        0x18, 0xF2,             // $015C JR Start
+        0x12, 0x34,             // $015E data
+        0xEA, 0x00, 0xC0,       // $0160 data, never reached
+    ]);
+    rom[0x4000..0x4003].copy_from_slice(&[0x3E, 0x2A, 0xC9]);  // LD A, $2A; RET
+
+    let symbols = Symbols::parse("00:0150 Start\n01:4000 Bank1\n00:C000 wBuffer\n").unwrap();
+    let disassembler = Disassembler::new(&rom, &symbols);
+    assert!(disassembler.code.contains_key(&0x0159));
+    assert!(!disassembler.code.contains_key(&0x015E));
+    assert!(!disassembler.code.contains_key(&0x0160));
+    assert!(disassembler.code.contains_key(&0x4002));
+
+    let listing = disassembler.listing();
+    let lines: Vec<&str> = listing.lines().map(str::trim_end).collect();
+    let start = lines.iter().position(|line| *line == "Start:").unwrap();
+    assert_eq!(vec![
+        "Start:",
+        "\tLD HL, wBuffer          ; $0150",
+        "\tLDH [$FF80], A          ; $0153",
+        "\tJR NZ, Addr_0159        ; $0155",
+        "\tBIT 7, H                ; $0157",
+        "Addr_0159:",
+        "\tCALL Bank1              ; $0159",
+        "\tJR Start                ; $015c",
+        "",
+        "\tDB $12, $34, $EA, $00, $C0; $015e",
+    ], lines[start..start + 10].to_vec());
+
+    assert!(lines.contains(&"wBuffer EQU $C000"));
+    assert!(lines.contains(&"SECTION \"ROM Bank $001\", ROMX[$4000], BANK[$1]"));
+    assert!(lines.contains(&"\tLD A, $2A               ; $4000"));
+    assert!(lines.contains(&"\tRET                     ; $4002"));
+    assert!(lines.iter().any(|line| line.starts_with("\tDS ") && line.contains("$FF")));
+    assert!(lines.contains(&"\tNOP                     ; $0100"));
+}
+
+#[test]
+fn disassembler_stop_ldh_c_test() {
+    let mut rom = vec![0x00; 0x8000];
+    rom[0x0100..0x0106].copy_from_slice(&[
+        0xF2,                   // $0100 LDH A, [C]
+        0x10, 0x42,             // $0101 STOP $42
+        0xC3, 0x00, 0x01,       // $0103 JP $0100
+    ]);
+
+    let listing = Disassembler::new(&rom, &Symbols::default()).listing();
+    let lines: Vec<&str> = listing.lines().map(str::trim_end).collect();
+    let start = lines.iter().position(|line| *line == "Addr_0100:").unwrap();
+    assert_eq!(vec![
+        "Addr_0100:",
+        "\tLDH A, [C]              ; $0100",
+        "\tSTOP $42                ; $0101",
+        "\tJP Addr_0100            ; $0103",
+    ], lines[start..start + 4].to_vec());
+}
@@ -1,15 +1,42 @@
-mod bios;
+pub mod bios;
+pub mod capture;
 mod cartridge;
+pub mod cheats;
 pub mod cpu;
+pub mod debugger;
 pub mod engine;
 pub mod ppu;
+pub mod rewind;
+pub mod search;
 pub mod joypad;
 pub mod mmu;
+pub mod model;
+pub mod movie;
 pub mod serial;
 pub mod sound;
+pub mod state;
+pub mod symbols;
+#[cfg(test)]
+pub mod test_fixture;
 pub mod timer;
+pub mod trace;
 
 use engine::Engine;
+use model::Model;
+use movie::{movie_busy, Movie, MovieState, Start};
+use rewind::Rewind;
+use joypad::bindings::Bindings;
+use serial::SerialSink;
+use serial::link::LinkPort;
+use sound::blip::Quality;
+use state::{Snapshot, StateReader, StateWriter, STATE_MAGIC, STATE_VERSION};
+use state::invalid_state;
+use capture::{save_png, VideoRecorder};
+use cheats::Cheats;
+use symbols::Symbols;
+use trace::Tracer;
+#[cfg(test)]
+use test_fixture::{bootable_rom, TempFile, ROM_SIZE};
 
 use sdl2::audio::AudioQueue;
 use sdl2::render::Texture;
@@ -18,6 +45,22 @@ use sdl2::render::Texture;
 pub struct Emulator {
     clock: u64,
     engine: Box<Engine>,
+
+    // Frames run since the ROM was opened
+    frame: u64,
+
+    // Snapshots taken while running, none unless enabled with set_rewind
+    rewind: Option<Rewind>,
+
+    // Screen recording, the sound goes to a WAV file next to it
+    video: Option<VideoRecorder>,
+
+    // Save state right after the ROM was opened, where power-on movies start
+    power_on_state: Vec<u8>,
+
+    // Input movie being recorded or played, and the frame it started at
+    movie: Option<MovieState>,
+    movie_start: u64,
 }
 
 impl Emulator {
@@ -25,6 +68,14 @@ impl Emulator {
         Emulator {
             clock: 0,
             engine: Box::new(Engine::default()),
+
+            frame: 0,
+            rewind: None,
+            video: None,
+
+            power_on_state: Vec::new(),
+            movie: None,
+            movie_start: 0,
         }
     }
 
@@ -32,23 +83,637 @@ impl Emulator {
         self.engine.blit_frame_to_texture(texture);
     }
 
-    pub fn enqueue_audio_samples(&mut self, channels: &mut [AudioQueue<i8>; 4]) {
+    pub fn enqueue_audio_samples(&mut self, channels: &mut [AudioQueue<i16>; 4]) {
         self.engine.enqueue_audio_samples(channels);
     }
 
+    pub fn set_audio_sample_rate(&mut self, sample_rate: u32) {
+        self.engine.set_audio_sample_rate(sample_rate);
+    }
+
+    pub fn set_audio_quality(&mut self, quality: Quality) {
+        self.engine.set_audio_quality(quality);
+    }
+
+    /// Print the sound register writes to stdout, on by default
+    pub fn set_audio_register_log(&mut self, enabled: bool) {
+        self.engine.set_audio_register_log(enabled);
+    }
+
+    pub fn start_audio_recording(&mut self, filename: &str, stems: bool) -> std::io::Result<()> {
+        self.engine.start_audio_recording(filename, stems)
+    }
+
+    pub fn stop_audio_recording(&mut self) -> std::io::Result<()> {
+        self.engine.stop_audio_recording()
+    }
+
+    pub fn is_audio_recording(&self) -> bool {
+        self.engine.is_audio_recording()
+    }
+
+    /// Save the screen as PNG, every pixel a `scale` x `scale` square
+    pub fn save_screenshot(&self, filename: &str, scale: usize) -> std::io::Result<()> {
+        save_png(filename, self.engine.ppu().frame_buffer(), scale)
+    }
+
+    /// Record every frame to an animated GIF (`.gif`) or raw RGB frames, and
+    /// the sound to a WAV file next to it (`game.gif` and `game.wav`)
+    pub fn start_video_recording(&mut self, filename: &str, scale: usize) -> std::io::Result<()> {
+        self.stop_video_recording()?;
+        let video = VideoRecorder::create(filename, scale)?;
+        let audio_filename = std::path::Path::new(filename).with_extension("wav");
+        self.engine.start_video_soundtrack(&audio_filename.to_string_lossy())?;
+        self.video = Some(video);
+        Ok(())
+    }
+
+    pub fn stop_video_recording(&mut self) -> std::io::Result<()> {
+        if let Some(video) = self.video.take() {
+            video.finish()?;
+            self.engine.stop_video_soundtrack()?;
+        }
+        Ok(())
+    }
+
+    pub fn is_video_recording(&self) -> bool {
+        self.video.is_some()
+    }
+
+    /// MD5 of the screen, to compare frames without keeping them
+    pub fn frame_hash(&self) -> [u8; 16] {
+        md5::compute(self.engine.ppu().frame_buffer()).0
+    }
+
+    /// Log the keys of every frame from now on
+    ///
+    /// The movie starts at power-on when nothing ran since the ROM was opened,
+    /// from a save state otherwise. A playing movie isn't replaced.
+    pub fn start_movie_recording(&mut self) -> std::io::Result<()> {
+        if self.is_movie_playing() {
+            return Err(movie_busy("a movie is playing"));
+        }
+
+        let state = self.save_state();
+        let start = if self.frame == 0 && state == self.power_on_state { Start::PowerOn } else { Start::State(state) };
+        self.engine.set_host_input(false);
+        self.movie = Some(MovieState::Recording(Movie::new(self.engine.rom_digest(), start)));
+        self.movie_start = self.frame;
+        Ok(())
+    }
+
+    /// The movie recorded so far, ending on the current frame
+    pub fn stop_movie_recording(&mut self) -> Option<Movie> {
+        match self.movie.take() {
+            Some(MovieState::Recording(mut movie)) => {
+                movie.final_frame = Some(self.frame_hash());
+                self.engine.set_host_input(true);
+                Some(movie)
+            }
+            movie => {
+                self.movie = movie;
+                None
+            }
+        }
+    }
+
+    pub fn is_movie_recording(&self) -> bool {
+        matches!(self.movie, Some(MovieState::Recording(_)))
+    }
+
+    /// Restore the start of a movie of the opened ROM and replay its keys,
+    /// the host inputs take over again after the last frame
+    pub fn play_movie(&mut self, movie: Movie) -> std::io::Result<()> {
+        if movie.rom_digest != self.engine.rom_digest() {
+            return Err(invalid_state("movie belongs to another ROM"));
+        }
+
+        self.stop_movie_recording();
+        match &movie.start {
+            Start::PowerOn => {
+                let state = self.power_on_state.clone();
+                self.restore_state(&state)?;
+                self.frame = 0;
+            }
+            Start::State(state) => self.restore_state(state)?,
+        }
+        if let Some(rewind) = self.rewind.as_mut() {
+            rewind.clear();
+        }
+
+        self.engine.set_host_input(false);
+        self.movie = Some(MovieState::Playing(movie));
+        self.movie_start = self.frame;
+        self.stop_finished_movie();
+        Ok(())
+    }
+
+    pub fn is_movie_playing(&self) -> bool {
+        matches!(self.movie, Some(MovieState::Playing(_)))
+    }
+
+    fn stop_finished_movie(&mut self) {
+        if let Some(MovieState::Playing(movie)) = self.movie.as_ref() {
+            if self.frame - self.movie_start >= movie.frames.len() as u64 {
+                if movie.final_frame.is_some_and(|hash| hash != self.frame_hash()) {
+                    println!("Movie desynchronized, the last frame differs from the recording");
+                }
+                self.movie = None;
+                self.engine.set_host_input(true);
+            }
+        }
+    }
+
+    /// Save State
+    ///
+    /// `KIWISAVE`, format version (u32), MD5 of the ROM (16 bytes), leftover
+    /// frame ticks (u64) and the machine state.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        state.write_bytes(STATE_MAGIC);
+        state.write_u32(STATE_VERSION);
+        state.write_bytes(&self.engine.rom_digest());
+        state.write_u64(self.clock);
+        self.engine.save_state(&mut state);
+        state.into_bytes()
+    }
+
+    /// Restore a save state of the same ROM, the machine is unchanged on error
+    ///
+    /// Refused while a movie records or plays, its frames would no longer
+    /// follow from its start.
+    pub fn load_state(&mut self, data: &[u8]) -> std::io::Result<()> {
+        if self.movie.is_some() {
+            return Err(movie_busy("a movie is recording or playing"));
+        }
+        self.restore_state(data)
+    }
+
+    fn restore_state(&mut self, data: &[u8]) -> std::io::Result<()> {
+        let mut state = StateReader::new(data);
+
+        let mut magic = [0; 8];
+        state.read_bytes(&mut magic)?;
+        if &magic != STATE_MAGIC {
+            return Err(invalid_state("not a save state"));
+        }
+
+        let version = state.read_u32()?;
+        if version != STATE_VERSION {
+            return Err(invalid_state(&format!("unsupported save state version {}", version)));
+        }
+
+        let mut digest = [0; 16];
+        state.read_bytes(&mut digest)?;
+        if digest != self.engine.rom_digest() {
+            return Err(invalid_state("save state belongs to another ROM"));
+        }
+
+        let backup = self.save_state();
+        let result = state.read_u64()
+            .and_then(|clock| {
+                self.clock = clock;
+                self.engine.load_state(&mut state)
+            })
+            .and_then(|()| match state.is_empty() {
+                true => Ok(()),
+                false => Err(invalid_state("trailing data after the save state")),
+            });
+
+        if result.is_err() {
+            self.restore_state(&backup).unwrap();
+        }
+        result
+    }
+
+    pub fn save_state_file(&self, filename: &str) -> std::io::Result<()> {
+        std::fs::write(filename, self.save_state())
+    }
+
+    pub fn load_state_file(&mut self, filename: &str) -> std::io::Result<()> {
+        let data = std::fs::read(filename)?;
+        self.load_state(&data)
+    }
+
+    /// Keep a snapshot every `interval` frames within `budget` bytes, or disable rewinding
+    pub fn set_rewind(&mut self, interval: u64, budget: usize) {
+        self.rewind = if budget > 0 { Some(Rewind::new(interval, budget)) } else { None };
+    }
+
+    /// Step one frame back in time, the frame plays silence
+    ///
+    /// Restores the newest snapshot at or before the previous frame and runs
+    /// muted up to it. Returns false once the history is exhausted, or while
+    /// a movie records or plays.
+    pub fn rewind_frame(&mut self) -> bool {
+        if self.movie.is_some() {
+            return false;
+        }
+
+        let mut rewind = match self.rewind.take() {
+            Some(rewind) => rewind,
+            None => return false,
+        };
+
+        let mut rewound = false;
+        if let Some(target) = self.frame.checked_sub(1) {
+            while let Some((frame, state)) = rewind.newest() {
+                if frame > target {
+                    rewind.pop();
+                    continue;
+                }
+
+                let state = state.to_vec();
+                if self.restore_state(&state).is_ok() {
+                    self.frame = frame;
+
+                    // replayed frames already went past the debugger once
+                    let debugging = self.engine.debugger().is_enabled();
+                    self.engine.debugger_mut().set_enabled(false);
+                    self.engine.set_audio_muted(true);
+                    while self.frame < target {
+                        self.engine.run_next_frame(&mut self.clock);
+                        self.frame += 1;
+                    }
+                    self.engine.set_audio_muted(false);
+                    self.engine.debugger_mut().set_enabled(debugging);
+                    rewound = true;
+                }
+                break;
+            }
+        }
+
+        self.engine.silence_audio_frame();
+        self.rewind = Some(rewind);
+        rewound
+    }
+
+    /// Open a ROM along with the RGBDS symbols and the cheats next to it
+    /// (`game.gb`, `game.sym` and `game.cht`)
     pub fn open_rom_file(&mut self, filename: &str) {
         self.engine.open_rom_file(filename);
+        self.frame = 0;
+        if let Some(rewind) = self.rewind.as_mut() {
+            rewind.clear();
+        }
+        if self.movie.take().is_some() {
+            self.engine.set_host_input(true);
+        }
+
+        self.engine.set_symbols(Symbols::default());
+        let symbols_filename = std::path::Path::new(filename).with_extension("sym");
+        if symbols_filename.exists() {
+            if let Err(error) = self.load_symbols(symbols_filename.to_str().unwrap()) {
+                println!("Failed to load symbols {}: {}", symbols_filename.display(), error);
+            }
+        }
+
+        self.engine.set_cheats(Cheats::default());
+        let cheats_filename = std::path::Path::new(filename).with_extension("cht");
+        if cheats_filename.exists() {
+            if let Err(error) = self.load_cheats(cheats_filename.to_str().unwrap()) {
+                println!("Failed to load cheats {}: {}", cheats_filename.display(), error);
+            }
+        }
+
+        self.power_on_state = self.save_state();
+    }
+
+    /// Boot from a boot ROM dump instead of the embedded one
+    /// - DMG0, DMG, MGB and SGB boot ROMs are 256 bytes, CGB ones 2304
+    pub fn load_boot_rom(&mut self, filename: &str) -> std::io::Result<()> {
+        let data = std::fs::read(filename)?;
+        if data.len() != 0x100 && data.len() != 0x900 {
+            let message = format!("boot ROM is {} bytes, not 256 or 2304", data.len());
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, message));
+        }
+        self.engine.set_bios(data);
+        Ok(())
+    }
+
+    /// Start the opened ROM at $0100 as if the boot ROM of the model had run
+    pub fn skip_boot(&mut self, model: Model) {
+        self.engine.skip_boot(model);
+    }
+
+    /// Replace the cheats with the ones of a cheat file
+    pub fn load_cheats(&mut self, filename: &str) -> std::io::Result<()> {
+        let cheats = Cheats::load(filename)?;
+        println!("Loaded {} cheats from {}", cheats.len(), filename);
+        self.engine.set_cheats(cheats);
+        Ok(())
+    }
+
+    /// Name addresses after an RGBDS symbol file in the debugger, traces and disassembly
+    pub fn load_symbols(&mut self, filename: &str) -> std::io::Result<()> {
+        let symbols = Symbols::load(filename)?;
+        println!("Loaded {} symbols from {}", symbols.len(), filename);
+        self.engine.set_symbols(symbols);
+        Ok(())
+    }
+
+    /// Plug the link cable into a partner (another emulator, a socket or a loopback)
+    pub fn connect_serial_link(&mut self, link: Box<dyn LinkPort>) {
+        self.engine.connect_serial_link(link);
+    }
+
+    /// Capture the bytes sent through the serial port while unplugged
+    pub fn set_serial_sink(&mut self, sink: Option<SerialSink>) {
+        self.engine.set_serial_sink(sink);
+    }
+
+    /// Bytes captured so far by a `SerialSink::Buffer`
+    pub fn serial_output(&self) -> &[u8] {
+        self.engine.serial_output()
+    }
+
+    /// Log executed instructions, `None` stops and flushes the log
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.engine.set_tracer(tracer);
+    }
+
+    pub fn set_input_bindings(&mut self, bindings: Bindings) {
+        self.engine.set_input_bindings(bindings);
     }
 
     pub fn process_event(&mut self, event: &sdl2::event::Event) {
         self.engine.process_event(event);
     }
 
-    pub fn run_next_frame(&mut self) {
-        self.clock = self.engine.run_next_frame(self.clock);
+    /// Returns false when the debugger stopped the emulation before the end of the frame
+    pub fn run_next_frame(&mut self) -> bool {
+        // movie keys stay the same for the whole frame, even when it's resumed
+        let position = (self.frame - self.movie_start) as usize;
+        match self.movie.as_mut() {
+            Some(MovieState::Recording(movie)) => {
+                if movie.frames.len() == position {
+                    self.engine.set_joypad_keys(self.engine.host_joypad_keys());
+                    movie.frames.push(self.engine.joypad_keys());
+                }
+                self.engine.set_joypad_keys(movie.frames[position]);
+            }
+            Some(MovieState::Playing(movie)) => self.engine.set_joypad_keys(movie.frames[position]),
+            None => {}
+        }
+
+        if !self.engine.run_next_frame(&mut self.clock) {
+            return false;
+        }
+        self.frame += 1;
+        self.stop_finished_movie();
+
+        if let Some(video) = self.video.as_mut() {
+            if let Err(error) = video.push_frame(self.engine.ppu().frame_buffer()) {
+                println!("Video recording failed: {}", error);
+                self.video = None;
+                if let Err(error) = self.engine.stop_video_soundtrack() {
+                    println!("Video soundtrack failed: {}", error);
+                }
+            }
+        }
+
+        if let Some(rewind) = self.rewind.as_ref() {
+            if self.frame % rewind.interval() == 0 {
+                let state = self.save_state();
+                self.rewind.as_mut().unwrap().push(self.frame, state);
+            }
+        }
+        true
+    }
+
+    pub fn engine(&self) -> &Engine {
+        &self.engine
+    }
+
+    /// Direct access to the machine, for the debugger and tools
+    pub fn engine_mut(&mut self) -> &mut Engine {
+        &mut self.engine
     }
 }
 
 impl Default for Emulator {
     fn default() -> Self { Self::new() }
 }
+
+#[test]
+fn emulator_skip_boot_test() {
+    let mut rom = bootable_rom();
+    rom[0x0100..0x0102].copy_from_slice(&[0x18, 0xFE]);
+    let rom = TempFile::rom("skip-boot", &rom);
+
+    let mut emulator = Emulator::new();
+    emulator.open_rom_file(rom.to_str());
+    emulator.skip_boot(Model::Dmg);
+
+    let engine = emulator.engine();
+    let regs = engine.cpu_regs();
+    assert_eq!((0x01B0, 0x0013, 0x00D8, 0x014D), (regs.af(), regs.bc(), regs.de(), regs.hl()));
+    assert_eq!((0xFFFE, 0x0100), (regs.sp(), regs.pc()));
+    assert_eq!(0x00, engine.peek(0x0000));
+    assert_eq!((0x91, 0xFC, 0xAB, 0xE1), (engine.peek(0xFF40), engine.peek(0xFF47), engine.peek(0xFF04), engine.peek(0xFF0F)));
+
+    // the logo is left in VRAM: $CE = 1100 1110 doubles into $F0 and $FC
+    assert_eq!([0xF0, 0x00, 0xF0, 0x00, 0xFC], [0x8010, 0x8011, 0x8012, 0x8013, 0x8014].map(|addr| engine.peek(addr)));
+    assert_eq!((0x01, 0x0D, 0x19), (engine.peek(0x9904), engine.peek(0x9924), engine.peek(0x9910)));
+
+    emulator.run_next_frame();
+    assert!(emulator.load_boot_rom(rom.with_extension("bin").to_str()).is_err());
+}
+
+#[test]
+fn emulator_capture_test() {
+    let rom = TempFile::blank_rom("capture");
+    let (gif_file, wav_file, png_file) = (rom.with_extension("gif"), rom.with_extension("wav"), rom.with_extension("png"));
+
+    let audio_file = TempFile::new("capture-audio", "wav");
+
+    // the audio recording runs on its own across the video one
+    let mut emulator = Emulator::new();
+    emulator.open_rom_file(rom.to_str());
+    emulator.start_audio_recording(audio_file.to_str(), false).unwrap();
+    emulator.start_video_recording(gif_file.to_str(), 1).unwrap();
+    for _ in 0..3 {
+        emulator.run_next_frame();
+    }
+    assert!(emulator.is_video_recording());
+    emulator.stop_video_recording().unwrap();
+    assert!(!emulator.is_video_recording());
+    assert!(emulator.is_audio_recording());
+    emulator.run_next_frame();
+    emulator.stop_audio_recording().unwrap();
+    assert!(std::fs::metadata(audio_file.path()).unwrap().len() > std::fs::metadata(wav_file.path()).unwrap().len());
+    emulator.save_screenshot(png_file.to_str(), 2).unwrap();
+
+    let gif = std::fs::read(gif_file.path()).unwrap();
+    assert_eq!((&b"GIF89a"[..], Some(&0x3B)), (&gif[..6], gif.last()));
+    assert!(std::fs::metadata(wav_file.path()).unwrap().len() > 44);
+    let png = std::fs::read(png_file.path()).unwrap();
+    assert_eq!(&[0, 0, 0x01, 0x40, 0, 0, 0x01, 0x20], &png[16..24]);
+}
+
+#[test]
+fn emulator_movie_test() {
+    use joypad::JoypadKeys;
+    use sdl2::event::Event;
+    use sdl2::keyboard::{Keycode, Mod};
+
+    let mut rom = vec![0; ROM_SIZE];
+    // read the direction keys and sum them at $C000 forever
+    rom[0x0100..0x010D].copy_from_slice(&[0x3E, 0x20, 0xE0, 0x00, 0xF0, 0x00, 0x21, 0x00, 0xC0, 0x86, 0x77, 0x18, 0xF3]);
+    let rom = TempFile::rom("movie", &rom);
+    let key = |keycode, down| match down {
+        true => Event::KeyDown { timestamp: 0, window_id: 0, keycode: Some(keycode), scancode: None, keymod: Mod::empty(), repeat: false },
+        false => Event::KeyUp { timestamp: 0, window_id: 0, keycode: Some(keycode), scancode: None, keymod: Mod::empty(), repeat: false },
+    };
+
+    let mut emulator = Emulator::new();
+    emulator.open_rom_file(rom.to_str());
+    emulator.start_movie_recording().unwrap();
+    assert_eq!(Start::PowerOn, emulator.stop_movie_recording().unwrap().start);
+
+    emulator.skip_boot(Model::Dmg);
+    emulator.start_movie_recording().unwrap();
+    assert!(emulator.is_movie_recording());
+    assert!(emulator.load_state(&emulator.save_state()).is_err());
+    for frame in 0..12 {
+        emulator.process_event(&key(Keycode::Right, frame % 3 == 1));
+        emulator.run_next_frame();
+    }
+    let movie = emulator.stop_movie_recording().unwrap();
+    let expected = (emulator.save_state(), emulator.frame_hash(), emulator.engine().peek(0xC000));
+    assert!(matches!(movie.start, Start::State(_)));
+    assert_eq!((12, Some(expected.1)), (movie.frames.len(), movie.final_frame));
+    assert_eq!((JoypadKeys::empty(), JoypadKeys::RIGHT), (movie.frames[0], movie.frames[1]));
+
+    // the host keys don't reach the game while the movie plays
+    emulator.play_movie(Movie::parse(&movie.to_text()).unwrap()).unwrap();
+    assert!(emulator.start_movie_recording().is_err());
+    assert!(emulator.load_state(&expected.0).is_err());
+    emulator.process_event(&key(Keycode::Left, true));
+    for _ in 0..6 {
+        emulator.run_next_frame();
+    }
+    emulator.process_event(&key(Keycode::Left, false));
+    while emulator.is_movie_playing() {
+        emulator.run_next_frame();
+    }
+    assert_eq!(expected, (emulator.save_state(), emulator.frame_hash(), emulator.engine().peek(0xC000)));
+
+    let other = Movie::new([0; 16], Start::PowerOn);
+    assert_eq!(std::io::ErrorKind::InvalidData, emulator.play_movie(other).unwrap_err().kind());
+}
+
+#[test]
+fn emulator_save_state_test() {
+    let rom = TempFile::blank_rom("state");
+    let mut emulator = Emulator::new();
+    emulator.open_rom_file(rom.to_str());
+    emulator.run_next_frame();
+
+    let state = emulator.save_state();
+    emulator.run_next_frame();
+    emulator.run_next_frame();
+    let expected = emulator.save_state();
+
+    // the machine runs the same way from the restored state
+    emulator.load_state(&state).unwrap();
+    assert_eq!(state, emulator.save_state());
+    emulator.run_next_frame();
+    emulator.run_next_frame();
+    assert_eq!(expected, emulator.save_state());
+
+    // truncated states are rejected without touching the machine
+    assert!(emulator.load_state(&state[..state.len() - 1]).is_err());
+    assert!(emulator.load_state(&[&state[..], &[0]].concat()).is_err());
+    assert_eq!(expected, emulator.save_state());
+
+    let mut other_rom = vec![0; ROM_SIZE];
+    other_rom[0x134] = b'K';
+    let other_rom = TempFile::rom("state-other", &other_rom);
+    let mut other = Emulator::new();
+    other.open_rom_file(other_rom.to_str());
+    assert_eq!(std::io::ErrorKind::InvalidData, other.load_state(&state).unwrap_err().kind());
+}
+
+#[test]
+fn emulator_rewind_test() {
+    let rom = TempFile::blank_rom("rewind");
+    let mut emulator = Emulator::new();
+    emulator.set_rewind(3, 1024 * 1024);
+    emulator.open_rom_file(rom.to_str());
+
+    let mut states = vec![emulator.save_state()];
+    for _ in 0..10 {
+        emulator.run_next_frame();
+        states.push(emulator.save_state());
+    }
+
+    // frames before the first snapshot (frame 3) can't be reached
+    for frame in (3..10).rev() {
+        assert!(emulator.rewind_frame());
+        assert_eq!(frame, emulator.frame);
+        assert_eq!(states[frame as usize], emulator.save_state());
+    }
+    assert!(!emulator.rewind_frame());
+}
+
+#[test]
+fn emulator_freeze_test() {
+    use mmu::Memory;
+
+    let rom = TempFile::blank_rom("freeze");
+    let mut emulator = Emulator::new();
+    emulator.open_rom_file(rom.to_str());
+    emulator.engine_mut().freeze(0xC123, 0x42);
+    emulator.engine_mut().write(0xC123, 0);
+    emulator.run_next_frame();
+    assert_eq!(0x42, emulator.engine().peek(0xC123));
+
+    assert!(emulator.engine_mut().unfreeze(0xC123));
+    emulator.engine_mut().write(0xC123, 0);
+    emulator.run_next_frame();
+    assert_eq!(0, emulator.engine().peek(0xC123));
+}
+
+#[test]
+fn emulator_cheats_test() {
+    let rom = TempFile::blank_rom("cheats");
+    let cheats_file = rom.with_extension("cht");
+    std::fs::write(cheats_file.path(), "+ 3E1-50F Patch\n+ 0142C0C0 Value\n").unwrap();
+
+    let mut emulator = Emulator::new();
+    emulator.open_rom_file(rom.to_str());
+    assert_eq!(2, emulator.engine().cheats().len());
+    assert_eq!(0x3E, emulator.engine().peek(0x0150));
+    assert_eq!(0x00, emulator.engine().peek(0x0151));
+
+    // GameShark codes are written on vertical blank
+    assert_eq!(0x00, emulator.engine().peek(0xC0C0));
+    emulator.run_next_frame();
+    assert_eq!(0x42, emulator.engine().peek(0xC0C0));
+
+    assert!(emulator.engine_mut().set_cheat_enabled(0, false));
+    assert_eq!(0x00, emulator.engine().peek(0x0150));
+}
+
+#[test]
+fn emulator_cheats_ram_bank_test() {
+    use crate::emulator::cheats::{Cheat, Code};
+    use crate::emulator::mmu::Memory;
+
+    let mut rom = vec![0; ROM_SIZE];
+    rom[0x0147] = 0x03;    // MBC1+RAM+BATTERY
+    let rom = TempFile::rom("cheats-ram-bank", &rom);
+
+    let mut emulator = Emulator::new();
+    emulator.open_rom_file(rom.to_str());
+    let codes = vec![Code::parse("825510A0").unwrap()];
+    emulator.engine_mut().add_cheat(Cheat { name: "Bank 2".to_string(), codes, enabled: true });
+
+    // written only while cartridge RAM bank 2 is selected
+    emulator.run_next_frame();
+    assert_eq!(0x00, emulator.engine().peek(0xA010));
+    emulator.engine_mut().write(0x4000, 0x02);
+    emulator.run_next_frame();
+    assert_eq!(0x55, emulator.engine().peek(0xA010));
+}
@@ -0,0 +1,386 @@
+pub mod deflate;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::emulator::ppu::{ARGB_BYTES_PER_PIXEL, SCREEN_PIXEL_HEIGHT, SCREEN_PIXEL_WIDTH};
+use deflate::{crc32, zlib_compress};
+
+pub const DEFAULT_CAPTURE_SCALE: usize = 1;
+
+// Frames per second of the emulation and the shortest GIF frame delay, in
+// hundredths of a second, browsers play anything shorter slower
+const FRAMES_PER_SECOND: u64 = 60;
+const GIF_MIN_DELAY: u64 = 2;
+
+// Largest GIF LZW code
+const LZW_MAX_CODE: u16 = 4095;
+
+/// RGB pixels of an ARGB frame buffer scaled up by an integer factor
+fn scale_frame(frame: &[u8], width: usize, height: usize, scale: usize) -> Vec<[u8; 3]> {
+    let mut pixels = Vec::with_capacity(width * height * scale * scale);
+    for y in 0..height * scale {
+        for x in 0..width * scale {
+            let pos = (x / scale + y / scale * width) * ARGB_BYTES_PER_PIXEL;
+            pixels.push([frame[pos + 1], frame[pos + 2], frame[pos + 3]]);
+        }
+    }
+    pixels
+}
+
+// Palette of the first 256 colors of the pixels and their indices, colors
+// past those get index 0 and the palette isn't exact
+struct Palette {
+    colors: Vec<[u8; 3]>,
+    indices: Vec<u8>,
+    exact: bool,
+}
+
+fn palette(pixels: &[[u8; 3]]) -> Palette {
+    let mut palette = Palette { colors: Vec::new(), indices: Vec::with_capacity(pixels.len()), exact: true };
+    let mut lookup = HashMap::new();
+    for pixel in pixels {
+        let index = match lookup.get(pixel) {
+            Some(index) => *index,
+            None if palette.colors.len() < 256 => {
+                let index = palette.colors.len() as u8;
+                palette.colors.push(*pixel);
+                lookup.insert(*pixel, index);
+                index
+            }
+            None => {
+                palette.exact = false;
+                0
+            }
+        };
+        palette.indices.push(index);
+    }
+    palette
+}
+
+fn write_png_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// PNG file of an ARGB frame buffer, every pixel scaled up to a square
+///
+/// Indexed color when there are at most 256 colors (always on a DMG), RGB
+/// otherwise.
+pub fn encode_png(frame: &[u8], width: usize, height: usize, scale: usize) -> Vec<u8> {
+    let scale = scale.max(1);
+    let (width, height, pixels) = (width * scale, height * scale, scale_frame(frame, width, height, scale));
+    let palette = Some(palette(&pixels)).filter(|palette| palette.exact);
+
+    // every row starts with filter type 0 (none)
+    let bytes_per_pixel = if palette.is_some() { 1 } else { 3 };
+    let stride = 1 + width * bytes_per_pixel;
+    let mut data = Vec::with_capacity(stride * height);
+    for row in 0..height {
+        data.push(0);
+        match palette.as_ref() {
+            Some(palette) => data.extend_from_slice(&palette.indices[row * width..(row + 1) * width]),
+            None => data.extend(pixels[row * width..(row + 1) * width].iter().flatten()),
+        }
+    }
+
+    let mut png = vec![0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+    let mut header = Vec::new();
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    header.extend_from_slice(&[8, if palette.is_some() { 3 } else { 2 }, 0, 0, 0]);
+    write_png_chunk(&mut png, b"IHDR", &header);
+    if let Some(palette) = palette.as_ref() {
+        write_png_chunk(&mut png, b"PLTE", &palette.colors.concat());
+    }
+    write_png_chunk(&mut png, b"IDAT", &zlib_compress(&data, &[bytes_per_pixel, stride]));
+    write_png_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+/// Save a screen frame as PNG
+pub fn save_png<P: AsRef<Path>>(filename: P, frame: &[u8], scale: usize) -> std::io::Result<()> {
+    std::fs::write(filename, encode_png(frame, SCREEN_PIXEL_WIDTH, SCREEN_PIXEL_HEIGHT, scale))
+}
+
+// Variable width LZW codes packed from the least significant bit, in
+// sub-blocks of up to 255 bytes
+fn lzw_compress(indices: &[u8], min_code_size: u32) -> Vec<u8> {
+    let clear = 1u16 << min_code_size;
+    let end = clear + 1;
+
+    let mut output = vec![min_code_size as u8];
+    let mut packed = Vec::new();
+    let (mut bits, mut count) = (0u32, 0u32);
+    let mut emit = |code: u16, size: u32| {
+        bits |= (code as u32) << count;
+        count += size;
+        while count >= 8 {
+            packed.push(bits as u8);
+            bits >>= 8;
+            count -= 8;
+        }
+    };
+
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next_code = end + 1;
+    let mut code_size = min_code_size + 1;
+    emit(clear, code_size);
+
+    let mut prefix: Option<u16> = None;
+    for index in indices {
+        let current = match prefix {
+            None => {
+                prefix = Some(*index as u16);
+                continue;
+            }
+            Some(current) => current,
+        };
+        if let Some(code) = table.get(&(current, *index)) {
+            prefix = Some(*code);
+            continue;
+        }
+
+        emit(current, code_size);
+        table.insert((current, *index), next_code);
+        next_code += 1;
+        if next_code > 1 << code_size && code_size < 12 {
+            code_size += 1;
+        }
+        if next_code > LZW_MAX_CODE {
+            emit(clear, code_size);
+            table.clear();
+            next_code = end + 1;
+            code_size = min_code_size + 1;
+        }
+        prefix = Some(*index as u16);
+    }
+
+    // the decoder adds an entry after the last code too, which can widen the end code
+    if let Some(current) = prefix {
+        emit(current, code_size);
+        if next_code + 1 > 1 << code_size && code_size < 12 {
+            code_size += 1;
+        }
+    }
+    emit(end, code_size);
+    if count > 0 {
+        packed.push(bits as u8);
+    }
+
+    for block in packed.chunks(255) {
+        output.push(block.len() as u8);
+        output.extend_from_slice(block);
+    }
+    output.push(0);
+    output
+}
+
+/// Animated GIF Writer
+///
+/// Every frame gets its own color table, frames identical to the previous
+/// one only lengthen its delay. GIF delays are hundredths of a second, so
+/// frames coming sooner than 2/100s after the previous one replace it.
+pub struct GifWriter {
+    writer: BufWriter<File>,
+    width: usize,
+    height: usize,
+    scale: usize,
+
+    // Frames received so far, for their timestamp
+    frames: u64,
+
+    // Frame not written yet, with its timestamp: its delay depends on the next one
+    pending: Option<(Vec<u8>, u64)>,
+}
+
+impl GifWriter {
+    pub fn create<P: AsRef<Path>>(filename: P, width: usize, height: usize, scale: usize) -> std::io::Result<Self> {
+        let scale = scale.max(1);
+        let mut writer = BufWriter::new(File::create(filename)?);
+        writer.write_all(b"GIF89a")?;
+        writer.write_all(&((width * scale) as u16).to_le_bytes())?;
+        writer.write_all(&((height * scale) as u16).to_le_bytes())?;
+        writer.write_all(&[0x00, 0x00, 0x00])?; // no global color table
+
+        // NETSCAPE2.0 extension: loop forever
+        writer.write_all(&[0x21, 0xFF, 0x0B])?;
+        writer.write_all(b"NETSCAPE2.0")?;
+        writer.write_all(&[0x03, 0x01, 0x00, 0x00, 0x00])?;
+
+        Ok(Self { writer, width, height, scale, frames: 0, pending: None })
+    }
+
+    /// Append an ARGB frame shown 1/60s
+    pub fn push_frame(&mut self, frame: &[u8]) -> std::io::Result<()> {
+        let time = self.frames * 100 / FRAMES_PER_SECOND;
+        self.frames += 1;
+
+        match self.pending.take() {
+            Some((pending, start)) if pending == frame => self.pending = Some((pending, start)),
+            Some((_, start)) if time - start < GIF_MIN_DELAY => self.pending = Some((frame.to_vec(), start)),
+            Some((pending, start)) => {
+                self.write_frame(&pending, time - start)?;
+                self.pending = Some((frame.to_vec(), time));
+            }
+            None => self.pending = Some((frame.to_vec(), time)),
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> std::io::Result<()> {
+        if let Some((pending, start)) = self.pending.take() {
+            let time = self.frames * 100 / FRAMES_PER_SECOND;
+            self.write_frame(&pending, (time - start).max(GIF_MIN_DELAY))?;
+        }
+        self.writer.write_all(&[0x3B])?;
+        self.writer.flush()
+    }
+
+    fn write_frame(&mut self, frame: &[u8], delay: u64) -> std::io::Result<()> {
+        let pixels = scale_frame(frame, self.width, self.height, self.scale);
+
+        // too colorful frames keep their first 256 colors
+        let Palette { colors, indices, .. } = palette(&pixels);
+        let table_bits = (colors.len().max(2) as u32).next_power_of_two().trailing_zeros();
+
+        // graphic control extension: the delay, keep the frame when the next one is drawn
+        self.writer.write_all(&[0x21, 0xF9, 0x04, 0x04])?;
+        self.writer.write_all(&(delay.min(u16::MAX as u64) as u16).to_le_bytes())?;
+        self.writer.write_all(&[0x00, 0x00])?;
+
+        // image descriptor with a local color table
+        self.writer.write_all(&[0x2C, 0x00, 0x00, 0x00, 0x00])?;
+        self.writer.write_all(&((self.width * self.scale) as u16).to_le_bytes())?;
+        self.writer.write_all(&((self.height * self.scale) as u16).to_le_bytes())?;
+        self.writer.write_all(&[0x80 | (table_bits as u8 - 1)])?;
+        for index in 0..1 << table_bits {
+            self.writer.write_all(colors.get(index).unwrap_or(&[0, 0, 0]))?;
+        }
+
+        self.writer.write_all(&lzw_compress(&indices, table_bits.max(2)))
+    }
+}
+
+/// Raw Video Writer
+///
+/// Frames as packed 24 bit RGB, one after the other and nothing else. To
+/// make a video of it along with the sound recorded next to it:
+///
+/// ```text
+/// ffmpeg -f rawvideo -pixel_format rgb24 -video_size 160x144 -framerate 60 -i game.rgb -i game.wav game.mp4
+/// ```
+pub struct RawWriter {
+    writer: BufWriter<File>,
+    width: usize,
+    height: usize,
+    scale: usize,
+}
+
+impl RawWriter {
+    pub fn create<P: AsRef<Path>>(filename: P, width: usize, height: usize, scale: usize) -> std::io::Result<Self> {
+        let writer = BufWriter::new(File::create(filename)?);
+        Ok(Self { writer, width, height, scale: scale.max(1) })
+    }
+
+    pub fn push_frame(&mut self, frame: &[u8]) -> std::io::Result<()> {
+        let pixels = scale_frame(frame, self.width, self.height, self.scale);
+        self.writer.write_all(&pixels.concat())
+    }
+
+    pub fn finish(mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Screen Recorder
+///
+/// An animated GIF when the filename ends in `.gif`, raw RGB frames otherwise.
+pub enum VideoRecorder {
+    Gif(GifWriter),
+    Raw(RawWriter),
+}
+
+impl VideoRecorder {
+    pub fn create<P: AsRef<Path>>(filename: P, scale: usize) -> std::io::Result<Self> {
+        let filename = filename.as_ref();
+        let is_gif = filename.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("gif"));
+        Ok(match is_gif {
+            true => VideoRecorder::Gif(GifWriter::create(filename, SCREEN_PIXEL_WIDTH, SCREEN_PIXEL_HEIGHT, scale)?),
+            false => VideoRecorder::Raw(RawWriter::create(filename, SCREEN_PIXEL_WIDTH, SCREEN_PIXEL_HEIGHT, scale)?),
+        })
+    }
+
+    pub fn push_frame(&mut self, frame: &[u8]) -> std::io::Result<()> {
+        match self {
+            VideoRecorder::Gif(writer) => writer.push_frame(frame),
+            VideoRecorder::Raw(writer) => writer.push_frame(frame),
+        }
+    }
+
+    pub fn finish(self) -> std::io::Result<()> {
+        match self {
+            VideoRecorder::Gif(writer) => writer.finish(),
+            VideoRecorder::Raw(writer) => writer.finish(),
+        }
+    }
+}
+
+#[test]
+fn capture_png_test() {
+    // 2x1 frame, black and white
+    let frame = [0xFF, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF];
+    let png = encode_png(&frame, 2, 1, 2);
+
+    assert_eq!(b"\x89PNG\r\n\x1A\n", &png[..8]);
+    assert_eq!(b"IHDR", &png[12..16]);
+    assert_eq!(&[0, 0, 0, 4, 0, 0, 0, 2, 8, 3], &png[16..26]);
+    assert_eq!(b"PLTE", &png[37..41]);
+    assert_eq!(&[0, 0, 0, 0xFF, 0xFF, 0xFF], &png[41..47]);
+
+    // every chunk checks out
+    let mut pos = 8;
+    let mut kinds = Vec::new();
+    while pos < png.len() {
+        let len = u32::from_be_bytes([png[pos], png[pos + 1], png[pos + 2], png[pos + 3]]) as usize;
+        let crc = &png[pos + 8 + len..pos + 12 + len];
+        assert_eq!(&crc32(&png[pos + 4..pos + 8 + len]).to_be_bytes(), crc);
+        kinds.push(String::from_utf8_lossy(&png[pos + 4..pos + 8]).into_owned());
+        pos += 12 + len;
+    }
+    assert_eq!(vec!["IHDR", "PLTE", "IDAT", "IEND"], kinds);
+}
+
+#[test]
+fn capture_gif_test() {
+    let filename = std::env::temp_dir().join(format!("kiwi-capture-{}.gif", std::process::id()));
+    let black = [0xFF, 0, 0, 0].repeat(SCREEN_PIXEL_WIDTH * SCREEN_PIXEL_HEIGHT);
+    let white = [0xFF; 4].repeat(SCREEN_PIXEL_WIDTH * SCREEN_PIXEL_HEIGHT);
+
+    // a second of black, then white every other frame, which is too fast
+    let mut writer = GifWriter::create(&filename, SCREEN_PIXEL_WIDTH, SCREEN_PIXEL_HEIGHT, 1).unwrap();
+    for frame in 0..70 {
+        let frame = if frame >= 60 && frame % 2 == 0 { &white } else { &black };
+        writer.push_frame(frame).unwrap();
+    }
+    writer.finish().unwrap();
+
+    let gif = std::fs::read(&filename).unwrap();
+    std::fs::remove_file(&filename).unwrap();
+    assert_eq!(b"GIF89a", &gif[..6]);
+    assert_eq!(Some(&0x3B), gif.last());
+
+    // the first frame lasts the whole second
+    assert_eq!(&[0x21, 0xF9, 0x04, 0x04, 100, 0], &gif[32..38]);
+    let delays = gif.windows(4).filter(|bytes| bytes == &[0x21, 0xF9, 0x04, 0x04]).count();
+    assert_eq!(8, delays);
+
+    // one code per pixel at worst for 2 colors: the LZW data stays small
+    assert!(gif.len() < 2000);
+}
@@ -0,0 +1,145 @@
+// Deflate with the fixed Huffman codes (RFC 1951 3.2.6)
+// - match lengths 3..=258 with their extra bits
+const LENGTH_BASES: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+    35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA_BITS: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+    3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+
+// - match distances 1..=32768 with their extra bits
+const DISTANCE_BASES: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+    257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA_BITS: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+    7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+
+// Deflate streams are packed starting from the least significant bit
+struct BitWriter {
+    bytes: Vec<u8>,
+    bits: u32,
+    count: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u32, count: u32) {
+        self.bits |= value << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.bytes.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    // Huffman codes go most significant bit first
+    fn write_code(&mut self, code: u32, count: u32) {
+        self.write(code.reverse_bits() >> (32 - count), count);
+    }
+
+    fn write_symbol(&mut self, symbol: u16) {
+        match symbol {
+            0..=143 => self.write_code(0x30 + symbol as u32, 8),
+            144..=255 => self.write_code(0x190 + (symbol - 144) as u32, 9),
+            256..=279 => self.write_code((symbol - 256) as u32, 7),
+            _ => self.write_code(0xC0 + (symbol - 280) as u32, 8),
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.bytes.push(self.bits as u8);
+        }
+        self.bytes
+    }
+}
+
+/// zlib stream of `data` in a single fixed Huffman block
+///
+/// Matches are only looked for at the `distances` given, which is enough
+/// for images: the previous pixel and the row above repeat the most.
+pub fn zlib_compress(data: &[u8], distances: &[usize]) -> Vec<u8> {
+    let mut writer = BitWriter { bytes: vec![0x78, 0x01], bits: 0, count: 0 };
+    writer.write(1, 1); // last block
+    writer.write(1, 2); // fixed Huffman codes
+
+    let mut pos = 0;
+    while pos < data.len() {
+        let best = distances.iter()
+            .filter(|distance| (1..=32768).contains(*distance) && **distance <= pos)
+            .map(|distance| {
+                let length = data[pos..].iter()
+                    .zip(&data[pos - distance..])
+                    .take(MAX_MATCH)
+                    .take_while(|(byte, earlier)| byte == earlier)
+                    .count();
+                (length, *distance)
+            })
+            .max_by_key(|(length, _)| *length)
+            .filter(|(length, _)| *length >= MIN_MATCH);
+
+        match best {
+            Some((length, distance)) => {
+                let code = LENGTH_BASES.iter().rposition(|base| *base as usize <= length).unwrap();
+                writer.write_symbol(257 + code as u16);
+                writer.write((length - LENGTH_BASES[code] as usize) as u32, LENGTH_EXTRA_BITS[code] as u32);
+
+                let code = DISTANCE_BASES.iter().rposition(|base| *base as usize <= distance).unwrap();
+                writer.write_code(code as u32, 5);
+                writer.write((distance - DISTANCE_BASES[code] as usize) as u32, DISTANCE_EXTRA_BITS[code] as u32);
+                pos += length;
+            }
+            None => {
+                writer.write_symbol(data[pos] as u16);
+                pos += 1;
+            }
+        }
+    }
+    writer.write_symbol(256);
+
+    let mut bytes = writer.finish();
+    bytes.extend_from_slice(&adler32(data).to_be_bytes());
+    bytes
+}
+
+pub fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+#[test]
+fn deflate_test() {
+    assert_eq!(0x11E6_0398, adler32(b"Wikipedia"));
+    assert_eq!(0xCBF4_3926, crc32(b"123456789"));
+
+    // zlib.compress(b"a", 9) from a single literal
+    assert_eq!(vec![0x78, 0x01, 0x4B, 0x04, 0x00, 0x00, 0x62, 0x00, 0x62], zlib_compress(b"a", &[1]));
+
+    // a run of 300 is a literal and two matches at distance 1
+    let compressed = zlib_compress(&[7; 300], &[1]);
+    assert!(compressed.len() < 16);
+    assert_eq!(&adler32(&[7; 300]).to_be_bytes(), &compressed[compressed.len() - 4..]);
+}
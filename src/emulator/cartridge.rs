@@ -1,9 +1,18 @@
+use crate::emulator::cheats::GameGenie;
+use crate::emulator::state::{Snapshot, StateReader, StateWriter};
+
 #[allow(dead_code)]
 pub struct Cartridge {
     pub rom: Vec<u8>,
     pub ram: Box<[u8; 0x2000]>,
 
     switchable_rom_bank_offset: usize,
+
+    // RAM bank register of the mapper, the banks all share the one emulated
+    ram_bank: u8,
+
+    // Game Genie codes applied to ROM reads
+    patches: Vec<GameGenie>,
 }
 
 fn rom_bank_count(rom_size_code: u8) -> usize {
@@ -26,6 +35,8 @@ impl Cartridge {
             rom: Vec::new(),
             ram: Box::new([0; 0x2000]),
             switchable_rom_bank_offset: 0x4000,
+            ram_bank: 0,
+            patches: Vec::new(),
         }
     }
 
@@ -40,15 +51,32 @@ impl Cartridge {
     }
 
     pub fn read_rom(&self, addr: u16) -> u8 {
-        let mut addr = addr as usize;
-        if addr >= 0x4000 {
-            addr -= 0x4000;
-            addr += self.switchable_rom_bank_offset;
+        let mut offset = addr as usize;
+        if offset >= 0x4000 {
+            offset -= 0x4000;
+            offset += self.switchable_rom_bank_offset;
         }
-        self.rom[addr]
+        let data = self.rom[offset];
+        self.patches.iter().find_map(|patch| patch.patch(addr, data)).unwrap_or(data)
+    }
+
+    pub fn set_patches(&mut self, patches: Vec<GameGenie>) {
+        self.patches = patches;
+    }
+
+    /// Bank mapped at $4000-$7FFF
+    pub fn rom_bank(&self) -> u16 {
+        (self.switchable_rom_bank_offset / 0x4000) as u16
     }
 
     pub fn write_rom(&mut self, addr: u16, data: u8) {
+        // MBC1 has two bits of RAM bank, MBC3 and MBC5 four
+        match (self.cart_type(), addr) {
+            (0x01..=0x03, 0x4000..=0x5FFF) => self.ram_bank = data & 0x03,
+            (0x0F..=0x13 | 0x19..=0x1E, 0x4000..=0x5FFF) => self.ram_bank = data & 0x0F,
+            _ => {}
+        }
+
         match self.cart_type() {
             1 => {
                 match addr {
@@ -75,6 +103,11 @@ impl Cartridge {
         self.ram[addr as usize] = data;
     }
 
+    /// Bank selected at $A000-$BFFF, a single 8 KiB bank is emulated for all
+    pub fn ram_bank(&self) -> u8 {
+        self.ram_bank
+    }
+
     pub fn title(&self) -> String {
         std::str::from_utf8(&self.rom[0x134..=0x142]).unwrap().to_string()
     }
@@ -126,6 +159,11 @@ impl Cartridge {
         self.rom[0x14d]
     }
 
+    /// MD5 of the whole ROM image, identifies the game in save states
+    pub fn rom_digest(&self) -> [u8; 16] {
+        md5::compute(&self.rom).0
+    }
+
     pub fn checksum(&self) -> u16 {
         u16::from_be_bytes([
             self.rom[0x14e],
@@ -133,6 +171,21 @@ impl Cartridge {
     }
 }
 
+// The ROM itself isn't saved, the digest guards against loading another game
+impl Snapshot for Cartridge {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u64(self.switchable_rom_bank_offset as u64);
+        state.write_u8(self.ram_bank);
+        state.write_bytes(&self.ram[..]);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> std::io::Result<()> {
+        self.switchable_rom_bank_offset = state.read_u64()? as usize;
+        self.ram_bank = state.read_u8()?;
+        state.read_bytes(&mut self.ram[..])
+    }
+}
+
 impl Default for Cartridge {
     fn default() -> Self { Self::new() }
 }
\ No newline at end of file
@@ -0,0 +1,245 @@
+use std::path::Path;
+
+/// Game Genie Code
+///
+/// `ABC-DEF-GHI`: AB is the new data, FCDE the address XOR $F000 and GI the
+/// compare byte XOR $BA rotated left twice (H is a check digit). Without the
+/// compare part (`ABC-DEF`) every bank mapped at the address is patched,
+/// with it only the banks holding the compare byte there are.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GameGenie {
+    pub addr: u16,
+    pub data: u8,
+    pub compare: Option<u8>,
+}
+
+impl GameGenie {
+    pub fn parse(code: &str) -> Option<GameGenie> {
+        let digits: Vec<u8> = code.chars()
+            .filter(|c| *c != '-')
+            .map(|c| c.to_digit(16).map(|digit| digit as u8))
+            .collect::<Option<_>>()?;
+        if digits.len() != 6 && digits.len() != 9 {
+            return None;
+        }
+
+        let addr = u16::from_be_bytes([(digits[5] ^ 0xF) << 4 | digits[2], digits[3] << 4 | digits[4]]);
+        let compare = match digits.len() {
+            9 => Some((digits[6] << 4 | digits[8]).rotate_right(2) ^ 0xBA),
+            _ => None,
+        };
+        Some(GameGenie {
+            addr,
+            data: digits[0] << 4 | digits[1],
+            compare,
+        }).filter(|code| code.addr < 0x8000)
+    }
+
+    /// Patched value of a ROM read, `rom_data` is the byte in the mapped bank
+    pub fn patch(&self, addr: u16, rom_data: u8) -> Option<u8> {
+        if addr == self.addr && self.compare.map_or(true, |compare| compare == rom_data) {
+            Some(self.data)
+        } else {
+            None
+        }
+    }
+}
+
+/// GameShark Code
+///
+/// `TTVVLLHH`: written every vertical blank, VV to $HHLL. The type 01 writes
+/// through the memory map, 8X writes to cartridge RAM bank X and 9X to work
+/// RAM bank X.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GameShark {
+    pub kind: u8,
+    pub addr: u16,
+    pub data: u8,
+}
+
+impl GameShark {
+    pub fn parse(code: &str) -> Option<GameShark> {
+        if code.len() != 8 {
+            return None;
+        }
+        let value = u32::from_str_radix(code, 16).ok()?;
+        let [kind, data, low, high] = value.to_be_bytes();
+        Some(GameShark {
+            kind,
+            addr: u16::from_le_bytes([low, high]),
+            data,
+        })
+    }
+
+    /// Cartridge RAM bank the code writes to, none when it goes through the memory map
+    pub fn cartridge_ram_bank(&self) -> Option<u8> {
+        match (self.kind & 0xF0, self.addr) {
+            (0x80, 0xA000..=0xBFFF) => Some(self.kind & 0x0F),
+            _ => None,
+        }
+    }
+
+    /// Work RAM bank at $D000-$DFFF the code writes to
+    pub fn work_ram_bank(&self) -> Option<u8> {
+        match (self.kind & 0xF0, self.addr) {
+            (0x90, 0xD000..=0xDFFF) => Some((self.kind & 0x0F).max(1)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Code {
+    GameGenie(GameGenie),
+    GameShark(GameShark),
+}
+
+impl Code {
+    /// Game Genie codes have dashes, GameShark codes are 8 hex digits
+    pub fn parse(code: &str) -> Result<Code, String> {
+        let parsed = if code.contains('-') {
+            GameGenie::parse(code).map(Code::GameGenie)
+        } else {
+            GameShark::parse(code).map(Code::GameShark)
+        };
+        parsed.ok_or(format!("invalid cheat code `{}`", code))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cheat {
+    pub name: String,
+    pub codes: Vec<Code>,
+    pub enabled: bool,
+}
+
+/// Cheat List
+///
+/// Read from a text file next to the ROM (`game.gb` and `game.cht`), a
+/// cheat per line: `+` when enabled or `-`, the codes separated by commas,
+/// then the name. `#` and `;` start comments.
+///
+/// ```text
+/// + 00A-17B-C49 Infinite lives
+/// - 010FE1C0,0199E2C0 Max coins
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Cheats {
+    cheats: Vec<Cheat>,
+}
+
+impl Cheats {
+    pub fn load<P: AsRef<Path>>(filename: P) -> std::io::Result<Self> {
+        let text = std::fs::read_to_string(filename)?;
+        Self::parse(&text).map_err(|error| std::io::Error::new(std::io::ErrorKind::InvalidData, error))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut cheats = Cheats::default();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split(['#', ';']).next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let error = |message: &str| format!("line {}: {}", number + 1, message);
+            let mut fields = line.splitn(3, char::is_whitespace);
+            let enabled = match fields.next() {
+                Some("+") => true,
+                Some("-") => false,
+                _ => return Err(error("expected `+` or `-`")),
+            };
+            let codes = fields.next().ok_or_else(|| error("expected a code"))?
+                .split(',')
+                .map(Code::parse)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|message| error(&message))?;
+            let name = fields.next().unwrap_or("").trim().to_string();
+            cheats.add(Cheat { name, codes, enabled });
+        }
+
+        Ok(cheats)
+    }
+
+    pub fn add(&mut self, cheat: Cheat) -> usize {
+        self.cheats.push(cheat);
+        self.cheats.len() - 1
+    }
+
+    /// False when there's no such cheat
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) -> bool {
+        match self.cheats.get_mut(index) {
+            Some(cheat) => {
+                cheat.enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Cheat> {
+        self.cheats.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.cheats.len()
+    }
+
+    fn enabled_codes(&self) -> impl Iterator<Item = &Code> {
+        self.cheats.iter().filter(|cheat| cheat.enabled).flat_map(|cheat| cheat.codes.iter())
+    }
+
+    /// ROM patches of the enabled cheats
+    pub fn game_genie(&self) -> Vec<GameGenie> {
+        self.enabled_codes().filter_map(|code| match code {
+            Code::GameGenie(code) => Some(*code),
+            _ => None,
+        }).collect()
+    }
+
+    /// RAM writes of the enabled cheats
+    pub fn game_shark(&self) -> Vec<GameShark> {
+        self.enabled_codes().filter_map(|code| match code {
+            Code::GameShark(code) => Some(*code),
+            _ => None,
+        }).collect()
+    }
+}
+
+#[test]
+fn cheat_codes_test() {
+    // 3E at $4A17 where the ROM has $0A (ROL2($0A ^ $BA) = $C2)
+    assert_eq!(Some(GameGenie { addr: 0x4A17, data: 0x3E, compare: Some(0x0A) }), GameGenie::parse("3EA-17B-C02"));
+    assert_eq!(Some(GameGenie { addr: 0x0150, data: 0x00, compare: None }), GameGenie::parse("001-50F"));
+    assert_eq!(None, GameGenie::parse("001-507"));
+    assert_eq!(None, GameGenie::parse("001-50F-0"));
+
+    let code = GameGenie::parse("3EA-17B-C02").unwrap();
+    assert_eq!(Some(0x3E), code.patch(0x4A17, 0x0A));
+    assert_eq!(None, code.patch(0x4A17, 0x0B));
+    assert_eq!(None, code.patch(0x4A18, 0x0A));
+
+    assert_eq!(Some(GameShark { kind: 0x01, addr: 0xC0E1, data: 0x0F }), GameShark::parse("010FE1C0"));
+    let code = GameShark::parse("8263FFA0").unwrap();
+    assert_eq!((Some(2), None), (code.cartridge_ram_bank(), code.work_ram_bank()));
+    assert_eq!(None, GameShark::parse("010FE1C"));
+
+    let cheats = Cheats::parse("
+        # Test
+        + 3EA-17B-C02 Infinite lives
+        - 010FE1C0,0199E2C0 Max coins ; both bytes
+    ").unwrap();
+    assert_eq!(2, cheats.len());
+    assert_eq!("Max coins", cheats.iter().nth(1).unwrap().name);
+    assert_eq!(1, cheats.game_genie().len());
+    assert!(cheats.game_shark().is_empty());
+
+    let mut cheats = cheats;
+    assert!(cheats.set_enabled(1, true));
+    assert!(!cheats.set_enabled(2, true));
+    assert_eq!(2, cheats.game_shark().len());
+
+    assert!(Cheats::parse("* 010FE1C0 Name").is_err());
+    assert!(Cheats::parse("+ 010FE1 Name").is_err());
+}
@@ -1,4 +1,3 @@
-#[allow(dead_code)]
 pub fn disassemble(opcode: u8, immediate8: u8, immediate16: u16) -> String {
     let opcode = opcode as usize;
     match opcode {
@@ -20,7 +19,6 @@ pub fn instruction_ticks(opcode: u8) -> u64 {
     INST_TICKS[opcode as usize] as u64
 }
 
-#[allow(dead_code)]
 /// Instruction Size
 pub const INST_SIZE: [u8; 256] = [
 //  0  1  2  3  4  5  6  7  8  9  A  B  C  D  E  F
@@ -41,10 +39,9 @@ pub const INST_SIZE: [u8; 256] = [
     1, 1, 3, 3, 3, 1, 2, 1, 1, 1, 3, 2, 3, 3, 2, 1,    // 0xC0 ~ 0xCF
     1, 1, 3, 1, 3, 1, 2, 1, 1, 1, 3, 1, 3, 1, 2, 1,    // 0xD0 ~ 0xDF
     2, 1, 1, 1, 1, 1, 2, 1, 2, 1, 3, 1, 1, 1, 2, 1,    // 0xE0 ~ 0xEF
-    2, 1, 2, 1, 1, 1, 2, 1, 2, 1, 3, 1, 1, 1, 2, 1,    // 0xF0 ~ 0xFF
+    2, 1, 1, 1, 1, 1, 2, 1, 2, 1, 3, 1, 1, 1, 2, 1,    // 0xF0 ~ 0xFF
 ];
 
-#[allow(dead_code)]
 /// Instruction Ticks
 pub const INST_TICKS: [u8; 256] = [
 //  x0  x1  x2  x3  x4  x5  x6  x7  x8  x9  xA  xB  xC  xD  xE  xF
@@ -70,7 +67,6 @@ pub const INST_TICKS: [u8; 256] = [
 ];
 
 /// Instruction Assembly
-#[allow(dead_code)]
 pub const INST_ASM: [&str; 256] = [
     // 0x00 ~ 0x0F
     "NOP",
@@ -361,7 +357,6 @@ pub const INST_ASM: [&str; 256] = [
     "RST $38",
 ];
 
-#[allow(dead_code)]
 /// Instruction Assembly (CB Extension)
 pub const INST_ASM_CB: [&str; 256] = [
     // 0x00 ~ 0x07
@@ -1,3 +1,5 @@
+use crate::emulator::state::{Snapshot, StateReader, StateWriter};
+
 #[derive(Copy,Clone,Default)]
 pub struct Regs {
     a: u8,
@@ -72,6 +74,22 @@ impl Regs {
     }
 }
 
+impl Snapshot for Regs {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&[self.a, self.f, self.b, self.c, self.d, self.e, self.h, self.l]);
+        state.write_u16(self.sp);
+        state.write_u16(self.pc);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> std::io::Result<()> {
+        let mut r = [0; 8];
+        state.read_bytes(&mut r)?;
+        let [a, f, b, c, d, e, h, l] = r;
+        *self = Self { a, f, b, c, d, e, h, l, sp: state.read_u16()?, pc: state.read_u16()? };
+        Ok(())
+    }
+}
+
 impl std::fmt::Debug for Regs {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Regs")
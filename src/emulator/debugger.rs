@@ -0,0 +1,459 @@
+use std::cell::Cell;
+
+use crate::emulator::cpu::regs::Regs;
+
+/// CPU register a breakpoint condition looks at
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Register {
+    A, F, B, C, D, E, H, L,
+    AF, BC, DE, HL, SP, PC,
+}
+
+const REGISTER_NAMES: [(&str, Register); 14] = [
+    ("a", Register::A), ("f", Register::F), ("b", Register::B), ("c", Register::C),
+    ("d", Register::D), ("e", Register::E), ("h", Register::H), ("l", Register::L),
+    ("af", Register::AF), ("bc", Register::BC), ("de", Register::DE), ("hl", Register::HL),
+    ("sp", Register::SP), ("pc", Register::PC),
+];
+
+impl Register {
+    pub fn parse(name: &str) -> Option<Register> {
+        let name = name.to_lowercase();
+        REGISTER_NAMES.iter().find(|(register, _)| *register == name).map(|(_, register)| *register)
+    }
+
+    pub fn name(self) -> &'static str {
+        REGISTER_NAMES.iter().find(|(_, register)| *register == self).unwrap().0
+    }
+
+    pub fn value(self, regs: &Regs) -> u16 {
+        match self {
+            Register::A => regs.a() as u16,
+            Register::F => regs.f() as u16,
+            Register::B => regs.b() as u16,
+            Register::C => regs.c() as u16,
+            Register::D => regs.d() as u16,
+            Register::E => regs.e() as u16,
+            Register::H => regs.h() as u16,
+            Register::L => regs.l() as u16,
+            Register::AF => regs.af(),
+            Register::BC => regs.bc(),
+            Register::DE => regs.de(),
+            Register::HL => regs.hl(),
+            Register::SP => regs.sp(),
+            Register::PC => regs.pc(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compare {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+const COMPARE_OPERATORS: [(&str, Compare); 6] = [
+    ("==", Compare::Equal),
+    ("!=", Compare::NotEqual),
+    ("<=", Compare::LessOrEqual),
+    (">=", Compare::GreaterOrEqual),
+    ("<", Compare::Less),
+    (">", Compare::Greater),
+];
+
+/// Register comparison guarding a breakpoint, e.g. `a == $10`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Condition {
+    pub register: Register,
+    pub compare: Compare,
+    pub value: u16,
+}
+
+impl Condition {
+    pub fn parse(text: &str) -> Result<Condition, String> {
+        let (index, operator, compare) = COMPARE_OPERATORS.iter()
+            .filter_map(|(operator, compare)| text.find(operator).map(|index| (index, *operator, *compare)))
+            .min_by_key(|(index, _, _)| *index)
+            .ok_or(format!("expected `<register> <operator> <value>` in `{}`", text))?;
+
+        let register = text[..index].trim();
+        let register = Register::parse(register).ok_or(format!("unknown register `{}`", register))?;
+        let value = parse_number(text[index + operator.len()..].trim())?;
+        Ok(Condition { register, compare, value })
+    }
+
+    pub fn is_met(&self, regs: &Regs) -> bool {
+        let register = self.register.value(regs);
+        match self.compare {
+            Compare::Equal => register == self.value,
+            Compare::NotEqual => register != self.value,
+            Compare::Less => register < self.value,
+            Compare::LessOrEqual => register <= self.value,
+            Compare::Greater => register > self.value,
+            Compare::GreaterOrEqual => register >= self.value,
+        }
+    }
+}
+
+impl std::fmt::Display for Condition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let operator = COMPARE_OPERATORS.iter().find(|(_, compare)| *compare == self.compare).unwrap().0;
+        write!(f, "{} {} ${:X}", self.register.name(), operator, self.value)
+    }
+}
+
+/// `$C000`, `0xC000` and `C000` are hexadecimal, `#49152` is decimal
+pub fn parse_number(text: &str) -> Result<u16, String> {
+    let result = if let Some(decimal) = text.strip_prefix('#') {
+        decimal.parse()
+    } else {
+        let hex = text.strip_prefix('$').or_else(|| text.strip_prefix("0x")).unwrap_or(text);
+        u16::from_str_radix(hex, 16)
+    };
+    result.map_err(|_| format!("invalid number `{}`", text))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Breakpoint {
+    pub addr: u16,
+    pub condition: Option<Condition>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+/// Memory range watched for CPU reads and/or writes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Watchpoint {
+    pub begin: u16,
+    pub end: u16,
+    pub access: Access,
+}
+
+impl Watchpoint {
+    fn matches(&self, addr: u16, write: bool) -> bool {
+        let access = match self.access {
+            Access::Read => !write,
+            Access::Write => write,
+            Access::ReadWrite => true,
+        };
+        access && (self.begin..=self.end).contains(&addr)
+    }
+}
+
+/// Reason the debugger stopped the emulation
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Break {
+    /// Stopped on request, before the next instruction
+    Pause,
+
+    /// Stepping finished
+    Step,
+
+    /// Entered the vertical blank period
+    VerticalBlank,
+
+    /// Breakpoint by index, its address is the current PC
+    Breakpoint(usize),
+
+    /// Watched memory accessed by the previous instruction
+    Watchpoint { index: usize, addr: u16, data: u8, write: bool },
+
+    /// `LD B,B` ran, test ROMs use it as a breakpoint
+    SoftwareBreakpoint,
+}
+
+impl std::fmt::Display for Break {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Break::Pause => write!(f, "paused"),
+            Break::Step => write!(f, "step"),
+            Break::VerticalBlank => write!(f, "vertical blank"),
+            Break::Breakpoint(index) => write!(f, "breakpoint {}", index),
+            Break::Watchpoint { index, addr, data, write: true } =>
+                write!(f, "watchpoint {}: write ${:02X} to ${:04X}", index, data, addr),
+            Break::Watchpoint { index, addr, data, write: false } =>
+                write!(f, "watchpoint {}: read ${:02X} from ${:04X}", index, data, addr),
+            Break::SoftwareBreakpoint => write!(f, "ld b,b"),
+        }
+    }
+}
+
+// What the emulation runs until, besides breakpoints and watchpoints
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum RunMode {
+    Continue,
+    StepInto,
+    StepOver { addr: u16, sp: u16 },
+    StepOut { sp: u16 },
+    VerticalBlank,
+}
+
+// CALL, CALL cc and RST push a return address
+pub fn is_call(opcode: u8) -> bool {
+    matches!(opcode, 0xCD | 0xC4 | 0xCC | 0xD4 | 0xDC) || opcode & 0xC7 == 0xC7
+}
+
+// RET, RET cc and RETI pop it
+pub fn is_return(opcode: u8) -> bool {
+    matches!(opcode, 0xC9 | 0xD9 | 0xC0 | 0xC8 | 0xD0 | 0xD8)
+}
+
+/// Debugger
+///
+/// Checked by the engine after every instruction while enabled. Breakpoints
+/// stop before the instruction at their address runs, watchpoints after the
+/// instruction that touched the memory. Once stopped the engine doesn't run
+/// until one of the run commands (`resume`, `step_*`, `run_to_vertical_blank`)
+/// is given.
+pub struct Debugger {
+    enabled: bool,
+
+    breakpoints: Vec<Option<Breakpoint>>,
+    watchpoints: Vec<Option<Watchpoint>>,
+
+    mode: RunMode,
+    stopped: Option<Break>,
+
+    // Stop after `LD B,B`
+    break_on_ld_b_b: bool,
+
+    // Watchpoint hit by the running instruction
+    // - memory reads only borrow the engine
+    watch_hit: Cell<Option<Break>>,
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+            mode: RunMode::Continue,
+            stopped: None,
+            break_on_ld_b_b: false,
+            watch_hit: Cell::new(None),
+        }
+    }
+}
+
+impl Debugger {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// Treat `LD B,B` as a breakpoint, as Mooneye test ROMs expect
+    pub fn set_break_on_ld_b_b(&mut self, enabled: bool) {
+        self.break_on_ld_b_b = enabled;
+    }
+
+    /// Returns the breakpoint index
+    pub fn add_breakpoint(&mut self, addr: u16, condition: Option<Condition>) -> usize {
+        self.breakpoints.push(Some(Breakpoint { addr, condition }));
+        self.breakpoints.len() - 1
+    }
+
+    /// Returns the watchpoint index
+    pub fn add_watchpoint(&mut self, begin: u16, end: u16, access: Access) -> usize {
+        self.watchpoints.push(Some(Watchpoint { begin, end, access }));
+        self.watchpoints.len() - 1
+    }
+
+    // Indices stay valid after removals
+    pub fn remove_breakpoint(&mut self, index: usize) -> bool {
+        self.breakpoints.get_mut(index).and_then(Option::take).is_some()
+    }
+
+    pub fn remove_watchpoint(&mut self, index: usize) -> bool {
+        self.watchpoints.get_mut(index).and_then(Option::take).is_some()
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = (usize, &Breakpoint)> {
+        self.breakpoints.iter().enumerate().filter_map(|(index, breakpoint)| Some((index, breakpoint.as_ref()?)))
+    }
+
+    pub fn watchpoints(&self) -> impl Iterator<Item = (usize, &Watchpoint)> {
+        self.watchpoints.iter().enumerate().filter_map(|(index, watchpoint)| Some((index, watchpoint.as_ref()?)))
+    }
+
+    /// Why the emulation is stopped, if it is
+    pub fn stopped(&self) -> Option<Break> {
+        self.stopped
+    }
+
+    pub fn pause(&mut self) {
+        self.stopped = Some(Break::Pause);
+    }
+
+    pub fn resume(&mut self) {
+        self.run(RunMode::Continue);
+    }
+
+    pub fn step_into(&mut self) {
+        self.run(RunMode::StepInto);
+    }
+
+    /// Runs a CALL or RST at `pc` until it returns to the next instruction
+    pub fn step_over(&mut self, pc: u16, opcode: u8, size: u16, sp: u16) {
+        if is_call(opcode) {
+            self.run(RunMode::StepOver { addr: pc.wrapping_add(size), sp });
+        } else {
+            self.run(RunMode::StepInto);
+        }
+    }
+
+    /// Runs until a return pops the stack above `sp`
+    pub fn step_out(&mut self, sp: u16) {
+        self.run(RunMode::StepOut { sp });
+    }
+
+    pub fn run_to_vertical_blank(&mut self) {
+        self.run(RunMode::VerticalBlank);
+    }
+
+    fn run(&mut self, mode: RunMode) {
+        self.mode = mode;
+        self.stopped = None;
+        self.watch_hit.set(None);
+    }
+
+    pub fn watch_read(&self, addr: u16, data: u8) {
+        self.watch(addr, data, false);
+    }
+
+    pub fn watch_write(&self, addr: u16, data: u8) {
+        self.watch(addr, data, true);
+    }
+
+    fn watch(&self, addr: u16, data: u8, write: bool) {
+        if !self.enabled || self.watch_hit.get().is_some() {
+            return;
+        }
+        let hit = self.watchpoints().find(|(_, watchpoint)| watchpoint.matches(addr, write));
+        if let Some((index, _)) = hit {
+            self.watch_hit.set(Some(Break::Watchpoint { index, addr, data, write }));
+        }
+    }
+
+    /// Decide whether to stop after an instruction
+    /// - `opcode` is the instruction that ran, `regs` the state it left
+    pub fn check(&mut self, opcode: u8, regs: &Regs, vertical_blank: bool) -> bool {
+        if self.stopped.is_some() {
+            return true;
+        }
+
+        let reason = self.watch_hit.take().or_else(|| match self.mode {
+            RunMode::StepInto => Some(Break::Step),
+            RunMode::StepOver { addr, sp } if regs.pc() == addr && regs.sp() >= sp => Some(Break::Step),
+            RunMode::StepOut { sp } if is_return(opcode) && regs.sp() > sp => Some(Break::Step),
+            RunMode::VerticalBlank if vertical_blank => Some(Break::VerticalBlank),
+            _ => None,
+        }).or_else(|| {
+            self.breakpoints()
+                .find(|(_, breakpoint)| {
+                    breakpoint.addr == regs.pc() && breakpoint.condition.as_ref().map_or(true, |condition| condition.is_met(regs))
+                })
+                .map(|(index, _)| Break::Breakpoint(index))
+        }).or((self.break_on_ld_b_b && opcode == 0x40).then_some(Break::SoftwareBreakpoint));
+
+        if reason.is_some() {
+            self.mode = RunMode::Continue;
+            self.stopped = reason;
+        }
+        self.stopped.is_some()
+    }
+}
+
+#[test]
+fn condition_test() {
+    let mut regs = Regs::default();
+    regs.set_a(0x10);
+    regs.set_hl(0xC000);
+
+    let condition = Condition::parse("a == $10").unwrap();
+    assert_eq!(Condition { register: Register::A, compare: Compare::Equal, value: 0x10 }, condition);
+    assert!(condition.is_met(&regs));
+    assert_eq!("a == $10", condition.to_string());
+
+    assert!(Condition::parse("HL>=c000").unwrap().is_met(&regs));
+    assert!(!Condition::parse("hl < 0xC000").unwrap().is_met(&regs));
+    assert!(Condition::parse("a != #15").unwrap().is_met(&regs));
+    assert!(Condition::parse("a <= #16").unwrap().is_met(&regs));
+
+    assert!(Condition::parse("x == 1").is_err());
+    assert!(Condition::parse("a = 1").is_err());
+    assert!(Condition::parse("a == zz").is_err());
+}
+
+#[test]
+fn call_return_opcodes_test() {
+    let calls: Vec<u8> = (0..=255).filter(|opcode| is_call(*opcode)).collect();
+    assert_eq!(vec![0xC4, 0xC7, 0xCC, 0xCD, 0xCF, 0xD4, 0xD7, 0xDC, 0xDF, 0xE7, 0xEF, 0xF7, 0xFF], calls);
+    assert!(is_return(0xC9) && is_return(0xD9) && !is_return(0xC3));
+}
+
+#[test]
+fn debugger_test() {
+    use crate::emulator::engine::Engine;
+    use crate::emulator::test_fixture::TempFile;
+
+    let rom = TempFile::blank_rom("debugger");
+    let mut engine = Engine::default();
+    engine.open_rom_file(rom.to_str());
+    engine.debugger_mut().set_enabled(true);
+    let mut ticks = 0;
+    let mut run_until_stopped = |engine: &mut Engine| {
+        while engine.run_next_frame(&mut ticks) {}
+        engine.debugger().stopped()
+    };
+
+    // the boot ROM clears VRAM from $9FFF down in a loop at $0007
+    let index = engine.debugger_mut().add_breakpoint(0x0007, Some(Condition::parse("hl == $9FF0").unwrap()));
+    assert_eq!(Some(Break::Breakpoint(index)), run_until_stopped(&mut engine));
+    assert_eq!(0x9FF0, engine.cpu_regs().hl());
+
+    // nothing runs until resumed
+    assert_eq!(Some(Break::Breakpoint(index)), run_until_stopped(&mut engine));
+    assert_eq!(0x9FF0, engine.cpu_regs().hl());
+    assert!(engine.debugger_mut().remove_breakpoint(index));
+
+    let index = engine.debugger_mut().add_watchpoint(0x8000, 0x8001, Access::Write);
+    engine.debugger_mut().resume();
+    let stopped = run_until_stopped(&mut engine);
+    assert_eq!(Some(Break::Watchpoint { index, addr: 0x8001, data: 0, write: true }), stopped);
+    engine.debugger_mut().remove_watchpoint(index);
+
+    // $0028 CALL $0095, $002B CALL $0096
+    let index = engine.debugger_mut().add_breakpoint(0x0028, None);
+    engine.debugger_mut().resume();
+    assert_eq!(Some(Break::Breakpoint(index)), run_until_stopped(&mut engine));
+    assert_eq!(0x0028, engine.cpu_regs().pc());
+
+    engine.debugger_mut().step_into();
+    assert_eq!(Some(Break::Step), run_until_stopped(&mut engine));
+    assert_eq!(0x0095, engine.cpu_regs().pc());
+    assert_eq!(0x002B, engine.stack_entry(0));
+
+    let sp = engine.cpu_regs().sp();
+    engine.debugger_mut().step_out(sp);
+    assert_eq!(Some(Break::Step), run_until_stopped(&mut engine));
+    assert_eq!(0x002B, engine.cpu_regs().pc());
+
+    let regs = engine.cpu_regs();
+    let opcode = engine.peek(regs.pc());
+    engine.debugger_mut().step_over(regs.pc(), opcode, 3, regs.sp());
+    assert_eq!(Some(Break::Step), run_until_stopped(&mut engine));
+    assert_eq!(0x002E, engine.cpu_regs().pc());
+}
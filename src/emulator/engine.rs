@@ -1,3 +1,5 @@
+use std::collections::BTreeMap;
+
 use sdl2::audio::AudioQueue;
 use sdl2::render::Texture;
 
@@ -8,13 +10,23 @@ use crate::emulator::cpu::regs::Regs;
 use crate::emulator::cpu::interrupts::Interrupts;
 use crate::emulator::cpu::Processor;
 use crate::emulator::cartridge::Cartridge;
+use crate::emulator::cheats::{Cheat, Cheats};
+use crate::emulator::debugger::Debugger;
 use crate::emulator::ppu::Ppu;
 use crate::emulator::ppu::SCREEN_BUFFER_WIDTH;
-use crate::emulator::joypad::Joypad;
+use crate::emulator::joypad::{Joypad, JoypadKeys};
+use crate::emulator::joypad::bindings::Bindings;
 use crate::emulator::mmu::Memory;
+use crate::emulator::model::Model;
 use crate::emulator::serial::Serial;
+use crate::emulator::serial::SerialSink;
+use crate::emulator::serial::link::LinkPort;
 use crate::emulator::sound::Sounder;
+use crate::emulator::sound::blip::Quality;
+use crate::emulator::state::{Snapshot, StateReader, StateWriter};
+use crate::emulator::symbols::Symbols;
 use crate::emulator::timer::Timer;
+use crate::emulator::trace::Tracer;
 
 pub const TICKS_PER_SECOND: u64 = 4_194_304;
 pub const TICKS_PER_FRAME:  u64 = TICKS_PER_SECOND / 60;
@@ -36,12 +48,17 @@ pub struct Engine {
     // - auxiliar flag to emulate EI/DI change after execute next instruction
     next_interrupt_enable: bool,
 
+    // STOP Mode
+    // - the CPU and the clocks halt until a joypad line goes low
+    stopped: bool,
+
     // BIOS Enabled
     bios_enable: bool,
 
     // BIOS Program
-    // - $0000..=$0100
-    bios: Box<[u8; 0x100]>,
+    // - $0000..=$00FF
+    // - $0200..=$08FF (CGB boot ROMs only)
+    bios: Vec<u8>,
 
     // Cartrige Loader
     // - $0000..=$7FFFF (ROM)
@@ -82,6 +99,22 @@ pub struct Engine {
     // Interruption Flag (IF)
     // - $FF0F (Hardware IO)
     interruptions_requested: Interrupts,
+
+    // Debugger
+    // - breakpoints and watchpoints, checked after every instruction while enabled
+    debugger: Debugger,
+
+    // Instruction Tracer
+    tracer: Option<Tracer>,
+
+    // Labels of the ROM, for the debugger and the tracer
+    symbols: Symbols,
+
+    // Values written back at the end of every frame
+    frozen: BTreeMap<u16, u8>,
+
+    // Game Genie codes live in the cartridge, GameShark codes are written every vertical blank
+    cheats: Cheats,
 }
 
 impl Engine {
@@ -89,19 +122,163 @@ impl Engine {
         self.cartridge.open(filename);
     }
 
+    /// Boot ROM mapped at power on in place of the embedded one, 256 bytes
+    /// or 2304 for a CGB boot ROM (the cartridge header shows through at $0100-$01FF)
+    pub fn set_bios(&mut self, bios: Vec<u8>) {
+        self.bios = bios;
+    }
+
+    /// Start at $0100 in the state the boot ROM of the model leaves behind
+    pub fn skip_boot(&mut self, model: Model) {
+        self.bios_enable = false;
+        let header: Vec<u8> = (0x0100..0x0150).map(|addr| self.peek(addr)).collect();
+
+        if model != Model::Cgb {
+            self.write_boot_logo();
+        }
+        for (addr, data) in model.boot_io() {
+            self.write(addr, data);
+        }
+        if let Some(divider) = model.boot_divider() {
+            self.timer.set_divider(divider);
+        }
+
+        self.set_cpu_regs(model.boot_regs(&header));
+        self.interrupt_enable = false;
+        self.next_interrupt_enable = false;
+    }
+
+    /// Whether the boot ROM is still mapped over the cartridge
+    pub fn is_booting(&self) -> bool {
+        self.bios_enable
+    }
+
+    // Logo tiles and map as the DMG boot ROM leaves them, scrolled into place
+    // - every nibble of the header logo doubles into a byte, on two rows
+    fn write_boot_logo(&mut self) {
+        for (index, addr) in (0x0104..0x0134).enumerate() {
+            let byte = self.peek(addr);
+            for (half, nibble) in [byte >> 4, byte & 0x0F].iter().enumerate() {
+                let row = (0..4).fold(0u8, |row, bit| (row << 2) | ((nibble >> (3 - bit) & 1) * 0b11));
+                let tile_addr = 0x8010 + (index * 8 + half * 4) as u16;
+                self.write(tile_addr, row);
+                self.write(tile_addr + 2, row);
+            }
+        }
+        for (index, row) in DMG_BIOS[0xD8..0xE0].iter().enumerate() {
+            self.write(0x8190 + index as u16 * 2, *row);
+        }
+
+        for tile in 0..12 {
+            self.write(0x9904 + tile, tile as u8 + 1);
+            self.write(0x9924 + tile, tile as u8 + 13);
+        }
+        self.write(0x9910, 0x19);
+    }
+
+    // Whether the boot ROM answers reads at an address
+    fn bios_mapped(&self, addr: u16) -> bool {
+        self.bios_enable && (addr < 0x0100 || (0x0200..0x0900).contains(&addr) && self.bios.len() > 0x0200)
+    }
+
+    pub fn rom_digest(&self) -> [u8; 16] {
+        self.cartridge.rom_digest()
+    }
+
     pub fn blit_frame_to_texture(&mut self, texture: &mut Texture) {
         texture.update(None, self.ppu.frame_buffer(), SCREEN_BUFFER_WIDTH).unwrap();
     }
 
-    pub fn enqueue_audio_samples(&mut self, channels: &mut [AudioQueue<i8>; 4]) {
+    pub fn enqueue_audio_samples(&mut self, channels: &mut [AudioQueue<i16>; 4]) {
         self.sounder.enqueue_audio_samples(channels);
     }
 
+    pub fn set_audio_muted(&mut self, muted: bool) {
+        self.sounder.set_muted(muted);
+    }
+
+    pub fn silence_audio_frame(&mut self) {
+        self.sounder.silence_frame();
+    }
+
+    pub fn set_audio_sample_rate(&mut self, sample_rate: u32) {
+        self.sounder.set_sample_rate(sample_rate);
+    }
+
+    pub fn set_audio_quality(&mut self, quality: Quality) {
+        self.sounder.set_quality(quality);
+    }
+
+    pub fn set_audio_register_log(&mut self, enabled: bool) {
+        self.sounder.set_register_log(enabled);
+    }
+
+    pub fn start_audio_recording(&mut self, filename: &str, stems: bool) -> std::io::Result<()> {
+        self.sounder.start_recording(filename, stems)
+    }
+
+    pub fn stop_audio_recording(&mut self) -> std::io::Result<()> {
+        self.sounder.stop_recording()
+    }
+
+    pub fn is_audio_recording(&self) -> bool {
+        self.sounder.is_recording()
+    }
+
+    pub fn start_video_soundtrack(&mut self, filename: &str) -> std::io::Result<()> {
+        self.sounder.start_video_soundtrack(filename)
+    }
+
+    pub fn stop_video_soundtrack(&mut self) -> std::io::Result<()> {
+        self.sounder.stop_video_soundtrack()
+    }
+
+    pub fn connect_serial_link(&mut self, link: Box<dyn LinkPort>) {
+        self.serial.connect(link);
+    }
+
+    pub fn set_serial_sink(&mut self, sink: Option<SerialSink>) {
+        self.serial.set_sink(sink);
+    }
+
+    pub fn serial_output(&self) -> &[u8] {
+        self.serial.sink_buffer()
+    }
+
+    pub fn set_input_bindings(&mut self, bindings: Bindings) {
+        self.joypad.set_bindings(bindings);
+    }
+
     pub fn process_event(&mut self, event: &sdl2::event::Event) {
         self.joypad.process_event(event);
     }
 
+    pub fn joypad_keys(&self) -> JoypadKeys {
+        self.joypad.keys()
+    }
+
+    /// Keys held on the host, even while they don't reach the game
+    pub fn host_joypad_keys(&self) -> JoypadKeys {
+        self.joypad.host_keys()
+    }
+
+    pub fn set_joypad_keys(&mut self, keys: JoypadKeys) {
+        self.joypad.set_keys(keys);
+    }
+
+    /// Whether the host inputs press the keys, movies turn it off
+    pub fn set_host_input(&mut self, enabled: bool) {
+        self.joypad.set_host_input(enabled);
+    }
+
     pub fn run_next_step(&mut self) -> u64 {
+        if self.stopped {
+            if !self.joypad.interruption_requested() {
+                return 4;
+            }
+            self.stopped = false;
+        }
+
         let ticks = self.fetch_decode_execute_store_cycle();
 
         self.serial.step(ticks);
@@ -109,6 +286,8 @@ impl Engine {
             self.interruptions_requested.set_serial_transfer_complete();
         }
 
+        self.sounder.step(ticks);
+
         self.timer.step(ticks);
         if self.timer.overflow_interrupt_requested() {
             self.interruptions_requested.set_timer_overflow();
@@ -120,6 +299,7 @@ impl Engine {
         }
         if self.ppu.vertical_blank_interrupt_requested() {
             self.interruptions_requested.set_vertical_blank();
+            self.write_game_shark_codes();
         }
 
         if self.joypad.interruption_requested() {
@@ -130,12 +310,176 @@ impl Engine {
         ticks
     }
 
-    pub fn run_next_frame(&mut self, ticks_counter: u64) -> u64 {
-        let mut ticks_counter = ticks_counter;
-        while ticks_counter < TICKS_PER_FRAME {
-            ticks_counter += self.run_next_step();
+    /// Run until the end of the frame
+    ///
+    /// Returns false when the debugger stops the emulation first, the ticks run
+    /// so far stay in `ticks_counter` and the next call carries on.
+    pub fn run_next_frame(&mut self, ticks_counter: &mut u64) -> bool {
+        while *ticks_counter < TICKS_PER_FRAME {
+            if !self.debugger.is_enabled() {
+                *ticks_counter += self.run_next_step();
+                continue;
+            }
+
+            if self.debugger.stopped().is_some() {
+                return false;
+            }
+            let opcode = self.peek(self.regs.pc());
+            *ticks_counter += self.run_next_step();
+            let regs = self.cpu_regs();
+            let vertical_blank = self.ppu.vertical_blank_interrupt_requested();
+            if self.debugger.check(opcode, &regs, vertical_blank) && *ticks_counter < TICKS_PER_FRAME {
+                return false;
+            }
+        }
+        self.sounder.end_frame();
+        self.joypad.step_frame();
+        self.write_frozen();
+        *ticks_counter -= TICKS_PER_FRAME;
+        true
+    }
+
+    /// Log every instruction from now on, or stop logging
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer;
+    }
+
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = symbols;
+    }
+
+    pub fn symbols(&self) -> &Symbols {
+        &self.symbols
+    }
+
+    /// Bank mapped at $4000-$7FFF
+    pub fn rom_bank(&self) -> u16 {
+        self.cartridge.rom_bank()
+    }
+
+    /// `Name+$offset` of an address as currently mapped, the boot ROM has no symbols
+    pub fn label(&self, addr: u16) -> Option<String> {
+        if self.bios_mapped(addr) {
+            return None;
+        }
+        self.symbols.label(self.rom_bank(), addr)
+    }
+
+    /// Instruction at `addr` with the addresses named after the symbols
+    pub fn disassemble(&self, addr: u16) -> String {
+        let bytes = [self.peek(addr), self.peek(addr.wrapping_add(1)), self.peek(addr.wrapping_add(2))];
+        if self.bios_mapped(addr) {
+            return Symbols::default().disassemble(0, addr, bytes);
+        }
+        self.symbols.disassemble(self.rom_bank(), addr, bytes)
+    }
+
+    /// Keep writing `value` to `addr` at the end of every frame
+    pub fn freeze(&mut self, addr: u16, value: u8) {
+        self.frozen.insert(addr, value);
+        self.write(addr, value);
+    }
+
+    pub fn unfreeze(&mut self, addr: u16) -> bool {
+        self.frozen.remove(&addr).is_some()
+    }
+
+    pub fn frozen(&self) -> impl Iterator<Item = (u16, u8)> + '_ {
+        self.frozen.iter().map(|(addr, value)| (*addr, *value))
+    }
+
+    pub fn is_frozen(&self, addr: u16) -> bool {
+        self.frozen.contains_key(&addr)
+    }
+
+    fn write_frozen(&mut self) {
+        let frozen: Vec<(u16, u8)> = self.frozen().collect();
+        for (addr, value) in frozen {
+            self.write(addr, value);
         }
-        ticks_counter - TICKS_PER_FRAME
+    }
+
+    pub fn set_cheats(&mut self, cheats: Cheats) {
+        self.cheats = cheats;
+        self.cartridge.set_patches(self.cheats.game_genie());
+    }
+
+    pub fn cheats(&self) -> &Cheats {
+        &self.cheats
+    }
+
+    pub fn add_cheat(&mut self, cheat: Cheat) -> usize {
+        let index = self.cheats.add(cheat);
+        self.cartridge.set_patches(self.cheats.game_genie());
+        index
+    }
+
+    /// False when there's no such cheat
+    pub fn set_cheat_enabled(&mut self, index: usize, enabled: bool) -> bool {
+        let found = self.cheats.set_enabled(index, enabled);
+        self.cartridge.set_patches(self.cheats.game_genie());
+        found
+    }
+
+    fn write_game_shark_codes(&mut self) {
+        for code in self.cheats.game_shark() {
+            let mapped = match (code.cartridge_ram_bank(), code.work_ram_bank()) {
+                (Some(bank), _) => bank == self.cartridge.ram_bank(),
+                // the DMG has a single work RAM bank at $D000
+                (_, Some(bank)) => bank == 1,
+                _ => true,
+            };
+            if mapped {
+                self.write(code.addr, code.data);
+            }
+        }
+    }
+
+    /// Video memory and registers, for the VRAM viewers
+    pub fn ppu(&self) -> &Ppu {
+        &self.ppu
+    }
+
+    pub fn debugger(&self) -> &Debugger {
+        &self.debugger
+    }
+
+    pub fn debugger_mut(&mut self) -> &mut Debugger {
+        &mut self.debugger
+    }
+
+    /// Registers with F holding the current flags
+    pub fn cpu_regs(&self) -> Regs {
+        let mut regs = self.regs;
+        regs.set_f(self.alu.flags.into());
+        regs
+    }
+
+    /// Replace the registers, the flags are taken from F
+    pub fn set_cpu_regs(&mut self, regs: Regs) {
+        self.regs = regs;
+        self.regs.set_f(regs.f() & 0xF0);
+        self.alu.flags = self.regs.f().into();
+    }
+
+    pub fn interrupt_master_enable(&self) -> bool {
+        self.interrupt_enable
+    }
+
+    pub fn interrupts_enabled(&self) -> Interrupts {
+        self.interruptions_enabled
+    }
+
+    pub fn interrupts_requested(&self) -> Interrupts {
+        self.interruptions_requested
+    }
+
+    /// Entry of the stack, 0 being the next one popped
+    pub fn stack_entry(&self, index: u16) -> u16 {
+        let stack_pointer = self.regs.sp().wrapping_add(index.wrapping_mul(2));
+        let lsb = self.peek(stack_pointer.wrapping_add(1));
+        let msb = self.peek(stack_pointer.wrapping_add(2));
+        u16::from_be_bytes([msb, lsb])
     }
 }
 
@@ -149,8 +493,10 @@ impl Default for Engine {
             interrupt_enable: true,
             next_interrupt_enable: true,
 
+            stopped: false,
+
             bios_enable: true,
-            bios: Box::new(DMG_BIOS),
+            bios: DMG_BIOS.to_vec(),
             ram: Box::new([0; 0x2000 + 127]),
             cartridge: Box::new(Cartridge::default()),
             ppu: Box::new(Ppu::default()),
@@ -160,13 +506,62 @@ impl Default for Engine {
             timer: Box::new(Timer::default()),
             interruptions_enabled: Interrupts::default(),
             interruptions_requested: Interrupts::default(),
+            debugger: Debugger::default(),
+            tracer: None,
+            symbols: Symbols::default(),
+            frozen: BTreeMap::new(),
+            cheats: Cheats::default(),
         }
     }
 }
 
-impl Memory for Engine {
-    fn read(&self, addr: u16) -> u8 {
-        if self.bios_enable && addr < 0x100 {
+// Host side configuration (link partner, bindings, audio output) is kept on load
+impl Snapshot for Engine {
+    fn save_state(&self, state: &mut StateWriter) {
+        self.regs.save_state(state);
+        state.write_u8(self.alu.into());
+        state.write_u16(self.next_pc);
+        state.write_bool(self.interrupt_enable);
+        state.write_bool(self.next_interrupt_enable);
+        state.write_bool(self.stopped);
+        state.write_bool(self.bios_enable);
+        state.write_bytes(&self.ram[..]);
+        state.write_u8(self.interruptions_enabled.into());
+        state.write_u8(self.interruptions_requested.into());
+
+        self.cartridge.save_state(state);
+        self.ppu.save_state(state);
+        self.joypad.save_state(state);
+        self.serial.save_state(state);
+        self.sounder.save_state(state);
+        self.timer.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> std::io::Result<()> {
+        self.regs.load_state(state)?;
+        self.alu = Alu::from(state.read_u8()? & 0xF0);
+        self.next_pc = state.read_u16()?;
+        self.interrupt_enable = state.read_bool()?;
+        self.next_interrupt_enable = state.read_bool()?;
+        self.stopped = state.read_bool()?;
+        self.bios_enable = state.read_bool()?;
+        state.read_bytes(&mut self.ram[..])?;
+        self.interruptions_enabled = state.read_u8()?.into();
+        self.interruptions_requested = state.read_u8()?.into();
+
+        self.cartridge.load_state(state)?;
+        self.ppu.load_state(state)?;
+        self.joypad.load_state(state)?;
+        self.serial.load_state(state)?;
+        self.sounder.load_state(state)?;
+        self.timer.load_state(state)
+    }
+}
+
+impl Engine {
+    /// Read memory without triggering watchpoints
+    pub fn peek(&self, addr: u16) -> u8 {
+        if self.bios_mapped(addr) {
             return self.bios[addr as usize];
         }
 
@@ -229,6 +624,8 @@ impl Memory for Engine {
                 0xFF25 => self.sounder.master_r1(),
                 0xFF26 => self.sounder.master_r2(),
 
+                0xFF15 | 0xFF1F | 0xFF27..=0xFF2F => 0xFF,
+
                 0xFF30 => self.sounder.channel3_sample(0x0),
                 0xFF31 => self.sounder.channel3_sample(0x1),
                 0xFF32 => self.sounder.channel3_sample(0x2),
@@ -267,7 +664,42 @@ impl Memory for Engine {
         }
     }
 
+    /// Write memory without triggering watchpoints or hardware side effects,
+    /// false for the ROM and the hardware registers which can't be written so
+    pub fn poke(&mut self, addr: u16, data: u8) -> bool {
+        if addr < 0x8000 {        // 0x0000..=0x7FFF (Cartridge ROM)
+            return false;
+        } else if addr < 0xA000 { // 0x8000..=0x9FFF (Video RAM)
+            self.ppu.write_video_ram(addr - 0x8000, data)
+        } else if addr < 0xC000 { // 0xA000..=0xBFFF (Cartridge RAM)
+            self.cartridge.write_ram(addr - 0xA000, data)
+        } else if addr < 0xE000 { // 0xC000..=0xDFFF (Internal RAM)
+            self.ram[(addr - 0xC000) as usize] = data
+        } else if addr < 0xFE00 { // 0xE000..=0xFDFF (Echo RAM)
+            self.ram[(addr - 0xE000) as usize] = data
+        } else if addr < 0xFEA0 { // 0xFE00..=0xFE9F (OAM)
+            self.ppu.write_object_attribute_ram(addr - 0xFE00, data)
+        } else if addr < 0xFF80 { // 0xFEA0..=0xFF7F (Unusable, Hardware IO)
+            return false;
+        } else if addr < 0xFFFF { // 0xFF80..=0xFFFE (Zero Page)
+            self.ram[0x2000 + (addr - 0xFF80) as usize] = data
+        } else {
+            self.interruptions_enabled = data.into()
+        }
+        true
+    }
+}
+
+impl Memory for Engine {
+    fn read(&self, addr: u16) -> u8 {
+        let data = self.peek(addr);
+        self.debugger.watch_read(addr, data);
+        data
+    }
+
     fn write(&mut self, addr: u16, data: u8) {
+        self.debugger.watch_write(addr, data);
+
         if addr < 0x8000 {        // 0x0000..=0x7FFF (Cartridge ROM)
             // println!("crom write {:02X} => {:04X}", data, addr);
             // read-only, but writting to it configures the rom bank switch
@@ -400,35 +832,53 @@ impl Processor for Engine {
 
         let i = self.interruptions_enabled & self.interruptions_requested;
 
+        self.next_pc = self.regs.pc();
         if i.vertical_blank() {
+            self.interruptions_requested.reset_vertical_blank();
             self.subroutine_call(0x40);
         } else if i.lcdc_status() {
+            self.interruptions_requested.reset_lcdc_status();
             self.subroutine_call(0x48);
         } else if i.timer_overflow() {
+            self.interruptions_requested.reset_timer_overflow();
             self.subroutine_call(0x50);
         } else if i.serial_transfer_complete() {
+            self.interruptions_requested.reset_serial_transfer_complete();
             self.subroutine_call(0x58);
         } else if i.high_to_low_pin10_to_pin_13() {
+            self.interruptions_requested.reset_high_to_low_pin10_to_pin_13();
             self.subroutine_call(0x60);
         } else {
             return false;
         }
+        self.regs.set_pc(self.next_pc);
 
         self.interrupt_enable = false;
+        self.next_interrupt_enable = false;
         return true;
     }
 
     fn fetch_decode_execute_store_cycle(&mut self) -> u64 {
         if self.interrupt_service_routine() {
-            return 4
+            return 20
         }
 
         let pc = self.regs.pc();
 
+        if let Some(mut tracer) = self.tracer.take() {
+            let pcmem = [0, 1, 2, 3].map(|offset| self.peek(pc.wrapping_add(offset)));
+            let symbols = if self.bios_mapped(pc) { None } else { Some((&self.symbols, self.rom_bank())) };
+            if let Err(error) = tracer.trace(&self.cpu_regs(), pcmem, self.bios_enable, symbols) {
+                println!("Trace failed: {}", error);
+            } else {
+                self.tracer = Some(tracer);
+            }
+        }
+
         // Fetch
-        let opcode = self.read(pc);
-        let immediate8: u8 = self.read(pc + 1);
-        let immediate16: u16 = u16::from_le_bytes([immediate8, self.read(pc + 2)]);
+        let opcode = self.peek(pc);
+        let immediate8: u8 = self.peek(pc + 1);
+        let immediate16: u16 = u16::from_le_bytes([immediate8, self.peek(pc + 2)]);
         self.next_pc = pc + instruction_size(opcode);
 
         // Decode => Execute => Store
@@ -501,6 +951,8 @@ impl Processor for Engine {
             }
             0x10 => {
                 // STOP 0
+                self.stopped = true;
+                self.timer.reset_divider();
             }
             0x11 => {
                 // LD DE, $0000
@@ -1408,6 +1860,7 @@ impl Processor for Engine {
                 // RETI
                 self.subroutine_return();
                 self.interrupt_enable = true;
+                self.next_interrupt_enable = true;
             }
             0xDA => {
                 // JP C $0000
@@ -1634,3 +2087,102 @@ impl Processor for Engine {
         u16::from_be_bytes([msb, lsb])
     }
 }
+
+// Engine running `program` from the start of the internal RAM
+#[cfg(test)]
+fn engine_running(program: &[u8]) -> Engine {
+    let mut engine = Engine::default();
+    for (offset, &data) in program.iter().enumerate() {
+        engine.poke(0xC000 + offset as u16, data);
+    }
+    let mut regs = engine.cpu_regs();
+    regs.set_pc(0xC000);
+    regs.set_sp(0xDFFE);
+    engine.set_cpu_regs(regs);
+    engine
+}
+
+#[test]
+fn engine_interrupt_service_routine_test() {
+    let mut engine = engine_running(&[0x00]);
+    engine.interruptions_enabled = 0x05.into();
+    engine.interruptions_requested = 0x05.into();
+
+    // V-Blank goes first, only its request is cleared and IME turns off
+    assert_eq!(20, engine.run_next_step());
+    assert_eq!(0x0040, engine.cpu_regs().pc());
+    assert_eq!(0xC000, engine.stack_entry(0));
+    let requested = engine.interrupts_requested();
+    assert!(!requested.vertical_blank() && requested.timer_overflow());
+    assert!(!engine.interrupt_master_enable());
+
+    // the timer request waits for IME
+    let mut regs = engine.cpu_regs();
+    regs.set_pc(0xC000);
+    engine.set_cpu_regs(regs);
+    assert_eq!(4, engine.run_next_step());
+    assert_eq!(0xC001, engine.cpu_regs().pc());
+}
+
+#[test]
+fn engine_reti_enable_timing_test() {
+    // RETI enables the interrupts right away, the handler runs next
+    let mut engine = engine_running(&[0xD9]);
+    engine.interrupt_enable = false;
+    engine.next_interrupt_enable = false;
+    engine.stack_push(0xC100);
+    engine.interruptions_enabled = 0x04.into();
+    engine.interruptions_requested = 0x04.into();
+    engine.run_next_step();
+    assert_eq!(0xC100, engine.cpu_regs().pc());
+    assert_eq!(20, engine.run_next_step());
+    assert_eq!(0x0050, engine.cpu_regs().pc());
+
+    // EI enables them only after the next instruction
+    let mut engine = engine_running(&[0xFB, 0x00, 0x00]);
+    engine.interrupt_enable = false;
+    engine.next_interrupt_enable = false;
+    engine.interruptions_enabled = 0x04.into();
+    engine.interruptions_requested = 0x04.into();
+    engine.run_next_step();
+    engine.run_next_step();
+    assert_eq!(0xC002, engine.cpu_regs().pc());
+    assert_eq!(20, engine.run_next_step());
+    assert_eq!(0x0050, engine.cpu_regs().pc());
+}
+
+#[test]
+fn engine_stop_test() {
+    let mut engine = engine_running(&[0x10, 0x00, 0x00]);
+    engine.timer.set_divider(0x12);
+    engine.write(0xFF00, 0x10);
+
+    // STOP resets DIV and nothing runs until a key is pressed
+    engine.run_next_step();
+    assert_eq!(0x00, engine.peek(0xFF04));
+    for _ in 0..16 {
+        assert_eq!(4, engine.run_next_step());
+    }
+    assert_eq!(0xC002, engine.cpu_regs().pc());
+    assert_eq!(0x00, engine.peek(0xFF04));
+
+    // a selected line going low wakes the CPU up
+    engine.set_joypad_keys(JoypadKeys::A);
+    engine.run_next_step();
+    assert_eq!(0xC003, engine.cpu_regs().pc());
+}
+
+#[test]
+fn engine_ld_a_c_size_test() {
+    // LD A,($FF00+C) is a single byte, the INC A right after it runs next
+    let mut engine = engine_running(&[0xF2, 0x3C]);
+    let mut regs = engine.cpu_regs();
+    regs.set_c(0x80);
+    engine.set_cpu_regs(regs);
+    engine.poke(0xFF80, 0x41);
+
+    engine.run_next_step();
+    assert_eq!((0x41, 0xC001), (engine.cpu_regs().a(), engine.cpu_regs().pc()));
+    engine.run_next_step();
+    assert_eq!((0x42, 0xC002), (engine.cpu_regs().a(), engine.cpu_regs().pc()));
+}
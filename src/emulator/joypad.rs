@@ -1,14 +1,13 @@
-use sdl2::event::Event;
-use sdl2::keyboard::*;
+pub mod bindings;
+
+use std::collections::HashSet;
+
+use bindings::Bindings;
+use bindings::Input;
 
-const BUTTON_A: Keycode = Keycode::Space;
-const BUTTON_B: Keycode = Keycode::LShift;
-const BUTTON_UP: Keycode = Keycode::Up;
-const BUTTON_DOWN: Keycode = Keycode::Down;
-const BUTTON_LEFT: Keycode = Keycode::Left;
-const BUTTON_RIGHT: Keycode = Keycode::Right;
-const BUTTON_START: Keycode = Keycode::Return;
-const BUTTON_SELECT: Keycode = Keycode::Backspace;
+use crate::emulator::state::{Snapshot, StateReader, StateWriter};
+
+use sdl2::event::Event;
 
 bitflags! {
     #[derive(Default)]
@@ -42,157 +41,154 @@ bitflags! {
     }
 }
 
-#[derive(Default)]
 pub struct Joypad {
     regs: JoypadRegs,
     keys: JoypadKeys,
     interruption_requested: bool,
+
+    // Host inputs mapped to keys
+    bindings: Bindings,
+    held: HashSet<Input>,
+
+    // Frames elapsed, paces turbo bindings
+    turbo_frame: u32,
+
+    // Keys the host inputs hold, applied unless a movie drives the keys
+    host_keys: JoypadKeys,
+    host_input: bool,
+}
+
+impl Default for Joypad {
+    fn default() -> Self {
+        Self {
+            regs: JoypadRegs::PAD_OUT | JoypadRegs::PAD_IN,
+            keys: JoypadKeys::empty(),
+            interruption_requested: false,
+
+            bindings: Bindings::default(),
+            held: HashSet::new(),
+
+            turbo_frame: 0,
+
+            host_keys: JoypadKeys::empty(),
+            host_input: true,
+        }
+    }
 }
 
 #[allow(dead_code)]
 impl Joypad {
     pub fn get_p1(&self) -> u8 {
-        self.regs.bits()
+        self.regs.bits() | 0b1100_0000
     }
 
     pub fn set_p1(&mut self, data: u8) {
-        self.regs = JoypadRegs::from_bits(data).unwrap();
+        // Only the selection lines are writable
+        self.regs.remove(JoypadRegs::PAD_OUT);
+        self.regs.insert(JoypadRegs::from_bits(data).unwrap() & JoypadRegs::PAD_OUT);
         self.update();
     }
 
+    pub fn set_bindings(&mut self, bindings: Bindings) {
+        self.bindings = bindings;
+        self.held.clear();
+        self.refresh_keys();
+    }
+
     pub fn process_event(&mut self, event: &sdl2::event::Event) {
         match event {
-            Event::KeyDown { keycode: Some(BUTTON_A), ..}      => { self.press_a(); }
-            Event::KeyDown { keycode: Some(BUTTON_B), ..}      => { self.press_b(); }
-            Event::KeyDown { keycode: Some(BUTTON_UP), .. }    => { self.press_up(); }
-            Event::KeyDown { keycode: Some(BUTTON_DOWN), .. }  => { self.press_down(); }
-            Event::KeyDown { keycode: Some(BUTTON_LEFT), .. }  => { self.press_left(); }
-            Event::KeyDown { keycode: Some(BUTTON_RIGHT), .. } => { self.press_right(); }
-            Event::KeyDown { keycode: Some(BUTTON_START), ..}  => { self.press_start(); }
-            Event::KeyDown { keycode: Some(BUTTON_SELECT), ..} => { self.press_select(); }
-
-            Event::KeyUp { keycode: Some(BUTTON_A), ..}      => { self.release_a(); }
-            Event::KeyUp { keycode: Some(BUTTON_B), ..}      => { self.release_b(); }
-            Event::KeyUp { keycode: Some(BUTTON_UP), .. }    => { self.release_up(); }
-            Event::KeyUp { keycode: Some(BUTTON_DOWN), .. }  => { self.release_down(); }
-            Event::KeyUp { keycode: Some(BUTTON_LEFT), .. }  => { self.release_left(); }
-            Event::KeyUp { keycode: Some(BUTTON_RIGHT), .. } => { self.release_right(); }
-            Event::KeyUp { keycode: Some(BUTTON_START), ..}  => { self.release_start(); }
-            Event::KeyUp { keycode: Some(BUTTON_SELECT), ..} => { self.release_select(); }
+            Event::KeyDown { keycode: Some(keycode), .. } => { self.set_input(Input::Key(*keycode), true); }
+            Event::KeyUp { keycode: Some(keycode), .. }   => { self.set_input(Input::Key(*keycode), false); }
 
-            _ => { }
-        }
-    }
+            Event::ControllerButtonDown { button, .. } => { self.set_input(Input::Button(*button), true); }
+            Event::ControllerButtonUp { button, .. }   => { self.set_input(Input::Button(*button), false); }
 
-    pub fn update(&mut self) {
-        let pad_selector = self.regs & JoypadRegs::PAD_OUT;
-        self.regs.remove(JoypadRegs::PAD_IN);
+            Event::ControllerAxisMotion { axis, value, .. } => {
+                let dead_zone = self.bindings.dead_zone();
+                self.set_input(Input::Axis(*axis, true), *value > dead_zone);
+                self.set_input(Input::Axis(*axis, false), *value < -dead_zone);
+            }
 
-        if pad_selector.contains(JoypadRegs::P15_OUT |JoypadRegs::P14_OUT) {
-            self.regs.insert(JoypadRegs::PAD_IN);
-        } else if pad_selector.contains(JoypadRegs::P15_OUT) {
-            self.regs.insert(JoypadRegs::from_bits(!self.keys.bits().wrapping_shr(4) & 0x0f).unwrap());
-        } else if pad_selector.contains(JoypadRegs::P14_OUT) {
-            self.regs.insert(JoypadRegs::from_bits(!self.keys.bits() & 0x0f).unwrap());
-        }  else {
-            self.regs.insert(JoypadRegs::PAD_IN);
+            _ => { }
         }
     }
 
-    pub fn press_up(&mut self) {
-        self.keys.insert(JoypadKeys::UP);
-        self.update();
-        self.interruption_requested = true;
-    }
-
-    pub fn release_up(&mut self) {
-        self.keys.remove(JoypadKeys::UP);
-        self.update();
-        self.interruption_requested = true;
+    /// Advance turbo bindings, called once per frame
+    pub fn step_frame(&mut self) {
+        self.turbo_frame = self.turbo_frame.wrapping_add(1);
+        self.refresh_keys();
     }
 
-    pub fn press_down(&mut self) {
-        self.keys.insert(JoypadKeys::DOWN);
-        self.update();
-        self.interruption_requested = true;
-    }
-
-    pub fn release_down(&mut self) {
-        self.keys.remove(JoypadKeys::DOWN);
-        self.update();
-        self.interruption_requested = true;
-    }
-
-    pub fn press_left(&mut self) {
-        self.keys.insert(JoypadKeys::LEFT);
-        self.update();
-        self.interruption_requested = true;
+    fn set_input(&mut self, input: Input, held: bool) {
+        let changed = if held { self.held.insert(input) } else { self.held.remove(&input) };
+        if changed {
+            self.refresh_keys();
+        }
     }
 
-    pub fn release_left(&mut self) {
-        self.keys.remove(JoypadKeys::LEFT);
-        self.update();
-        self.interruption_requested = true;
-    }
+    fn refresh_keys(&mut self) {
+        let turbo_pressed = (self.turbo_frame / self.bindings.turbo_period()) & 1 == 0;
 
-    pub fn press_right(&mut self) {
-        self.keys.insert(JoypadKeys::RIGHT);
-        self.update();
-        self.interruption_requested = true;
-    }
+        self.host_keys = self.bindings.bindings().iter()
+            .filter(|binding| self.held.contains(&binding.input))
+            .filter(|binding| !binding.turbo || turbo_pressed)
+            .fold(JoypadKeys::empty(), |keys, binding| keys | binding.keys);
 
-    pub fn release_right(&mut self) {
-        self.keys.remove(JoypadKeys::RIGHT);
-        self.update();
-        self.interruption_requested = true;
+        if self.host_input && self.host_keys != self.keys {
+            self.set_keys(self.host_keys);
+        }
     }
 
-    pub fn press_a(&mut self) {
-        self.keys.insert(JoypadKeys::A);
-        self.update();
-        self.interruption_requested = true;
+    /// Keys pressed, as the game sees them
+    pub fn keys(&self) -> JoypadKeys {
+        self.keys
     }
 
-    pub fn release_a(&mut self) {
-        self.keys.remove(JoypadKeys::A);
-        self.update();
-        self.interruption_requested = true;
+    /// Keys the host inputs hold, whether they are applied or not
+    pub fn host_keys(&self) -> JoypadKeys {
+        self.host_keys
     }
 
-    pub fn press_b(&mut self) {
-        self.keys.insert(JoypadKeys::B);
-        self.update();
-        self.interruption_requested = true;
+    /// Let the host inputs press the keys, or leave them to `set_keys` alone
+    pub fn set_host_input(&mut self, enabled: bool) {
+        self.host_input = enabled;
+        self.refresh_keys();
     }
 
-    pub fn release_b(&mut self) {
-        self.keys.remove(JoypadKeys::B);
-        self.update();
-        self.interruption_requested = true;
-    }
+    /// Apply the pressed keys, direction conflicts filtered out if enabled
+    pub fn set_keys(&mut self, keys: JoypadKeys) {
+        let mut keys = keys;
+        if self.bindings.filter_opposing() {
+            if keys.contains(JoypadKeys::LEFT | JoypadKeys::RIGHT) {
+                keys.remove(JoypadKeys::LEFT | JoypadKeys::RIGHT);
+            }
+            if keys.contains(JoypadKeys::UP | JoypadKeys::DOWN) {
+                keys.remove(JoypadKeys::UP | JoypadKeys::DOWN);
+            }
+        }
 
-    pub fn press_select(&mut self) {
-        self.keys.insert(JoypadKeys::SELECT);
+        self.keys = keys;
         self.update();
-        self.interruption_requested = true;
     }
 
-    pub fn release_select(&mut self) {
-        self.keys.remove(JoypadKeys::SELECT);
-        self.update();
-        self.interruption_requested = true;
-    }
+    // P14 low selects direction keys and P15 low the button keys, pressed keys
+    // pull their P10-P13 line low. A line going high to low requests the interruption.
+    pub fn update(&mut self) {
+        let mut lines = JoypadRegs::PAD_IN.bits();
+        if !self.regs.contains(JoypadRegs::P14_OUT) {
+            lines &= !self.keys.bits() & 0x0f;
+        }
+        if !self.regs.contains(JoypadRegs::P15_OUT) {
+            lines &= !self.keys.bits().wrapping_shr(4) & 0x0f;
+        }
 
-    pub fn press_start(&mut self) {
-        self.keys.insert(JoypadKeys::START);
-        self.update();
-        self.interruption_requested = true;
-    }
+        let previous_lines = (self.regs & JoypadRegs::PAD_IN).bits();
+        if previous_lines & !lines != 0 {
+            self.interruption_requested = true;
+        }
 
-    pub fn release_start(&mut self) {
-        self.keys.remove(JoypadKeys::START);
-        self.update();
-        self.interruption_requested = true;
+        self.regs.remove(JoypadRegs::PAD_IN);
+        self.regs.insert(JoypadRegs::from_bits(lines).unwrap());
     }
 
     pub fn interruption_requested(&self) -> bool {
@@ -204,25 +200,119 @@ impl Joypad {
     }
 }
 
+// Bindings and held inputs belong to the host, they are kept on load
+impl Snapshot for Joypad {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.regs.bits());
+        state.write_u8(self.keys.bits());
+        state.write_bool(self.interruption_requested);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> std::io::Result<()> {
+        self.regs = JoypadRegs::from_bits_truncate(state.read_u8()?);
+        self.keys = JoypadKeys::from_bits_truncate(state.read_u8()?);
+        self.interruption_requested = state.read_bool()?;
+        Ok(())
+    }
+}
+
 #[test]
 fn joypad_down_key_test() {
     assert_eq!(JoypadKeys::from_bits(0x94).unwrap(), JoypadKeys::A | JoypadKeys::START | JoypadKeys::UP);
 }
 
+#[test]
+fn joypad_bindings_test() {
+    let mut joypad = Joypad::default();
+    let mut bindings = Bindings::default();
+    bindings.bind(Input::Button(sdl2::controller::Button::LeftShoulder), JoypadKeys::A, false);
+    joypad.set_bindings(bindings);
+
+    // keys stay pressed while any of their inputs is held
+    joypad.set_input(Input::Button(sdl2::controller::Button::A), true);
+    joypad.set_input(Input::Button(sdl2::controller::Button::LeftShoulder), true);
+    joypad.set_input(Input::Button(sdl2::controller::Button::A), false);
+    assert_eq!(JoypadKeys::A, joypad.keys);
+    joypad.set_input(Input::Button(sdl2::controller::Button::LeftShoulder), false);
+    assert_eq!(JoypadKeys::empty(), joypad.keys);
+
+    // turbo toggles every period
+    joypad.set_input(Input::Button(sdl2::controller::Button::X), true);
+    let mut pressed = Vec::new();
+    for _ in 0..8 {
+        pressed.push(joypad.keys.contains(JoypadKeys::B));
+        joypad.step_frame();
+    }
+    assert_eq!(vec![true, true, false, false, true, true, false, false], pressed);
+}
+
+#[test]
+fn joypad_host_input_test() {
+    let mut joypad = Joypad::default();
+    joypad.set_host_input(false);
+    joypad.set_input(Input::Key(sdl2::keyboard::Keycode::Space), true);
+    assert_eq!((JoypadKeys::empty(), JoypadKeys::A), (joypad.keys(), joypad.host_keys()));
+
+    joypad.set_keys(JoypadKeys::B);
+    joypad.step_frame();
+    assert_eq!(JoypadKeys::B, joypad.keys());
+
+    joypad.set_host_input(true);
+    assert_eq!(JoypadKeys::A, joypad.keys());
+}
+
 #[test]
 fn joypad_p1_read_test() {
     let mut joypad = Joypad::default();
     joypad.keys = JoypadKeys::A | JoypadKeys::B | JoypadKeys::LEFT;
 
     // READ BUTTONS
-    joypad.regs.insert(JoypadRegs::P15_OUT);
-    joypad.regs.remove(JoypadRegs::P14_OUT);
+    joypad.regs.remove(JoypadRegs::P15_OUT);
+    joypad.regs.insert(JoypadRegs::P14_OUT);
     joypad.update();
-    assert_eq!(JoypadRegs::P15_OUT | JoypadRegs::P13_IN | JoypadRegs::P12_IN, joypad.regs);
+    assert_eq!(JoypadRegs::P14_OUT | JoypadRegs::P13_IN | JoypadRegs::P12_IN, joypad.regs);
 
     // READ DIRECTION
-    joypad.regs.remove(JoypadRegs::P15_OUT);
-    joypad.regs.insert(JoypadRegs::P14_OUT);
+    joypad.regs.insert(JoypadRegs::P15_OUT);
+    joypad.regs.remove(JoypadRegs::P14_OUT);
     joypad.update();
-    assert_eq!(JoypadRegs::P14_OUT | JoypadRegs::P13_IN | JoypadRegs::P12_IN | JoypadRegs::P10_IN, joypad.regs);
-}
\ No newline at end of file
+    assert_eq!(JoypadRegs::P15_OUT | JoypadRegs::P13_IN | JoypadRegs::P12_IN | JoypadRegs::P10_IN, joypad.regs);
+}
+
+#[test]
+fn joypad_interruption_test() {
+    let mut joypad = Joypad::default();
+    assert_eq!(0xFF, joypad.get_p1());
+
+    // direction keys selected, buttons don't pull any line low
+    joypad.set_p1(0x20);
+    joypad.set_keys(JoypadKeys::A);
+    assert!(!joypad.interruption_requested());
+    joypad.set_keys(JoypadKeys::A | JoypadKeys::DOWN);
+    assert!(joypad.interruption_requested());
+    joypad.reset_interruption_requested();
+
+    // releasing a key raises the line, no interruption
+    joypad.set_keys(JoypadKeys::A);
+    assert!(!joypad.interruption_requested());
+    assert_eq!(0xEF, joypad.get_p1());
+
+    // selecting the buttons with A held pulls P10 low
+    joypad.set_p1(0x10);
+    assert!(joypad.interruption_requested());
+    assert_eq!(0xDE, joypad.get_p1());
+}
+
+#[test]
+fn joypad_opposing_directions_test() {
+    let mut joypad = Joypad::default();
+    joypad.set_keys(JoypadKeys::LEFT | JoypadKeys::RIGHT | JoypadKeys::UP);
+    assert_eq!(JoypadKeys::LEFT | JoypadKeys::RIGHT | JoypadKeys::UP, joypad.keys);
+
+    let ini = crate::config::Ini::parse("[input]\nfilter_opposing = true").unwrap();
+    joypad.set_bindings(Bindings::from_ini(&ini).unwrap());
+    joypad.set_keys(JoypadKeys::LEFT | JoypadKeys::RIGHT | JoypadKeys::UP);
+    assert_eq!(JoypadKeys::UP, joypad.keys);
+    joypad.set_keys(JoypadKeys::UP | JoypadKeys::DOWN | JoypadKeys::A);
+    assert_eq!(JoypadKeys::A, joypad.keys);
+}
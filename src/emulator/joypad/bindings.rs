@@ -0,0 +1,202 @@
+use sdl2::controller::{Axis, Button};
+use sdl2::keyboard::Keycode;
+
+use crate::config::split_list;
+use crate::config::Ini;
+use crate::emulator::joypad::JoypadKeys;
+
+const DEFAULT_TURBO_PERIOD: u32 = 2;
+const DEFAULT_DEAD_ZONE: i16 = 8000;
+
+const BUTTON_NAMES: [(&str, JoypadKeys); 8] = [
+    ("a", JoypadKeys::A),
+    ("b", JoypadKeys::B),
+    ("select", JoypadKeys::SELECT),
+    ("start", JoypadKeys::START),
+    ("up", JoypadKeys::UP),
+    ("down", JoypadKeys::DOWN),
+    ("left", JoypadKeys::LEFT),
+    ("right", JoypadKeys::RIGHT),
+];
+
+/// Host input bound to Game Boy buttons
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Input {
+    Key(Keycode),
+    Button(Button),
+
+    /// Analog axis pushed past the dead zone, positive or negative direction
+    Axis(Axis, bool),
+}
+
+impl Input {
+    /// `Space`, `pad:a` or `axis:leftx-`, key and button names as SDL spells them
+    pub fn parse(name: &str) -> Option<Input> {
+        if let Some(button) = name.strip_prefix("pad:") {
+            Button::from_string(button).map(Input::Button)
+        } else if let Some(axis) = name.strip_prefix("axis:") {
+            let (axis, positive) = match axis.strip_suffix('-') {
+                Some(axis) => (axis, false),
+                None => (axis.strip_suffix('+').unwrap_or(axis), true),
+            };
+            Axis::from_string(axis).map(|axis| Input::Axis(axis, positive))
+        } else {
+            Keycode::from_name(name).map(Input::Key)
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Binding {
+    pub input: Input,
+    pub keys: JoypadKeys,
+
+    // Autofire, the keys toggle every turbo period while held
+    pub turbo: bool,
+}
+
+/// Input Bindings
+///
+/// Several inputs may drive the same Game Boy button. Loaded from the
+/// `[input]` section of the configuration file:
+///
+/// ```ini
+/// [input]
+/// a = Space, pad:a
+/// up = Up, pad:dpup, axis:lefty-
+/// turbo_a = S, pad:y
+/// turbo_period = 2   ; frames pressed, then as many released
+/// dead_zone = 8000
+/// filter_opposing = true   ; Left+Right and Up+Down press neither
+/// ```
+///
+/// Buttons missing from the file keep their default bindings.
+#[derive(Clone, Debug)]
+pub struct Bindings {
+    bindings: Vec<Binding>,
+    turbo_period: u32,
+    dead_zone: i16,
+
+    // Left+Right and Up+Down cancel each other
+    filter_opposing: bool,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        let mut bindings = Self {
+            bindings: Vec::new(),
+            turbo_period: DEFAULT_TURBO_PERIOD,
+            dead_zone: DEFAULT_DEAD_ZONE,
+            filter_opposing: false,
+        };
+
+        bindings.bind(Input::Key(Keycode::Space), JoypadKeys::A, false);
+        bindings.bind(Input::Key(Keycode::LShift), JoypadKeys::B, false);
+        bindings.bind(Input::Key(Keycode::Up), JoypadKeys::UP, false);
+        bindings.bind(Input::Key(Keycode::Down), JoypadKeys::DOWN, false);
+        bindings.bind(Input::Key(Keycode::Left), JoypadKeys::LEFT, false);
+        bindings.bind(Input::Key(Keycode::Right), JoypadKeys::RIGHT, false);
+        bindings.bind(Input::Key(Keycode::Return), JoypadKeys::START, false);
+        bindings.bind(Input::Key(Keycode::Backspace), JoypadKeys::SELECT, false);
+
+        bindings.bind(Input::Button(Button::A), JoypadKeys::A, false);
+        bindings.bind(Input::Button(Button::B), JoypadKeys::B, false);
+        bindings.bind(Input::Button(Button::Y), JoypadKeys::A, true);
+        bindings.bind(Input::Button(Button::X), JoypadKeys::B, true);
+        bindings.bind(Input::Button(Button::DPadUp), JoypadKeys::UP, false);
+        bindings.bind(Input::Button(Button::DPadDown), JoypadKeys::DOWN, false);
+        bindings.bind(Input::Button(Button::DPadLeft), JoypadKeys::LEFT, false);
+        bindings.bind(Input::Button(Button::DPadRight), JoypadKeys::RIGHT, false);
+        bindings.bind(Input::Button(Button::Start), JoypadKeys::START, false);
+        bindings.bind(Input::Button(Button::Back), JoypadKeys::SELECT, false);
+
+        bindings.bind(Input::Axis(Axis::LeftY, false), JoypadKeys::UP, false);
+        bindings.bind(Input::Axis(Axis::LeftY, true), JoypadKeys::DOWN, false);
+        bindings.bind(Input::Axis(Axis::LeftX, false), JoypadKeys::LEFT, false);
+        bindings.bind(Input::Axis(Axis::LeftX, true), JoypadKeys::RIGHT, false);
+
+        bindings
+    }
+}
+
+impl Bindings {
+    /// Defaults overridden by the `[input]` section
+    pub fn from_ini(ini: &Ini) -> Result<Self, String> {
+        let mut bindings = Self::default();
+
+        for (key, value) in ini.entries("input") {
+            match key {
+                "turbo_period" => {
+                    bindings.turbo_period = value.parse().map_err(|_| format!("invalid turbo_period `{}`", value))?;
+                    bindings.turbo_period = bindings.turbo_period.max(1);
+                }
+                "filter_opposing" => {
+                    bindings.filter_opposing = value.parse().map_err(|_| format!("invalid filter_opposing `{}`", value))?;
+                }
+                "dead_zone" => {
+                    bindings.dead_zone = value.parse().map_err(|_| format!("invalid dead_zone `{}`", value))?;
+                }
+                _ => {
+                    let (name, turbo) = match key.strip_prefix("turbo_") {
+                        Some(name) => (name, true),
+                        None => (key, false),
+                    };
+                    let keys = BUTTON_NAMES.iter()
+                        .find(|(button, _)| *button == name)
+                        .map(|(_, keys)| *keys)
+                        .ok_or(format!("unknown button `{}`", key))?;
+
+                    bindings.unbind(keys, turbo);
+                    for input in split_list(value) {
+                        let input = Input::parse(input).ok_or(format!("unknown input `{}`", input))?;
+                        bindings.bind(input, keys, turbo);
+                    }
+                }
+            }
+        }
+
+        Ok(bindings)
+    }
+
+    pub fn bind(&mut self, input: Input, keys: JoypadKeys, turbo: bool) {
+        self.bindings.push(Binding { input, keys, turbo });
+    }
+
+    /// Remove every input bound to the keys
+    pub fn unbind(&mut self, keys: JoypadKeys, turbo: bool) {
+        self.bindings.retain(|binding| binding.keys != keys || binding.turbo != turbo);
+    }
+
+    pub fn bindings(&self) -> &[Binding] {
+        &self.bindings
+    }
+
+    pub fn turbo_period(&self) -> u32 {
+        self.turbo_period
+    }
+
+    pub fn dead_zone(&self) -> i16 {
+        self.dead_zone
+    }
+
+    pub fn filter_opposing(&self) -> bool {
+        self.filter_opposing
+    }
+}
+
+#[test]
+fn bindings_from_ini_test() {
+    let ini = Ini::parse("[input]\nturbo_period = 4\nstart =\nturbo_b = []\nfilter_opposing = true\n").unwrap();
+    let bindings = Bindings::from_ini(&ini).unwrap();
+
+    assert_eq!(4, bindings.turbo_period());
+    assert!(bindings.filter_opposing());
+    assert_eq!(DEFAULT_DEAD_ZONE, bindings.dead_zone());
+    assert!(bindings.bindings().iter().all(|binding| binding.keys != JoypadKeys::START));
+    assert!(bindings.bindings().iter().all(|binding| !binding.turbo || binding.keys != JoypadKeys::B));
+    assert!(bindings.bindings().iter().any(|binding| binding.turbo && binding.keys == JoypadKeys::A));
+    assert!(bindings.bindings().iter().any(|binding| binding.input == Input::Key(Keycode::Space)));
+
+    let ini = Ini::parse("[input]\njump = Space\n").unwrap();
+    assert!(Bindings::from_ini(&ini).is_err());
+}
@@ -0,0 +1,119 @@
+use crate::emulator::cpu::regs::Regs;
+
+/// Hardware Revision
+///
+/// Only the DMG is emulated, the others only change the boot state.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Model {
+    /// Early DMG, its boot ROM doesn't check the logo
+    Dmg0,
+    #[default]
+    Dmg,
+    /// Game Boy Pocket
+    Mgb,
+    Sgb,
+    Cgb,
+}
+
+impl Model {
+    pub fn parse(name: &str) -> Option<Model> {
+        match name.to_lowercase().as_str() {
+            "dmg0" => Some(Model::Dmg0),
+            "dmg" => Some(Model::Dmg),
+            "mgb" => Some(Model::Mgb),
+            "sgb" => Some(Model::Sgb),
+            "cgb" => Some(Model::Cgb),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Model::Dmg0 => "DMG0",
+            Model::Dmg => "DMG",
+            Model::Mgb => "MGB",
+            Model::Sgb => "SGB",
+            Model::Cgb => "CGB",
+        }
+    }
+
+    /// CPU registers when the boot ROM jumps to $0100
+    /// - `header` is the cartridge ROM at $0100-$014F
+    pub fn boot_regs(&self, header: &[u8]) -> Regs {
+        // H and C are set unless the header checksum is 0
+        let flags = if header[0x4D] == 0 { 0x80 } else { 0xB0 };
+
+        // CGB games in CGB mode, others in compatibility mode where B and HL
+        // also depend on the licensee, which isn't modelled
+        let [a, f, b, c, d, e, h, l] = match self {
+            Model::Dmg0 => [0x01, 0x00, 0xFF, 0x13, 0x00, 0xC1, 0x84, 0x03],
+            Model::Dmg => [0x01, flags, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D],
+            Model::Mgb => [0xFF, flags, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D],
+            Model::Sgb => [0x01, 0x00, 0x00, 0x14, 0x00, 0x00, 0xC0, 0x60],
+            Model::Cgb if header[0x43] & 0x80 != 0 => [0x11, 0x80, 0x00, 0x00, 0xFF, 0x56, 0x00, 0x0D],
+            Model::Cgb => [0x11, 0x80, 0x00, 0x00, 0x00, 0x08, 0x00, 0x7C],
+        };
+
+        let mut regs = Regs::default();
+        regs.set_af(u16::from_be_bytes([a, f]));
+        regs.set_bc(u16::from_be_bytes([b, c]));
+        regs.set_de(u16::from_be_bytes([d, e]));
+        regs.set_hl(u16::from_be_bytes([h, l]));
+        regs.set_sp(0xFFFE);
+        regs.set_pc(0x0100);
+        regs
+    }
+
+    /// I/O register writes that leave them as the boot ROM does, in order
+    /// - the sound is powered on first, the SGB boot ROM doesn't play the chime
+    ///   so channel 1 is off in NR52
+    /// - LY, STAT and DMA can't be set by writing them
+    pub fn boot_io(&self) -> Vec<(u16, u8)> {
+        let (sound_on, chime) = if *self == Model::Sgb { (0xF0, 0x3F) } else { (0xF1, 0xBF) };
+        let serial_control = if *self == Model::Cgb { 0x7F } else { 0x7E };
+        vec![
+            (0xFF26, sound_on), (0xFF10, 0x80), (0xFF11, 0xBF), (0xFF12, 0xF3), (0xFF13, 0xFF), (0xFF14, chime),
+            (0xFF16, 0x3F), (0xFF17, 0x00), (0xFF18, 0xFF), (0xFF19, 0xBF),
+            (0xFF1A, 0x7F), (0xFF1B, 0xFF), (0xFF1C, 0x9F), (0xFF1D, 0xFF), (0xFF1E, 0xBF),
+            (0xFF20, 0xFF), (0xFF21, 0x00), (0xFF22, 0x00), (0xFF23, 0xBF),
+            (0xFF24, 0x77), (0xFF25, 0xF3),
+            (0xFF00, 0xCF), (0xFF01, 0x00), (0xFF02, serial_control),
+            (0xFF05, 0x00), (0xFF06, 0x00), (0xFF07, 0xF8),
+            (0xFF42, 0x00), (0xFF43, 0x00), (0xFF45, 0x00), (0xFF47, 0xFC), (0xFF4A, 0x00), (0xFF4B, 0x00),
+            (0xFF40, 0x91),
+            (0xFF0F, 0xE1), (0xFFFF, 0x00),
+        ]
+    }
+
+    /// DIV at $0100, none where it depends on how long the boot took
+    pub fn boot_divider(&self) -> Option<u8> {
+        match self {
+            Model::Dmg0 => Some(0x18),
+            Model::Dmg | Model::Mgb => Some(0xAB),
+            Model::Sgb | Model::Cgb => None,
+        }
+    }
+}
+
+#[test]
+fn model_boot_regs_test() {
+    let mut header = [0; 0x50];
+    header[0x4D] = 0x3A;
+    let regs = Model::Dmg.boot_regs(&header);
+    assert_eq!((0x01B0, 0x0013, 0x00D8, 0x014D), (regs.af(), regs.bc(), regs.de(), regs.hl()));
+    assert_eq!((0xFFFE, 0x0100), (regs.sp(), regs.pc()));
+
+    header[0x4D] = 0x00;
+    assert_eq!(0x0180, Model::Dmg.boot_regs(&header).af());
+    assert_eq!(0xFF80, Model::Mgb.boot_regs(&header).af());
+    assert_eq!(0x0014, Model::Sgb.boot_regs(&header).bc());
+    assert_eq!(0x007C, Model::Cgb.boot_regs(&header).hl());
+    header[0x43] = 0x80;
+    assert_eq!((0x1180, 0xFF56), (Model::Cgb.boot_regs(&header).af(), Model::Cgb.boot_regs(&header).de()));
+
+    assert!(Model::Sgb.boot_io().contains(&(0xFF26, 0xF0)));
+    assert!(Model::Dmg.boot_io().contains(&(0xFF26, 0xF1)));
+
+    assert_eq!(Some(Model::Mgb), Model::parse("MGB"));
+    assert_eq!(None, Model::parse("agb"));
+}
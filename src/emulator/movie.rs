@@ -0,0 +1,211 @@
+use std::path::Path;
+
+use crate::emulator::joypad::JoypadKeys;
+
+pub const MOVIE_VERSION: u32 = 1;
+
+// Input log columns, left to right
+const KEY_COLUMNS: [(char, JoypadKeys); 8] = [
+    ('U', JoypadKeys::UP),
+    ('D', JoypadKeys::DOWN),
+    ('L', JoypadKeys::LEFT),
+    ('R', JoypadKeys::RIGHT),
+    ('S', JoypadKeys::START),
+    ('s', JoypadKeys::SELECT),
+    ('B', JoypadKeys::B),
+    ('A', JoypadKeys::A),
+];
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Start {
+    /// The ROM as just opened, booting
+    PowerOn,
+
+    /// A save state taken when the recording started
+    State(Vec<u8>),
+}
+
+/// Input Movie
+///
+/// The keys pressed on every frame from a known start, replayed they take
+/// the machine through the same frames again. Stored as text, a field per
+/// line and then the input log, a frame per line in the BK2 style:
+///
+/// ```text
+/// kiwi-movie 1
+/// rom 0123456789abcdef0123456789abcdef
+/// start power-on
+/// final fedcba9876543210fedcba9876543210
+/// input
+/// |........|
+/// |...RS...|
+/// ```
+///
+/// - `rom` is the MD5 of the ROM the movie plays on
+/// - `start` is `power-on` or `state` followed by a `state <hex>` line with
+///   the save state
+/// - `final` is the MD5 of the frame buffer after the last frame, when known
+/// - input lines hold `UDLRSsBA` (Up, Down, Left, Right, Start, select, B
+///   and A), `.` for the keys released
+#[derive(Clone, Debug, PartialEq)]
+pub struct Movie {
+    pub rom_digest: [u8; 16],
+    pub start: Start,
+    pub frames: Vec<JoypadKeys>,
+    pub final_frame: Option<[u8; 16]>,
+}
+
+impl Movie {
+    pub fn new(rom_digest: [u8; 16], start: Start) -> Self {
+        Self { rom_digest, start, frames: Vec::new(), final_frame: None }
+    }
+
+    pub fn load<P: AsRef<Path>>(filename: P) -> std::io::Result<Self> {
+        let text = std::fs::read_to_string(filename)?;
+        Self::parse(&text).map_err(|error| std::io::Error::new(std::io::ErrorKind::InvalidData, error))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, filename: P) -> std::io::Result<()> {
+        std::fs::write(filename, self.to_text())
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut lines = text.lines().enumerate().map(|(number, line)| (number + 1, line.trim()));
+
+        match lines.next() {
+            Some((_, line)) if line == format!("kiwi-movie {}", MOVIE_VERSION) => {}
+            Some((_, line)) if line.starts_with("kiwi-movie ") => return Err(format!("unsupported movie version `{}`", &line[11..])),
+            _ => return Err("not a movie".to_string()),
+        }
+
+        let mut rom_digest = None;
+        let mut start = None;
+        let mut final_frame = None;
+        for (number, line) in lines.by_ref() {
+            let error = |message: &str| format!("line {}: {}", number, message);
+            let (field, value) = line.split_once(' ').unwrap_or((line, ""));
+            match (field, value) {
+                ("input", _) => break,
+                ("rom", value) => rom_digest = Some(parse_digest(value).ok_or_else(|| error("invalid ROM digest"))?),
+                ("final", value) => final_frame = Some(parse_digest(value).ok_or_else(|| error("invalid frame digest"))?),
+                ("start", "power-on") => start = Some(Start::PowerOn),
+                ("start", "state") => {}
+                ("state", value) => start = Some(Start::State(parse_hex(value).ok_or_else(|| error("invalid save state"))?)),
+                ("", _) => {}
+                _ => return Err(error(&format!("unknown field `{}`", field))),
+            }
+        }
+
+        let mut frames = Vec::new();
+        for (number, line) in lines {
+            if line.is_empty() {
+                continue;
+            }
+            frames.push(parse_keys(line).ok_or(format!("line {}: expected `|UDLRSsBA|`", number))?);
+        }
+
+        Ok(Self {
+            rom_digest: rom_digest.ok_or("missing ROM digest")?,
+            start: start.ok_or("missing start")?,
+            frames,
+            final_frame,
+        })
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = format!("kiwi-movie {}\nrom {}\n", MOVIE_VERSION, format_hex(&self.rom_digest));
+        match &self.start {
+            Start::PowerOn => text.push_str("start power-on\n"),
+            Start::State(state) => text.push_str(&format!("start state\nstate {}\n", format_hex(state))),
+        }
+        if let Some(digest) = self.final_frame.as_ref() {
+            text.push_str(&format!("final {}\n", format_hex(digest)));
+        }
+        text.push_str("input\n");
+        for keys in self.frames.iter() {
+            text.push_str(&format_keys(*keys));
+            text.push('\n');
+        }
+        text
+    }
+}
+
+/// Movie attached to the emulator
+pub enum MovieState {
+    /// Keys pressed on the host are applied and logged frame by frame
+    Recording(Movie),
+
+    /// Logged keys are applied, the host inputs are ignored until the end
+    Playing(Movie),
+}
+
+/// Error for what can't be done while a movie records or plays
+pub fn movie_busy(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::Other, message.to_string())
+}
+
+/// `|UDLRSsBA|` with `.` for the keys released
+pub fn format_keys(keys: JoypadKeys) -> String {
+    let columns: String = KEY_COLUMNS.iter()
+        .map(|(name, key)| if keys.contains(*key) { *name } else { '.' })
+        .collect();
+    format!("|{}|", columns)
+}
+
+pub fn parse_keys(text: &str) -> Option<JoypadKeys> {
+    let columns: Vec<char> = text.strip_prefix('|')?.strip_suffix('|')?.chars().collect();
+    if columns.len() != KEY_COLUMNS.len() {
+        return None;
+    }
+    columns.iter().zip(KEY_COLUMNS.iter()).try_fold(JoypadKeys::empty(), |keys, (column, (name, key))| {
+        match column {
+            '.' => Some(keys),
+            _ if column == name => Some(keys | *key),
+            _ => None,
+        }
+    })
+}
+
+fn format_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn parse_hex(text: &str) -> Option<Vec<u8>> {
+    if text.len() % 2 != 0 {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|index| u8::from_str_radix(text.get(index..index + 2)?, 16).ok()).collect()
+}
+
+fn parse_digest(text: &str) -> Option<[u8; 16]> {
+    let bytes = parse_hex(text)?;
+    let mut digest = [0; 16];
+    if bytes.len() != digest.len() {
+        return None;
+    }
+    digest.copy_from_slice(&bytes);
+    Some(digest)
+}
+
+#[test]
+fn movie_text_test() {
+    assert_eq!("|...RS...|", format_keys(JoypadKeys::RIGHT | JoypadKeys::START));
+    assert_eq!(Some(JoypadKeys::UP | JoypadKeys::SELECT | JoypadKeys::A), parse_keys("|U....s.A|"));
+    assert_eq!(None, parse_keys("|U....S.A"));
+    assert_eq!(None, parse_keys("|u.......|"));
+
+    let mut movie = Movie::new([0xAB; 16], Start::State(vec![1, 2, 0xFE]));
+    movie.frames = vec![JoypadKeys::empty(), JoypadKeys::A | JoypadKeys::B];
+    movie.final_frame = Some([0x01; 16]);
+    let text = movie.to_text();
+    assert!(text.contains("\nstate 0102fe\n"));
+    assert!(text.ends_with("input\n|........|\n|......BA|\n"));
+    assert_eq!(movie, Movie::parse(&text).unwrap());
+
+    let movie = Movie::parse("kiwi-movie 1\nrom 000102030405060708090a0b0c0d0e0f\nstart power-on\ninput\n").unwrap();
+    assert_eq!((Start::PowerOn, 0, None), (movie.start, movie.frames.len(), movie.final_frame));
+
+    assert!(Movie::parse("kiwi-movie 2\n").is_err());
+    assert!(Movie::parse("kiwi-movie 1\nstart power-on\ninput\n").is_err());
+    assert!(Movie::parse("kiwi-movie 1\nrom 00\nstart power-on\ninput\n").is_err());
+}
@@ -2,6 +2,7 @@ pub mod lcd_control;
 pub mod lcd_control_status;
 pub mod palette;
 pub mod sprite;
+pub mod viewer;
 
 use lcd_control::LcdControl;
 use lcd_control_status::LcdControlStatus;
@@ -9,6 +10,8 @@ use lcd_control_status::LcdControlMode;
 use palette::Palette;
 use sprite::Sprite;
 
+use crate::emulator::state::{Snapshot, StateReader, StateWriter};
+
 use sdl2::pixels::Color;
 
 pub const SCREEN_PIXEL_WIDTH:  usize = 160;
@@ -47,6 +50,13 @@ pub struct Ppu {
     window_y: u8,
     window_x: u8,
 
+    // Window Line Counter
+    // - window lines drawn this frame, it doesn't move while the window is hidden
+    window_line: u8,
+
+    // LY matched WY at some line of this frame, the window can't show before
+    window_y_triggered: bool,
+
     background_palette: Palette,
 
     object_palette_0: Palette,
@@ -89,6 +99,8 @@ impl Default for Ppu {
 
             window_x: 0,
             window_y: 0,
+            window_line: 0,
+            window_y_triggered: false,
 
             background_palette: Palette::default(),
             object_palette_0: Palette::default(),
@@ -155,18 +167,20 @@ impl Ppu {
 
     fn increment_scanline(&mut self) {
         self.scanline += 1;
-        self.stat.set_scanline_coincidence(self.scanline == self.scanline_compare);
-
-        if self.stat.contains(LcdControlStatus::LINE_Y_COINCIDENCE_INTERRUPT_ENABLE) {
-            self.lcdc_status_interrupt_requested = true;
-        }
+        self.compare_scanline();
     }
 
     fn reset_scanline(&mut self) {
         self.scanline = 0;
-        self.stat.set_scanline_coincidence(self.scanline == self.scanline_compare);
+        self.compare_scanline();
+    }
 
-        if self.stat.contains(LcdControlStatus::LINE_Y_COINCIDENCE_INTERRUPT_ENABLE) {
+    // LY=LYC sets the coincidence flag and requests the interruption if enabled
+    fn compare_scanline(&mut self) {
+        let coincidence = self.scanline == self.scanline_compare;
+        self.stat.set_scanline_coincidence(coincidence);
+
+        if coincidence && self.stat.contains(LcdControlStatus::LINE_Y_COINCIDENCE_INTERRUPT_ENABLE) {
             self.lcdc_status_interrupt_requested = true;
         }
     }
@@ -289,63 +303,124 @@ impl Ppu {
         }
     }
 
-    pub fn render_scanline(&mut self) {
-        let tile_map_base_addr: usize = match self.lcdc.contains(
-            LcdControl::BACKGROUND_AND_TILE_MAP_DISPLAY_SELECT) {
-            true => 0x1C00,
-            false => 0x1800,
-        };
-
-        let tile_data_base_addr: usize = match self.lcdc.contains(
-            LcdControl::BACKGROUND_AND_TILE_DATA_DISPLAY_SELECT) {
-            true => 0x0000,
-            false => 0x1000,
-        };
+    /// Color number (0-3) of a pixel of one of the 384 tiles at $8000-$97FF
+    pub fn tile_pixel(&self, tile: usize, x: usize, y: usize) -> u8 {
+        let addr = tile * TILE_SIZE + y * PIXEL_BIT_DEPTH;
+        let bit_index = 7 - x as u32;
+        let lsb = self.video_ram[addr].wrapping_shr(bit_index) & 1;
+        let msb = self.video_ram[addr + 1].wrapping_shr(bit_index) & 1;
+        msb << 1 | lsb
+    }
 
-        let y = self.scanline as usize;
-        let tile_y = self.scanline.wrapping_add(self.scroll_y) as usize;
-        let frame_buffer = &mut self.frame_buffer[self.back_buffer_index];
+    /// Tile used by a BG or window map entry, $8800 addressing is signed
+    pub fn tile_map_tile(&self, map: usize, column: usize, row: usize) -> usize {
+        let tile = self.video_ram[0x1800 + map * 0x400 + row * TILE_PER_ROW + column] as usize;
+        if self.lcdc.contains(LcdControl::BACKGROUND_AND_TILE_DATA_DISPLAY_SELECT) {
+            tile
+        } else {
+            (0x100 + (tile as i8 as isize)) as usize
+        }
+    }
 
-        let mut x = 0;
-        while x < SCREEN_PIXEL_WIDTH {
-            let tile_x = (x as u8).wrapping_add(self.scroll_x) as usize;
-            let tile_map_offset = (tile_x / TILE_WIDTH) + (tile_y / TILE_HEIGHT) * TILE_PER_ROW;
+    // Color index of the BG or window pixel at (x, y) of the map at $9800 (0) or $9C00 (1)
+    fn tile_map_pixel(&self, map: usize, x: u8, y: u8) -> u8 {
+        let (x, y) = (x as usize, y as usize);
+        let tile = self.tile_map_tile(map, x / TILE_WIDTH, y / TILE_HEIGHT);
+        self.tile_pixel(tile, x % TILE_WIDTH, y % TILE_HEIGHT)
+    }
 
-            let tile_map_addr = tile_map_base_addr + tile_map_offset;
-            let tile_map = self.video_ram[tile_map_addr] as usize;
+    pub fn render_scanline(&mut self) {
+        let y = self.scanline as usize;
+        if self.scanline == self.window_y {
+            self.window_y_triggered = true;
+        }
 
-            let tile_data_addr: usize = if tile_map >= 128 && tile_data_base_addr != 0  {
-                let tile_map = 255 - tile_map + 1;
-                tile_data_base_addr - tile_map * TILE_SIZE
-            } else {
-                tile_data_base_addr + tile_map * TILE_SIZE
-            } + (tile_y % TILE_HEIGHT) * PIXEL_BIT_DEPTH;
+        // BG and window color indexes, sprites go behind the ones other than 0
+        let mut background = [0u8; SCREEN_PIXEL_WIDTH];
+        let mut shades = [0u8; SCREEN_PIXEL_WIDTH];
+
+        // LCDC bit 0 blanks both the BG and the window to white
+        if self.lcdc.is_background_on() {
+            let map = self.lcdc.contains(LcdControl::BACKGROUND_AND_TILE_MAP_DISPLAY_SELECT) as usize;
+            let tile_y = self.scanline.wrapping_add(self.scroll_y);
+            for (x, color) in background.iter_mut().enumerate() {
+                let tile_x = (x as u8).wrapping_add(self.scroll_x);
+                *color = self.tile_map_pixel(map, tile_x, tile_y);
+            }
 
-            let tile_data_lsb = self.video_ram[tile_data_addr];
-            let tile_data_msb = self.video_ram[tile_data_addr + 1];
+            // the window starts at WX - 7, its lines are counted only when drawn
+            let window_x = self.window_x as usize;
+            if self.lcdc.is_window_on() && self.window_y_triggered && window_x < SCREEN_PIXEL_WIDTH + 7 {
+                let map = self.lcdc.contains(LcdControl::WINDOW_TILE_MAP_DISPLAY_SELECT) as usize;
+                for (x, color) in background.iter_mut().enumerate().skip(window_x.saturating_sub(7)) {
+                    let tile_x = (x + 7 - window_x) as u8;
+                    *color = self.tile_map_pixel(map, tile_x, self.window_line);
+                }
+                self.window_line = self.window_line.wrapping_add(1);
+            }
 
-            let bit_index = 7 - (tile_x % 8) as u32;
+            for (shade, color) in shades.iter_mut().zip(background.iter()) {
+                *shade = self.background_palette.palette_color_index(*color);
+            }
+        }
 
-            let palette_index = (tile_data_lsb.wrapping_shr(bit_index) & 1) * 2 +
-                                (tile_data_msb.wrapping_shr(bit_index) & 1);
+        if self.lcdc.is_object_sprite_on() {
+            let (_, height) = self.lcdc.object_sprite_size();
+
+            // the first 10 sprites of the line in OAM order, the one with the
+            // smallest X is drawn on top, the first in OAM on a tie
+            let mut line_sprites = [Sprite::default(); 10];
+            let mut count = 0;
+            for sprite in self.object_attribute_ram.iter()
+                .filter(|sprite| (y + 16).wrapping_sub(sprite.y() as usize) < height as usize)
+                .take(line_sprites.len())
+            {
+                line_sprites[count] = *sprite;
+                count += 1;
+            }
+            let sprites = &mut line_sprites[..count];
+            sprites.sort_by_key(|sprite| sprite.x());
+
+            // pixels taken by a sprite above, even when it's behind the BG
+            let mut covered = [false; SCREEN_PIXEL_WIDTH];
+            for sprite in sprites.iter() {
+                let mut row = (y + 16 - sprite.y() as usize) as u8;
+                if sprite.vertical_flip() {
+                    row = height - 1 - row;
+                }
 
-            let pallete_shift = palette_index as u32 * PIXEL_BIT_DEPTH as u32;
+                // 8x16 sprites ignore bit 0 of the tile, the bottom half is the next tile
+                let tile = if height == 16 { sprite.tile() & 0xFE } else { sprite.tile() };
 
-            let palette_mask = 3;
+                let palette = if sprite.palette_index() == 0 { self.object_palette_0 } else { self.object_palette_1 };
+                for column in 0..TILE_WIDTH {
+                    let x = match (sprite.x() as usize + column).checked_sub(8) {
+                        Some(x) if x < SCREEN_PIXEL_WIDTH && !covered[x] => x,
+                        _ => continue,
+                    };
 
-            let palette: u32 = self.background_palette.into();
+                    let color = self.tile_pixel(tile as usize, if sprite.horizontal_flip() { 7 - column } else { column }, row as usize);
+                    if color == 0 {
+                        continue;
+                    }
+                    covered[x] = true;
 
-            let shade_index = (palette.wrapping_shr(pallete_shift) & palette_mask) as usize;
+                    if !sprite.priority() || background[x] == 0 {
+                        shades[x] = palette.palette_color_index(color);
+                    }
+                }
+            }
+        }
 
+        let frame_buffer = &mut self.frame_buffer[self.back_buffer_index];
+        for (x, shade_index) in shades.iter().enumerate() {
             let pos: usize = (x + y * SCREEN_PIXEL_WIDTH) * ARGB_BYTES_PER_PIXEL;
 
-            let shade = &SHADE[shade_index];
+            let shade = &SHADE[*shade_index as usize];
             frame_buffer[pos + 0] = shade.a;
             frame_buffer[pos + 1] = shade.r;
             frame_buffer[pos + 2] = shade.g;
             frame_buffer[pos + 3] = shade.b;
-
-            x += 1;
         }
     }
 
@@ -360,7 +435,7 @@ impl Ppu {
                     self.ticks -= 204;
                     self.increment_scanline();
     
-                    if self.scanline >= 143 {
+                    if self.scanline >= 144 {
                         self.set_mode(LcdControlMode::VerticalBlank);
                     } else {
                         self.set_mode(LcdControlMode::ScanningOAM);
@@ -376,6 +451,8 @@ impl Ppu {
                     if self.scanline > 153 {
                         self.set_mode(LcdControlMode::ScanningOAM);
                         self.reset_scanline();
+                        self.window_line = 0;
+                        self.window_y_triggered = false;
 
                         // Swap frame buffers (XOR SWAP)
                         self.back_buffer_index  ^= self.front_buffer_index;
@@ -402,3 +479,307 @@ impl Ppu {
         }
     }
 }
+
+impl Snapshot for Ppu {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.lcdc.into());
+        state.write_u8(self.stat.into());
+        state.write_bytes(&[self.scanline, self.scanline_compare, self.scroll_y, self.scroll_x, self.window_y, self.window_x, self.window_line]);
+        state.write_bool(self.window_y_triggered);
+        state.write_bytes(&[self.background_palette.into(), self.object_palette_0.into(), self.object_palette_1.into()]);
+        state.write_u64(self.ticks);
+        state.write_bool(self.lcdc_status_interrupt_requested);
+        state.write_bool(self.vertical_blank_interrupt_requested);
+
+        state.write_u8(self.front_buffer_index as u8);
+        state.write_bytes(&self.frame_buffer[0][..]);
+        state.write_bytes(&self.frame_buffer[1][..]);
+
+        for addr in 0..160 {
+            state.write_u8(self.read_object_attribute_ram(addr));
+        }
+        state.write_bytes(&self.video_ram[..]);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> std::io::Result<()> {
+        self.lcdc = LcdControl::from(state.read_u8()?);
+        self.stat = LcdControlStatus::from_bits_truncate(state.read_u8()?);
+
+        let mut r = [0; 7];
+        state.read_bytes(&mut r)?;
+        let [scanline, scanline_compare, scroll_y, scroll_x, window_y, window_x, window_line] = r;
+        self.scanline = scanline;
+        self.scanline_compare = scanline_compare;
+        self.scroll_y = scroll_y;
+        self.scroll_x = scroll_x;
+        self.window_y = window_y;
+        self.window_x = window_x;
+        self.window_line = window_line;
+        self.window_y_triggered = state.read_bool()?;
+
+        self.background_palette = state.read_u8()?.into();
+        self.object_palette_0 = state.read_u8()?.into();
+        self.object_palette_1 = state.read_u8()?.into();
+        self.ticks = state.read_u64()?;
+        self.lcdc_status_interrupt_requested = state.read_bool()?;
+        self.vertical_blank_interrupt_requested = state.read_bool()?;
+
+        self.front_buffer_index = (state.read_u8()? & 1) as usize;
+        self.back_buffer_index = self.front_buffer_index ^ 1;
+        state.read_bytes(&mut self.frame_buffer[0][..])?;
+        state.read_bytes(&mut self.frame_buffer[1][..])?;
+
+        let mut oam = [0; 160];
+        state.read_bytes(&mut oam)?;
+        self.populate_object_attribute_ram(&oam);
+        state.read_bytes(&mut self.video_ram[..])
+    }
+}
+
+#[cfg(test)]
+fn rendered_shades(ppu: &Ppu, y: usize) -> Vec<usize> {
+    let frame_buffer = &ppu.frame_buffer[ppu.back_buffer_index];
+    (0..SCREEN_PIXEL_WIDTH)
+        .map(|x| {
+            let pos = (x + y * SCREEN_PIXEL_WIDTH) * ARGB_BYTES_PER_PIXEL;
+            SHADE.iter().position(|shade| [shade.a, shade.r, shade.g, shade.b] == frame_buffer[pos..pos + 4]).unwrap()
+        })
+        .collect()
+}
+
+#[test]
+fn ppu_tile_data_addressing_test() {
+    let mut ppu = Ppu::default();
+    ppu.set_background_palette(0b1110_0100);
+
+    // tiles 0, 1, $80 and $FF along the first line of the map at $9800,
+    // colors 1, 2 and 3 set in the low, high and both bit planes
+    for (index, tile) in [0x00, 0x01, 0x80, 0xFF].iter().enumerate() {
+        ppu.write_video_ram(0x1800 + index as u16, *tile);
+    }
+    ppu.write_video_ram(0x1000, 0xFF);          // $9000 tile 0 signed
+    ppu.write_video_ram(0x1011, 0xFF);          // $9010 tile 1 signed
+    ppu.write_video_ram(0x0800, 0xFF);          // $8800 tile $80 signed
+    ppu.write_video_ram(0x0801, 0xFF);
+    ppu.write_video_ram(0x0FF0, 0xFF);          // $8FF0 tile $FF signed, $80 unsigned
+    ppu.write_video_ram(0x0010, 0xFF);          // $8010 tile 1 unsigned
+
+    ppu.set_lcdc(0x81);
+    ppu.render_scanline();
+    assert_eq!(vec![1, 2, 3, 1], rendered_shades(&ppu, 0).iter().step_by(8).take(4).copied().collect::<Vec<_>>());
+
+    ppu.set_lcdc(0x91);
+    ppu.render_scanline();
+    assert_eq!(vec![0, 1, 3, 1], rendered_shades(&ppu, 0).iter().step_by(8).take(4).copied().collect::<Vec<_>>());
+
+    // the map at $9C00 with LCDC bit 3
+    ppu.write_video_ram(0x1C00, 0x01);
+    ppu.set_lcdc(0x99);
+    ppu.render_scanline();
+    assert_eq!(1, rendered_shades(&ppu, 0)[0]);
+}
+
+#[test]
+fn ppu_background_disable_test() {
+    let mut ppu = Ppu::default();
+    ppu.set_background_palette(0b0001_1011);
+    ppu.set_object_palette_0(0b1110_0100);
+    ppu.write_video_ram(0x0000, 0xFF);
+    ppu.write_video_ram(0x0010, 0xFF);
+    ppu.write_object_attribute_ram(0, 16);
+    ppu.write_object_attribute_ram(1, 8);
+    ppu.write_object_attribute_ram(2, 1);
+
+    ppu.set_lcdc(0x93);
+    ppu.render_scanline();
+    assert_eq!((1, 2), (rendered_shades(&ppu, 0)[0], rendered_shades(&ppu, 0)[8]));
+
+    // BG and window blank to white, sprites are still drawn
+    ppu.set_lcdc(0xB2);
+    ppu.set_window_y(0);
+    ppu.set_window_x(7);
+    ppu.render_scanline();
+    assert_eq!((1, 0), (rendered_shades(&ppu, 0)[0], rendered_shades(&ppu, 0)[8]));
+    assert_eq!(0, ppu.window_line);
+}
+
+#[test]
+fn ppu_sprite_priority_test() {
+    let mut ppu = Ppu::default();
+    ppu.set_background_palette(0b1110_0100);
+    ppu.set_object_palette_0(0b1110_0100);
+    ppu.set_object_palette_1(0b0000_0000);
+
+    // BG color 1 on the left half of the first tile, sprite tile 1 color 3
+    ppu.write_video_ram(0x0000, 0xF0);
+    ppu.write_video_ram(0x0010, 0xFF);
+    ppu.write_video_ram(0x0011, 0xFF);
+
+    // behind the BG, shown only over BG color 0
+    ppu.write_object_attribute_ram(0, 16);
+    ppu.write_object_attribute_ram(1, 8);
+    ppu.write_object_attribute_ram(2, 1);
+    ppu.write_object_attribute_ram(3, 0x80);
+
+    ppu.set_lcdc(0x93);
+    ppu.render_scanline();
+    assert_eq!(vec![1, 1, 1, 1, 3, 3, 3, 3], rendered_shades(&ppu, 0)[..8].to_vec());
+
+    // a sprite behind the BG still hides the ones below it
+    ppu.write_object_attribute_ram(4, 16);
+    ppu.write_object_attribute_ram(5, 8);
+    ppu.write_object_attribute_ram(6, 1);
+    ppu.write_object_attribute_ram(7, 0x10);
+    ppu.render_scanline();
+    assert_eq!(vec![1, 1, 1, 1, 3, 3, 3, 3], rendered_shades(&ppu, 0)[..8].to_vec());
+
+    // sprites are hidden with LCDC bit 1 off
+    ppu.set_lcdc(0x91);
+    ppu.render_scanline();
+    assert_eq!(vec![1, 1, 1, 1, 0, 0, 0, 0], rendered_shades(&ppu, 0)[..8].to_vec());
+}
+
+#[test]
+fn ppu_sprite_ordering_test() {
+    let mut ppu = Ppu::default();
+    ppu.set_object_palette_0(0b1110_0100);
+    ppu.set_object_palette_1(0b0000_1000);
+
+    // tile 1 is color 1 on its left half and transparent on the right
+    ppu.write_video_ram(0x0010, 0xF0);
+    ppu.set_lcdc(0x83);
+
+    // the smaller X is on top, whatever the OAM order
+    let put_sprite = |ppu: &mut Ppu, index: u16, x: u8, flags: u8| {
+        ppu.write_object_attribute_ram(index * 4, 16);
+        ppu.write_object_attribute_ram(index * 4 + 1, x);
+        ppu.write_object_attribute_ram(index * 4 + 2, 1);
+        ppu.write_object_attribute_ram(index * 4 + 3, flags);
+    };
+    put_sprite(&mut ppu, 0, 10, 0x00);
+    put_sprite(&mut ppu, 1, 8, 0x10);
+    ppu.render_scanline();
+    assert_eq!(vec![2, 2, 2, 2, 1, 1, 0, 0], rendered_shades(&ppu, 0)[..8].to_vec());
+
+    // the first in OAM is on top at the same X, its transparent pixels show the other
+    put_sprite(&mut ppu, 1, 10, 0x30);
+    ppu.render_scanline();
+    assert_eq!(vec![0, 0, 1, 1, 1, 1, 2, 2], rendered_shades(&ppu, 0)[..8].to_vec());
+
+    // only the first 10 sprites of the line are drawn, off screen ones count
+    for index in 0..12 {
+        put_sprite(&mut ppu, index, if index < 10 { 0 } else { 8 }, 0x00);
+    }
+    ppu.render_scanline();
+    assert_eq!(0, rendered_shades(&ppu, 0)[0]);
+}
+
+#[test]
+fn ppu_tall_sprite_test() {
+    let mut ppu = Ppu::default();
+    ppu.set_object_palette_0(0b1110_0100);
+
+    // tile 2 is color 1, tile 3 color 2
+    for row in 0..8 {
+        ppu.write_video_ram(0x0020 + row * 2, 0xFF);
+        ppu.write_video_ram(0x0031 + row * 2, 0xFF);
+    }
+    ppu.write_object_attribute_ram(0, 16);
+    ppu.write_object_attribute_ram(1, 8);
+    ppu.write_object_attribute_ram(2, 3);
+
+    // bit 0 of the tile index is ignored, tile 3 is the bottom half
+    ppu.set_lcdc(0x87);
+    let shades_at = |ppu: &mut Ppu, y: u8| {
+        ppu.scanline = y;
+        ppu.render_scanline();
+        rendered_shades(ppu, y as usize)[0]
+    };
+    assert_eq!((1, 1, 2, 2, 0), (shades_at(&mut ppu, 0), shades_at(&mut ppu, 7), shades_at(&mut ppu, 8), shades_at(&mut ppu, 15), shades_at(&mut ppu, 16)));
+
+    // flipped across all 16 lines
+    ppu.write_object_attribute_ram(3, 0x40);
+    assert_eq!((2, 1), (shades_at(&mut ppu, 0), shades_at(&mut ppu, 15)));
+
+    // 8x8 sprites use the index as is
+    ppu.set_lcdc(0x83);
+    assert_eq!((2, 0), (shades_at(&mut ppu, 0), shades_at(&mut ppu, 8)));
+}
+
+#[test]
+fn ppu_window_line_counter_test() {
+    let mut ppu = Ppu::default();
+    ppu.set_background_palette(0b1110_0100);
+
+    // window map at $9C00 is tile 1, its rows are colors 0 to 3 and back
+    ppu.write_video_ram(0x1C00, 0x01);
+    for row in 0..8 {
+        ppu.write_video_ram(0x0010 + row * 2, if row & 1 != 0 { 0xFF } else { 0x00 });
+        ppu.write_video_ram(0x0011 + row * 2, if row & 2 != 0 { 0xFF } else { 0x00 });
+    }
+    ppu.set_window_y(2);
+    ppu.set_window_x(7);
+
+    let mut shades = Vec::new();
+    for (y, window_x) in [(0, 7), (1, 7), (2, 7), (3, 7), (4, 200), (5, 7), (6, 3)] {
+        ppu.scanline = y;
+        ppu.set_window_x(window_x);
+        ppu.set_lcdc(0xF1);
+        ppu.render_scanline();
+        shades.push(rendered_shades(&ppu, y as usize)[0]);
+    }
+
+    // the hidden line 4 doesn't count, WX 3 shows the window from its column 4
+    assert_eq!(vec![0, 0, 0, 1, 0, 2, 3], shades);
+    assert_eq!(4, ppu.window_line);
+}
+
+#[test]
+fn ppu_window_y_latch_test() {
+    let mut ppu = Ppu::default();
+    ppu.set_lcdc(0xF1);
+    ppu.set_window_x(7);
+
+    // WY moved above LY before it ever matched, the window stays hidden
+    ppu.set_window_y(10);
+    for y in 0..4 {
+        ppu.scanline = y;
+        ppu.render_scanline();
+    }
+    ppu.set_window_y(1);
+    ppu.scanline = 4;
+    ppu.render_scanline();
+    assert_eq!(0, ppu.window_line);
+
+    // once matched it shows to the end of the frame, wherever WY goes
+    ppu.set_window_y(5);
+    for y in 5..8 {
+        ppu.scanline = y;
+        ppu.render_scanline();
+        ppu.set_window_y(0xFF);
+    }
+    assert_eq!(3, ppu.window_line);
+}
+
+#[test]
+fn ppu_scanline_interruption_test() {
+    let mut ppu = Ppu::default();
+    ppu.set_scanline_compare(5);
+    ppu.set_stat(0x40);
+    ppu.set_mode(LcdControlMode::ScanningOAM);
+
+    // LY=LYC requests the interruption on line 5 only, line 143 is drawn
+    // before the vertical blank
+    let mut interruptions = Vec::new();
+    let mut vertical_blank = None;
+    for _ in 0..154 * 456 / 4 {
+        ppu.step(4);
+        if ppu.lcdc_status_interrupt_requested() {
+            interruptions.push(ppu.scanline());
+        }
+        if ppu.vertical_blank_interrupt_requested() {
+            vertical_blank = Some(ppu.scanline());
+        }
+    }
+    assert_eq!((vec![5], Some(144)), (interruptions, vertical_blank));
+}
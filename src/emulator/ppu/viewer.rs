@@ -0,0 +1,211 @@
+use super::*;
+
+pub const TILE_COUNT: usize = 384;
+
+// Tile data viewer layout, the three 128 tile blocks stacked
+pub const TILES_PER_VIEWER_ROW: usize = 16;
+pub const TILES_VIEWER_WIDTH: usize = TILES_PER_VIEWER_ROW * TILE_WIDTH;
+pub const TILES_VIEWER_HEIGHT: usize = TILE_COUNT / TILES_PER_VIEWER_ROW * TILE_HEIGHT;
+
+pub const TILE_MAP_PIXEL_SIZE: usize = TILE_PER_ROW * TILE_WIDTH;
+
+pub const VIEWPORT_COLOR: Color = Color::RGB(0xE0, 0x30, 0x30);
+pub const WINDOW_COLOR: Color = Color::RGB(0x30, 0x60, 0xE0);
+
+// Color 0 of the sprites shows through
+pub const TRANSPARENT_COLOR: Color = Color::RGB(0x80, 0x80, 0x80);
+
+/// ARGB picture in the frame buffer format
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![0; width * height * ARGB_BYTES_PER_PIXEL],
+        }
+    }
+
+    /// Bytes per row
+    pub fn pitch(&self) -> usize {
+        self.width * ARGB_BYTES_PER_PIXEL
+    }
+
+    #[cfg(test)]
+    pub fn pixel(&self, x: usize, y: usize) -> Color {
+        let pos = (x + y * self.width) * ARGB_BYTES_PER_PIXEL;
+        Color::RGBA(self.pixels[pos + 1], self.pixels[pos + 2], self.pixels[pos + 3], self.pixels[pos])
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, color: Color) {
+        let pos = (x + y * self.width) * ARGB_BYTES_PER_PIXEL;
+        self.pixels[pos] = color.a;
+        self.pixels[pos + 1] = color.r;
+        self.pixels[pos + 2] = color.g;
+        self.pixels[pos + 3] = color.b;
+    }
+
+    pub fn fill(&mut self, x: usize, y: usize, width: usize, height: usize, color: Color) {
+        for row in y..(y + height).min(self.height) {
+            for column in x..(x + width).min(self.width) {
+                self.set_pixel(column, row, color);
+            }
+        }
+    }
+
+    /// Copy another image with its top left corner at `x`, `y`
+    pub fn draw(&mut self, image: &Image, x: usize, y: usize) {
+        for row in 0..image.height.min(self.height.saturating_sub(y)) {
+            let begin = (x + (y + row) * self.width) * ARGB_BYTES_PER_PIXEL;
+            let width = image.width.min(self.width.saturating_sub(x));
+            let source = row * image.pitch();
+            self.pixels[begin..begin + width * ARGB_BYTES_PER_PIXEL].copy_from_slice(&image.pixels[source..source + width * ARGB_BYTES_PER_PIXEL]);
+        }
+    }
+}
+
+/// VRAM Viewers
+///
+/// Pictures of the video memory as the PPU would use it right now, for the
+/// debug windows. Nothing here changes the PPU state.
+impl Ppu {
+    pub fn sprites(&self) -> &[Sprite; 40] {
+        &self.object_attribute_ram
+    }
+
+    /// All the tiles, 16 per row, through BGP
+    pub fn render_tiles(&self) -> Image {
+        let mut image = Image::new(TILES_VIEWER_WIDTH, TILES_VIEWER_HEIGHT);
+        for tile in 0..TILE_COUNT {
+            let tile_x = tile % TILES_PER_VIEWER_ROW * TILE_WIDTH;
+            let tile_y = tile / TILES_PER_VIEWER_ROW * TILE_HEIGHT;
+            for y in 0..TILE_HEIGHT {
+                for x in 0..TILE_WIDTH {
+                    let color = self.tile_pixel(tile, x, y);
+                    image.set_pixel(tile_x + x, tile_y + y, SHADE[self.background_palette.palette_color_index(color) as usize]);
+                }
+            }
+        }
+        image
+    }
+
+    /// 32x32 map at $9800 (0) or $9C00 (1) through BGP
+    ///
+    /// The screen area is outlined when the map is the one of the background
+    /// (wrapping around), and the window area when it's the window's.
+    pub fn render_tile_map(&self, map: usize) -> Image {
+        let mut image = Image::new(TILE_MAP_PIXEL_SIZE, TILE_MAP_PIXEL_SIZE);
+        for y in 0..TILE_MAP_PIXEL_SIZE {
+            for x in 0..TILE_MAP_PIXEL_SIZE {
+                let tile = self.tile_map_tile(map, x / TILE_WIDTH, y / TILE_HEIGHT);
+                let color = self.tile_pixel(tile, x % TILE_WIDTH, y % TILE_HEIGHT);
+                image.set_pixel(x, y, SHADE[self.background_palette.palette_color_index(color) as usize]);
+            }
+        }
+
+        let background_map = self.lcdc.contains(LcdControl::BACKGROUND_AND_TILE_MAP_DISPLAY_SELECT) as usize;
+        if map == background_map {
+            outline(&mut image, self.scroll_x as usize, self.scroll_y as usize, SCREEN_PIXEL_WIDTH, SCREEN_PIXEL_HEIGHT, VIEWPORT_COLOR);
+        }
+
+        let window_map = self.lcdc.contains(LcdControl::WINDOW_TILE_MAP_DISPLAY_SELECT) as usize;
+        let window_x = (self.window_x as usize).saturating_sub(7);
+        let window_y = self.window_y as usize;
+        if map == window_map && self.lcdc.is_window_on() && window_x < SCREEN_PIXEL_WIDTH && window_y < SCREEN_PIXEL_HEIGHT {
+            outline(&mut image, 0, 0, SCREEN_PIXEL_WIDTH - window_x, SCREEN_PIXEL_HEIGHT - window_y, WINDOW_COLOR);
+        }
+        image
+    }
+
+    /// One OAM entry as drawn (8x8 or 8x16, flipped, through OBP0/OBP1)
+    pub fn render_sprite(&self, index: usize) -> Image {
+        let sprite = &self.object_attribute_ram[index];
+        let (_, height) = self.lcdc.object_sprite_size();
+        let height = height as usize;
+        let palette = if sprite.palette_index() == 0 { self.object_palette_0 } else { self.object_palette_1 };
+
+        // the tile number bit 0 is ignored for 8x16 sprites
+        let first_tile = if height == 16 { sprite.tile() & 0xFE } else { sprite.tile() } as usize;
+
+        let mut image = Image::new(TILE_WIDTH, height);
+        for y in 0..height {
+            for x in 0..TILE_WIDTH {
+                let tile_x = if sprite.horizontal_flip() { TILE_WIDTH - 1 - x } else { x };
+                let tile_y = if sprite.vertical_flip() { height - 1 - y } else { y };
+                let color = self.tile_pixel(first_tile + tile_y / TILE_HEIGHT, tile_x, tile_y % TILE_HEIGHT);
+                let shade = match color {
+                    0 => TRANSPARENT_COLOR,
+                    _ => SHADE[palette.palette_color_index(color) as usize],
+                };
+                image.set_pixel(x, y, shade);
+            }
+        }
+        image
+    }
+}
+
+// Rectangle border wrapping around the edges
+fn outline(image: &mut Image, x: usize, y: usize, width: usize, height: usize, color: Color) {
+    for offset in 0..width {
+        image.set_pixel((x + offset) % image.width, y % image.height, color);
+        image.set_pixel((x + offset) % image.width, (y + height - 1) % image.height, color);
+    }
+    for offset in 0..height {
+        image.set_pixel(x % image.width, (y + offset) % image.height, color);
+        image.set_pixel((x + width - 1) % image.width, (y + offset) % image.height, color);
+    }
+}
+
+#[test]
+fn vram_viewer_test() {
+    let mut ppu = Ppu::default();
+    ppu.set_background_palette(0xE4);
+    ppu.set_object_palette_1(0x1B);
+
+    // tile 1: top row colors 0, 1, 2, 3 then 0s; tile 0x101 ($9010) a diagonal
+    ppu.write_video_ram(0x0010, 0b0101_0000);
+    ppu.write_video_ram(0x0011, 0b0011_0000);
+    for y in 0..8 {
+        ppu.write_video_ram(0x1010 + y * 2, 0x80 >> y);
+        ppu.write_video_ram(0x1010 + y * 2 + 1, 0x80 >> y);
+    }
+
+    let tiles = ppu.render_tiles();
+    assert_eq!((128, 192), (tiles.width, tiles.height));
+    assert_eq!(SHADE_0, tiles.pixel(8, 0));
+    assert_eq!(SHADE_1, tiles.pixel(9, 0));
+    assert_eq!(SHADE_2, tiles.pixel(10, 0));
+    assert_eq!(SHADE_3, tiles.pixel(11, 0));
+
+    // $8800 addressing: entry 1 of the map is tile $101
+    ppu.write_video_ram(0x1800 + 33, 1);
+    ppu.set_lcdc(0x81);
+    assert_eq!(0x101, ppu.tile_map_tile(0, 1, 1));
+    ppu.set_lcdc(0x91);
+    assert_eq!(1, ppu.tile_map_tile(0, 1, 1));
+
+    ppu.set_lcdc(0x81);
+    ppu.set_scroll_x(200);
+    ppu.set_scroll_y(4);
+    let map = ppu.render_tile_map(0);
+    assert_eq!(SHADE_3, map.pixel(9, 9));
+    assert_eq!(VIEWPORT_COLOR, map.pixel(200, 4));
+    assert_eq!(VIEWPORT_COLOR, map.pixel((200 + 159) % 256, 4 + 143));
+    assert_eq!(VIEWPORT_COLOR, map.pixel(0, 4));
+    assert_ne!(VIEWPORT_COLOR, ppu.render_tile_map(1).pixel(200, 4));
+
+    // flipped 8x8 sprite through OBP1
+    ppu.write_object_attribute_ram(2, 1);
+    ppu.write_object_attribute_ram(3, 0x30);
+    let sprite = ppu.render_sprite(0);
+    assert_eq!((8, 8), (sprite.width, sprite.height));
+    assert_eq!(TRANSPARENT_COLOR, sprite.pixel(7, 0));
+    assert_eq!(SHADE_2, sprite.pixel(6, 0));
+    assert_eq!(SHADE_1, sprite.pixel(5, 0));
+    assert_eq!(SHADE_0, sprite.pixel(4, 0));
+}
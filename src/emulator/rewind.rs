@@ -0,0 +1,191 @@
+use std::collections::VecDeque;
+
+pub const DEFAULT_REWIND_INTERVAL: u64 = 2;
+pub const DEFAULT_REWIND_BUDGET: usize = 32 * 1024 * 1024;
+
+/// Rewind Buffer
+///
+/// Ring buffer of save states taken every `interval` frames. Only the newest
+/// state is kept whole, every older one is stored as the difference with its
+/// newer neighbour (XOR, zero runs compressed), so dropping the oldest entry
+/// to stay within the memory budget never breaks the chain.
+pub struct Rewind {
+    interval: u64,
+    budget: usize,
+
+    // Newest snapshot and the frame it was taken at
+    newest: Option<(u64, Vec<u8>)>,
+
+    // Older snapshots, oldest first, each delta compressed against the next one
+    history: VecDeque<(u64, Vec<u8>)>,
+    history_size: usize,
+}
+
+impl Rewind {
+    pub fn new(interval: u64, budget: usize) -> Self {
+        Self {
+            interval: interval.max(1),
+            budget,
+            newest: None,
+            history: VecDeque::new(),
+            history_size: 0,
+        }
+    }
+
+    pub fn interval(&self) -> u64 {
+        self.interval
+    }
+
+    /// Bytes used by the snapshots
+    pub fn size(&self) -> usize {
+        self.history_size + self.newest.as_ref().map_or(0, |(_, state)| state.len())
+    }
+
+    pub fn clear(&mut self) {
+        self.newest = None;
+        self.history.clear();
+        self.history_size = 0;
+    }
+
+    pub fn push(&mut self, frame: u64, state: Vec<u8>) {
+        if let Some((newest_frame, newest_state)) = self.newest.take() {
+            let delta = compress_delta(&newest_state, &state);
+            self.history_size += delta.len();
+            self.history.push_back((newest_frame, delta));
+        }
+        self.newest = Some((frame, state));
+
+        while self.size() > self.budget {
+            match self.history.pop_front() {
+                Some((_, delta)) => self.history_size -= delta.len(),
+                None => break,
+            }
+        }
+    }
+
+    /// Newest snapshot and its frame
+    pub fn newest(&self) -> Option<(u64, &[u8])> {
+        self.newest.as_ref().map(|(frame, state)| (*frame, state.as_slice()))
+    }
+
+    /// Drop the newest snapshot, the previous one becomes the newest
+    pub fn pop(&mut self) -> Option<(u64, Vec<u8>)> {
+        let (frame, state) = self.newest.take()?;
+        if let Some((previous_frame, delta)) = self.history.pop_back() {
+            self.history_size -= delta.len();
+            self.newest = Some((previous_frame, apply_delta(&state, &delta)));
+        }
+        Some((frame, state))
+    }
+}
+
+fn write_length(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_length(data: &[u8], position: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = data[*position];
+        *position += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+// Delta that turns `base` into `state`, both must be the same length:
+// (zero run length, literal length, literal bytes) records of `base ^ state`
+fn compress_delta(base: &[u8], state: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_length(&mut out, state.len());
+
+    let xor = |index: usize| base.get(index).copied().unwrap_or(0) ^ state[index];
+
+    let mut index = 0;
+    while index < state.len() {
+        let zeros_begin = index;
+        while index < state.len() && xor(index) == 0 {
+            index += 1;
+        }
+
+        let literal_begin = index;
+        while index < state.len() && (xor(index) != 0 || (index + 1 < state.len() && xor(index + 1) != 0)) {
+            index += 1;
+        }
+
+        write_length(&mut out, literal_begin - zeros_begin);
+        write_length(&mut out, index - literal_begin);
+        out.extend((literal_begin..index).map(xor));
+    }
+    out
+}
+
+fn apply_delta(base: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut position = 0;
+    let len = read_length(delta, &mut position);
+
+    let mut state = base.to_vec();
+    state.resize(len, 0);
+
+    let mut index = 0;
+    while index < len {
+        index += read_length(delta, &mut position);
+        let literal = read_length(delta, &mut position);
+        for value in state[index..index + literal].iter_mut() {
+            *value ^= delta[position];
+            position += 1;
+        }
+        index += literal;
+    }
+    state
+}
+
+#[test]
+fn rewind_delta_test() {
+    let base: Vec<u8> = (0..1000).map(|index| (index % 251) as u8).collect();
+    let mut state = base.clone();
+    state[0] ^= 0xFF;
+    state[500] = 0;
+    state[501] = 1;
+    state[999] = 7;
+
+    let delta = compress_delta(&base, &state);
+    assert!(delta.len() < 32);
+    assert_eq!(state, apply_delta(&base, &delta));
+
+    let longer: Vec<u8> = state.iter().chain([1, 2, 3].iter()).copied().collect();
+    assert_eq!(longer, apply_delta(&base, &compress_delta(&base, &longer)));
+    assert_eq!(base, apply_delta(&longer, &compress_delta(&longer, &base)));
+}
+
+#[test]
+fn rewind_ring_buffer_test() {
+    let snapshot = |frame: u64| -> Vec<u8> {
+        let mut state = vec![0; 4096];
+        state[frame as usize] = frame as u8;
+        state
+    };
+
+    let mut rewind = Rewind::new(2, 4096 + 64);
+    for frame in 0..100 {
+        rewind.push(frame, snapshot(frame));
+    }
+    assert!(rewind.size() <= 4096 + 64);
+
+    let mut frame = 99;
+    while let Some((popped_frame, state)) = rewind.pop() {
+        assert_eq!(frame, popped_frame);
+        assert_eq!(snapshot(frame), state);
+        frame -= 1;
+    }
+    assert!(rewind.newest().is_none());
+    assert!(frame < 97);
+}
@@ -0,0 +1,154 @@
+use std::ops::RangeInclusive;
+
+use crate::emulator::engine::Engine;
+
+/// Writable memory areas, for the memory viewer and the RAM search
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Region {
+    VideoRam,
+    CartridgeRam,
+    WorkRam,
+    ObjectAttributeRam,
+    Io,
+    HighRam,
+}
+
+pub const REGIONS: [Region; 6] = [
+    Region::VideoRam,
+    Region::CartridgeRam,
+    Region::WorkRam,
+    Region::ObjectAttributeRam,
+    Region::Io,
+    Region::HighRam,
+];
+
+impl Region {
+    pub fn range(&self) -> RangeInclusive<u16> {
+        match self {
+            Region::VideoRam => 0x8000..=0x9FFF,
+            Region::CartridgeRam => 0xA000..=0xBFFF,
+            Region::WorkRam => 0xC000..=0xDFFF,
+            Region::ObjectAttributeRam => 0xFE00..=0xFE9F,
+            Region::Io => 0xFF00..=0xFF7F,
+            Region::HighRam => 0xFF80..=0xFFFF,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Region::VideoRam => "VRAM",
+            Region::CartridgeRam => "SRAM",
+            Region::WorkRam => "WRAM",
+            Region::ObjectAttributeRam => "OAM",
+            Region::Io => "IO",
+            Region::HighRam => "HRAM",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Region> {
+        REGIONS.iter().copied().find(|region| region.name().eq_ignore_ascii_case(name))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Greater,
+    Less,
+}
+
+impl Comparison {
+    /// `=`, `!=`, `>`, `<`, or `unchanged`, `changed`, `increased` and `decreased`
+    pub fn parse(text: &str) -> Option<Comparison> {
+        match text {
+            "=" | "==" | "unchanged" => Some(Comparison::Equal),
+            "!=" | "changed" => Some(Comparison::NotEqual),
+            ">" | "increased" => Some(Comparison::Greater),
+            "<" | "decreased" => Some(Comparison::Less),
+            _ => None,
+        }
+    }
+
+    fn is_met(&self, lhs: u8, rhs: u8) -> bool {
+        match self {
+            Comparison::Equal => lhs == rhs,
+            Comparison::NotEqual => lhs != rhs,
+            Comparison::Greater => lhs > rhs,
+            Comparison::Less => lhs < rhs,
+        }
+    }
+}
+
+/// RAM Search
+///
+/// Candidate addresses with the value they had at the last snapshot. Every
+/// `filter` compares the memory against the snapshot (or a value), drops the
+/// addresses that don't match and takes a new snapshot of the rest.
+#[derive(Default)]
+pub struct RamSearch {
+    candidates: Vec<(u16, u8)>,
+}
+
+impl RamSearch {
+    /// Every address of the regions is a candidate
+    pub fn start(&mut self, engine: &Engine, regions: &[Region]) {
+        self.candidates = regions.iter()
+            .flat_map(|region| region.range())
+            .map(|addr| (addr, engine.peek(addr)))
+            .collect();
+    }
+
+    /// Keep the addresses whose value compares to `value`, or to the snapshot without one
+    pub fn filter(&mut self, engine: &Engine, comparison: Comparison, value: Option<u8>) -> usize {
+        self.candidates.retain_mut(|(addr, previous)| {
+            let current = engine.peek(*addr);
+            let is_met = comparison.is_met(current, value.unwrap_or(*previous));
+            *previous = current;
+            is_met
+        });
+        self.candidates.len()
+    }
+
+    /// Addresses left and their value at the last snapshot
+    pub fn candidates(&self) -> &[(u16, u8)] {
+        &self.candidates
+    }
+
+    pub fn len(&self) -> usize {
+        self.candidates.len()
+    }
+}
+
+#[test]
+fn ram_search_test() {
+    use crate::emulator::mmu::Memory;
+
+    let mut engine = Engine::default();
+    for addr in 0xC000..=0xDFFF {
+        engine.write(addr, 0);
+    }
+    engine.write(0xC010, 3);
+    engine.write(0xC020, 3);
+    engine.write(0xFF90, 3);
+
+    let mut search = RamSearch::default();
+    search.start(&engine, &[Region::WorkRam, Region::HighRam]);
+    assert_eq!(0x2000 + 0x80, search.len());
+    assert_eq!(3, search.filter(&engine, Comparison::Equal, Some(3)));
+
+    // lives: one lost, the other value is a decoy that grows
+    engine.write(0xC010, 2);
+    engine.write(0xC020, 4);
+    assert_eq!(1, search.filter(&engine, Comparison::Less, None));
+    assert_eq!(&[(0xC010, 2)], search.candidates());
+
+    assert_eq!(1, search.filter(&engine, Comparison::Equal, None));
+    engine.write(0xC010, 1);
+    assert_eq!(1, search.filter(&engine, Comparison::NotEqual, None));
+    assert_eq!(0, search.filter(&engine, Comparison::Greater, None));
+    assert_eq!(0, search.len());
+
+    assert_eq!(Some(Region::HighRam), Region::parse("hram"));
+    assert_eq!(None, Region::parse("rom"));
+}
@@ -1,5 +1,30 @@
+pub mod link;
+
+use link::LinkMessage;
+use link::LinkPort;
+
+use crate::emulator::engine::TICKS_PER_FRAME;
+use crate::emulator::state::{Snapshot, StateReader, StateWriter};
 use crate::emulator::engine::TICKS_PER_SECOND;
-const SHIFT_PER_TICKS: u64 = TICKS_PER_SECOND / 8192;
+
+// Internal clock runs at 8192Hz, one bit shifted per cycle
+const TICKS_PER_SHIFT: u64 = TICKS_PER_SECOND / 8192;
+
+// Internal clock transfers give up waiting for the partner after this long,
+// shifting in $FF as if the cable was unplugged
+const LINK_TIMEOUT_TICKS: u64 = TICKS_PER_FRAME * 4;
+
+const CONTROL_TRANSFER_START: u8 = 0b1000_0000;
+const CONTROL_INTERNAL_CLOCK: u8 = 0b0000_0001;
+
+/// Serial Sink
+///
+/// Receives the bytes sent with the internal clock while no link partner is
+/// connected, test ROMs report their results this way.
+pub enum SerialSink {
+    Buffer(Vec<u8>),
+    Callback(Box<dyn FnMut(u8)>),
+}
 
 pub struct Serial {
     control: u8,
@@ -9,6 +34,17 @@ pub struct Serial {
     shift_ticks: u64,
     transfering: bool,
     transfering_completion_interruption_requested: bool,
+
+    // Link Cable Partner
+    // - none behaves like an unplugged cable
+    link: Option<Box<dyn LinkPort>>,
+
+    // Byte shifted in from the partner while driving the clock
+    link_reply: Option<u8>,
+    link_wait_ticks: u64,
+
+    // Output capture while unplugged
+    sink: Option<SerialSink>,
 }
 
 impl Default for Serial {
@@ -21,21 +57,38 @@ impl Default for Serial {
             shift_ticks: 0,
             transfering: false,
             transfering_completion_interruption_requested: false,
+
+            link: None,
+            link_reply: None,
+            link_wait_ticks: 0,
+
+            sink: None,
         }
     }
 }
 
 impl Serial {
     pub fn control(&self) -> u8 {
-        self.control
+        self.control | 0b0111_1110
     }
 
     pub fn set_control(&mut self, control: u8) {
         self.control = control;
-        
+
         self.shift_bits = 8;
         self.shift_ticks = 0;
-        self.transfering = (control & 0b1000_000) != 0;
+        self.transfering = (control & CONTROL_TRANSFER_START) != 0;
+        self.link_reply = None;
+        self.link_wait_ticks = 0;
+
+        if self.transfering && self.internal_clock() {
+            match (self.link.as_mut(), self.sink.as_mut()) {
+                (Some(link), _) => link.send(LinkMessage::Transfer(self.data)),
+                (None, Some(SerialSink::Buffer(buffer))) => buffer.push(self.data),
+                (None, Some(SerialSink::Callback(callback))) => callback(self.data),
+                (None, None) => {}
+            }
+        }
     }
 
     pub fn data(&self) -> u8 {
@@ -50,24 +103,209 @@ impl Serial {
         self.transfering_completion_interruption_requested
     }
 
+    pub fn connect(&mut self, link: Box<dyn LinkPort>) {
+        self.link = Some(link);
+    }
+
+    pub fn set_sink(&mut self, sink: Option<SerialSink>) {
+        self.sink = sink;
+    }
+
+    /// Bytes captured so far by a buffer sink
+    pub fn sink_buffer(&self) -> &[u8] {
+        match &self.sink {
+            Some(SerialSink::Buffer(buffer)) => buffer,
+            _ => &[],
+        }
+    }
+
+    fn internal_clock(&self) -> bool {
+        (self.control & CONTROL_INTERNAL_CLOCK) != 0
+    }
+
+    fn complete_transfer(&mut self) {
+        self.control &= !CONTROL_TRANSFER_START;
+        self.transfering = false;
+        self.transfering_completion_interruption_requested = true;
+    }
+
+    fn receive_link_messages(&mut self) {
+        let mut link = match self.link.take() {
+            Some(link) => link,
+            None => return,
+        };
+
+        while let Some(message) = link.recv() {
+            match message {
+                LinkMessage::Transfer(data) => {
+                    // The partner clock shifts our byte out whether a transfer
+                    // was requested or not, only the interruption depends on it
+                    link.send(LinkMessage::Reply(self.data));
+                    self.data = data;
+                    if self.transfering && !self.internal_clock() {
+                        self.complete_transfer();
+                    }
+                }
+                LinkMessage::Reply(data) => {
+                    if self.transfering && self.internal_clock() {
+                        self.link_reply = Some(data);
+                    }
+                }
+            }
+        }
+
+        self.link = Some(link);
+    }
+
     pub fn step(&mut self, ticks: u64) {
         self.transfering_completion_interruption_requested = false;
 
-        if !self.transfering {
+        self.receive_link_messages();
+
+        // External clock transfers wait for the partner
+        if !self.transfering || !self.internal_clock() {
             return
         }
 
         self.shift_ticks += ticks;
-        if self.shift_bits < SHIFT_PER_TICKS {
-            return
+        while self.shift_bits > 0 && self.shift_ticks >= TICKS_PER_SHIFT {
+            self.shift_ticks -= TICKS_PER_SHIFT;
+            self.shift_bits -= 1;
+
+            if self.link.is_none() {
+                // Unplugged cable input is pulled high
+                self.data = (self.data << 1) | 1;
+            }
         }
 
-        self.data = self.data.wrapping_shl(1);
-        self.shift_bits -= 1;
+        if self.shift_bits > 0 {
+            return
+        }
 
-        if self.shift_bits <= 0 {
-            self.transfering = false;
-            self.transfering_completion_interruption_requested = true;
+        if self.link.is_none() {
+            self.complete_transfer();
+        } else if let Some(data) = self.link_reply.take() {
+            self.data = data;
+            self.complete_transfer();
+        } else {
+            self.link_wait_ticks += ticks;
+            if self.link_wait_ticks >= LINK_TIMEOUT_TICKS {
+                self.data = 0xFF;
+                self.complete_transfer();
+            }
         }
     }
 }
+
+// The link partner and the sink belong to the host, they are kept on load
+impl Snapshot for Serial {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.control);
+        state.write_u8(self.data);
+        state.write_u64(self.shift_bits);
+        state.write_u64(self.shift_ticks);
+        state.write_bool(self.transfering);
+        state.write_bool(self.transfering_completion_interruption_requested);
+        state.write_bool(self.link_reply.is_some());
+        state.write_u8(self.link_reply.unwrap_or(0xFF));
+        state.write_u64(self.link_wait_ticks);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> std::io::Result<()> {
+        self.control = state.read_u8()?;
+        self.data = state.read_u8()?;
+        self.shift_bits = state.read_u64()?;
+        self.shift_ticks = state.read_u64()?;
+        self.transfering = state.read_bool()?;
+        self.transfering_completion_interruption_requested = state.read_bool()?;
+        let link_reply = state.read_bool()?;
+        let data = state.read_u8()?;
+        self.link_reply = if link_reply { Some(data) } else { None };
+        self.link_wait_ticks = state.read_u64()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+fn run_serial_transfer(serial: &mut Serial, partner: &mut Serial) -> (bool, bool) {
+    let mut completed = (false, false);
+    for _ in 0..(8 * TICKS_PER_SHIFT / 4 + 8) {
+        serial.step(4);
+        partner.step(4);
+        completed.0 |= serial.transfering_completion_interruption_requested();
+        completed.1 |= partner.transfering_completion_interruption_requested();
+    }
+    completed
+}
+
+#[test]
+fn serial_unplugged_test() {
+    let mut serial = Serial::default();
+    let mut partner = Serial::default();
+
+    serial.set_data(0x42);
+    serial.set_control(0x81);
+    assert_eq!(0xFF, serial.control());
+
+    serial.step(TICKS_PER_SHIFT * 4);
+    assert!(!serial.transfering_completion_interruption_requested());
+    assert_eq!(0x2F, serial.data());
+
+    assert_eq!((true, false), run_serial_transfer(&mut serial, &mut partner));
+    assert_eq!(0xFF, serial.data());
+    assert_eq!(0x7F, serial.control());
+}
+
+#[test]
+fn serial_sink_test() {
+    let mut serial = Serial::default();
+    let mut partner = Serial::default();
+    serial.set_sink(Some(SerialSink::Buffer(Vec::new())));
+
+    for byte in b"Passed" {
+        serial.set_data(*byte);
+        serial.set_control(0x81);
+        assert_eq!((true, false), run_serial_transfer(&mut serial, &mut partner));
+        assert_eq!(0xFF, serial.data());
+    }
+
+    // external clock transfers never complete without a partner
+    serial.set_data(b'!');
+    serial.set_control(0x80);
+    assert_eq!((false, false), run_serial_transfer(&mut serial, &mut partner));
+
+    assert_eq!(b"Passed", serial.sink_buffer());
+}
+
+#[test]
+fn serial_link_cable_test() {
+    let (link1, link2) = link::CableLink::pair();
+
+    let mut master = Serial::default();
+    master.connect(Box::new(link1));
+    let mut slave = Serial::default();
+    slave.connect(Box::new(link2));
+
+    master.set_data(0x12);
+    slave.set_data(0x34);
+    slave.set_control(0x80);
+    master.set_control(0x81);
+
+    assert_eq!((true, true), run_serial_transfer(&mut master, &mut slave));
+    assert_eq!(0x34, master.data());
+    assert_eq!(0x12, slave.data());
+    assert_eq!(0x7E, slave.control());
+}
+
+#[test]
+fn serial_loopback_test() {
+    let mut serial = Serial::default();
+    serial.connect(Box::new(link::LoopbackLink::default()));
+    let mut partner = Serial::default();
+
+    serial.set_data(0xA5);
+    serial.set_control(0x81);
+
+    assert_eq!((true, false), run_serial_transfer(&mut serial, &mut partner));
+    assert_eq!(0xA5, serial.data());
+}
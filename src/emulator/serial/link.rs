@@ -0,0 +1,356 @@
+use std::collections::VecDeque;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::Path;
+
+const MESSAGE_TRANSFER: u8 = 0x01;
+const MESSAGE_REPLY: u8 = 0x02;
+
+/// Byte exchanged through the link cable
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LinkMessage {
+    /// Byte shifted out by a side driving the clock (internal clock)
+    Transfer(u8),
+
+    /// Byte shifted back by the side following the clock (external clock)
+    Reply(u8),
+}
+
+impl LinkMessage {
+    fn to_bytes(self) -> [u8; 2] {
+        match self {
+            LinkMessage::Transfer(data) => [MESSAGE_TRANSFER, data],
+            LinkMessage::Reply(data) => [MESSAGE_REPLY, data],
+        }
+    }
+
+    fn from_bytes(bytes: [u8; 2]) -> Option<Self> {
+        match bytes[0] {
+            MESSAGE_TRANSFER => Some(LinkMessage::Transfer(bytes[1])),
+            MESSAGE_REPLY => Some(LinkMessage::Reply(bytes[1])),
+            _ => None,
+        }
+    }
+}
+
+/// Link Port
+///
+/// Connects the serial port to a partner. Both operations must not block,
+/// the serial port polls for messages on every step.
+pub trait LinkPort {
+    fn send(&mut self, message: LinkMessage);
+
+    fn recv(&mut self) -> Option<LinkMessage>;
+}
+
+/// Cable with the output plugged back into the input
+#[derive(Default)]
+pub struct LoopbackLink {
+    inbox: VecDeque<LinkMessage>,
+}
+
+impl LinkPort for LoopbackLink {
+    fn send(&mut self, message: LinkMessage) {
+        if let LinkMessage::Transfer(data) = message {
+            self.inbox.push_back(LinkMessage::Reply(data));
+        }
+    }
+
+    fn recv(&mut self) -> Option<LinkMessage> {
+        self.inbox.pop_front()
+    }
+}
+
+/// In-process cable between two emulators, possibly running on different threads
+pub struct CableLink {
+    sender: Sender<LinkMessage>,
+    receiver: Receiver<LinkMessage>,
+}
+
+impl CableLink {
+    pub fn pair() -> (CableLink, CableLink) {
+        let (sender_a, receiver_b) = channel();
+        let (sender_b, receiver_a) = channel();
+        (
+            CableLink { sender: sender_a, receiver: receiver_a },
+            CableLink { sender: sender_b, receiver: receiver_b },
+        )
+    }
+
+    /// Wait for the partner to connect in the background, the cable is
+    /// unplugged until then
+    pub fn listen<A: ToSocketAddrs>(addr: A) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        Ok(Self::relay(move || {
+            let (stream, _) = listener.accept()?;
+            stream.set_nodelay(true)?;
+            Ok((stream.try_clone()?, stream))
+        }))
+    }
+
+    /// Wait for the partner to connect in the background, the cable is
+    /// unplugged until then
+    #[cfg(unix)]
+    pub fn listen_unix<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let listener = UnixListener::bind(path)?;
+        Ok(Self::relay(move || {
+            let (stream, _) = listener.accept()?;
+            Ok((stream.try_clone()?, stream))
+        }))
+    }
+
+    // Cable whose other end is relayed over the blocking stream `open`
+    // returns, read and written on threads of their own
+    fn relay<R, W, F>(open: F) -> Self
+    where
+        R: Read + Send + 'static,
+        W: Write + Send + 'static,
+        F: FnOnce() -> std::io::Result<(R, W)> + Send + 'static,
+    {
+        let (cable, partner) = Self::pair();
+        thread::spawn(move || {
+            let (mut reader, mut writer) = match open() {
+                Ok(stream) => stream,
+                Err(error) => {
+                    println!("Serial link connection failed: {}", error);
+                    return;
+                }
+            };
+            println!("Serial link connected");
+
+            // Bytes shifted out while unplugged went nowhere
+            let CableLink { sender, receiver } = partner;
+            while receiver.try_recv().is_ok() {}
+
+            thread::spawn(move || {
+                for message in receiver {
+                    if let Err(error) = writer.write_all(&message.to_bytes()) {
+                        println!("Serial link send failed: {}", error);
+                        break;
+                    }
+                }
+            });
+
+            let mut bytes = [0u8; 2];
+            while reader.read_exact(&mut bytes).is_ok() {
+                if let Some(message) = LinkMessage::from_bytes(bytes) {
+                    if sender.send(message).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+        cable
+    }
+}
+
+impl LinkPort for CableLink {
+    fn send(&mut self, message: LinkMessage) {
+        // A disconnected partner behaves like an unplugged cable
+        let _ = self.sender.send(message);
+    }
+
+    fn recv(&mut self) -> Option<LinkMessage> {
+        self.receiver.try_recv().ok()
+    }
+}
+
+/// Cable over a byte stream (TCP or Unix socket), two bytes per message
+pub struct StreamLink<S: Read + Write> {
+    stream: S,
+    pending: Vec<u8>,
+
+    // Bytes the non-blocking stream didn't take yet, sent before any other
+    unsent: VecDeque<u8>,
+}
+
+impl<S: Read + Write> StreamLink<S> {
+    /// Wrap an already connected, non-blocking stream
+    pub fn from_stream(stream: S) -> Self {
+        Self {
+            stream,
+            pending: Vec::new(),
+            unsent: VecDeque::new(),
+        }
+    }
+
+    // Write as much of the unsent bytes as the stream takes without blocking
+    fn flush_unsent(&mut self) {
+        while !self.unsent.is_empty() {
+            let (bytes, _) = self.unsent.as_slices();
+            match self.stream.write(bytes) {
+                Ok(0) => break,
+                Ok(size) => {
+                    self.unsent.drain(..size);
+                }
+                Err(ref error) if error.kind() == ErrorKind::WouldBlock => break,
+                Err(ref error) if error.kind() == ErrorKind::Interrupted => continue,
+                Err(error) => {
+                    println!("Serial link send failed: {}", error);
+                    self.unsent.clear();
+                }
+            }
+        }
+    }
+}
+
+impl StreamLink<TcpStream> {
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> std::io::Result<Self> {
+        Self::from_tcp_stream(TcpStream::connect(addr)?)
+    }
+
+    pub fn from_tcp_stream(stream: TcpStream) -> std::io::Result<Self> {
+        stream.set_nodelay(true)?;
+        stream.set_nonblocking(true)?;
+        Ok(Self::from_stream(stream))
+    }
+}
+
+#[cfg(unix)]
+impl StreamLink<UnixStream> {
+    pub fn connect_unix<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let stream = UnixStream::connect(path)?;
+        stream.set_nonblocking(true)?;
+        Ok(Self::from_stream(stream))
+    }
+}
+
+impl<S: Read + Write> LinkPort for StreamLink<S> {
+    fn send(&mut self, message: LinkMessage) {
+        self.unsent.extend(message.to_bytes().iter());
+        self.flush_unsent();
+    }
+
+    fn recv(&mut self) -> Option<LinkMessage> {
+        self.flush_unsent();
+
+        let mut buffer = [0u8; 64];
+        loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(size) => self.pending.extend_from_slice(&buffer[..size]),
+                Err(ref error) if error.kind() == ErrorKind::WouldBlock => break,
+                Err(ref error) if error.kind() == ErrorKind::Interrupted => continue,
+                Err(error) => {
+                    println!("Serial link recv failed: {}", error);
+                    break;
+                }
+            }
+        }
+
+        if self.pending.len() < 2 {
+            return None;
+        }
+
+        let bytes = [self.pending[0], self.pending[1]];
+        self.pending.drain(..2);
+        LinkMessage::from_bytes(bytes)
+    }
+}
+
+#[test]
+fn link_loopback_test() {
+    let mut link = LoopbackLink::default();
+    link.send(LinkMessage::Transfer(0x5A));
+    assert_eq!(Some(LinkMessage::Reply(0x5A)), link.recv());
+    assert_eq!(None, link.recv());
+}
+
+// Stream taking at most `capacity` bytes before blocking
+#[cfg(test)]
+struct ChokedStream {
+    written: Vec<u8>,
+    capacity: usize,
+}
+
+#[cfg(test)]
+impl Read for ChokedStream {
+    fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+        Err(ErrorKind::WouldBlock.into())
+    }
+}
+
+#[cfg(test)]
+impl Write for ChokedStream {
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+        let size = bytes.len().min(self.capacity - self.written.len());
+        if size == 0 {
+            return Err(ErrorKind::WouldBlock.into());
+        }
+        self.written.extend_from_slice(&bytes[..size]);
+        Ok(size)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn link_stream_partial_write_test() {
+    let mut link = StreamLink::from_stream(ChokedStream { written: Vec::new(), capacity: 3 });
+    link.send(LinkMessage::Transfer(0x12));
+    link.send(LinkMessage::Reply(0x34));
+    link.send(LinkMessage::Transfer(0x56));
+    assert_eq!(vec![MESSAGE_TRANSFER, 0x12, MESSAGE_REPLY], link.stream.written);
+
+    // the rest goes out in order once the stream drains
+    link.stream.capacity = 6;
+    assert_eq!(None, link.recv());
+    assert_eq!(vec![MESSAGE_TRANSFER, 0x12, MESSAGE_REPLY, 0x34, MESSAGE_TRANSFER, 0x56], link.stream.written);
+}
+
+#[test]
+fn link_tcp_stream_test() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let mut client = StreamLink::connect(addr).unwrap();
+    let mut server = StreamLink::from_tcp_stream(listener.accept().unwrap().0).unwrap();
+
+    client.send(LinkMessage::Transfer(0x12));
+    let message = loop {
+        if let Some(message) = server.recv() {
+            break message;
+        }
+    };
+    assert_eq!(LinkMessage::Transfer(0x12), message);
+
+    server.send(LinkMessage::Reply(0x34));
+    let message = loop {
+        if let Some(message) = client.recv() {
+            break message;
+        }
+    };
+    assert_eq!(LinkMessage::Reply(0x34), message);
+}
+
+#[test]
+fn link_cable_relay_test() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut cable = CableLink::relay(move || {
+        let (stream, _) = listener.accept()?;
+        Ok((stream.try_clone()?, stream))
+    });
+
+    let mut partner = TcpStream::connect(addr).unwrap();
+    partner.write_all(&[MESSAGE_TRANSFER, 0x12]).unwrap();
+    let message = loop {
+        if let Some(message) = cable.recv() {
+            break message;
+        }
+    };
+    assert_eq!(LinkMessage::Transfer(0x12), message);
+
+    cable.send(LinkMessage::Reply(0x34));
+    let mut bytes = [0u8; 2];
+    partner.read_exact(&mut bytes).unwrap();
+    assert_eq!([MESSAGE_REPLY, 0x34], bytes);
+}
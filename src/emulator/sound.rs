@@ -1,9 +1,37 @@
+pub mod blip;
 pub mod flags;
+pub mod recorder;
 
+use blip::BlipBuffer;
+use blip::Quality;
 use flags::*;
+use recorder::Recorder;
+
+use crate::emulator::engine::TICKS_PER_SECOND;
+use crate::emulator::state::{Snapshot, StateReader, StateWriter};
 
 use sdl2::audio::AudioQueue;
 
+pub const DEFAULT_SAMPLE_RATE: u32 = 44_100;
+
+// Frame Sequencer (512Hz) clocks length (256Hz), sweep (128Hz) and envelope (64Hz)
+const FRAME_SEQUENCER_TICKS: u64 = TICKS_PER_SECOND / 512;
+
+// Output level of one channel at full volume (±15) and full master volume
+const SAMPLE_SCALE: f32 = 512.0;
+
+// Limit of audio frames waiting in a queue, anything above is dropped
+const MAX_QUEUED_FRAMES: u32 = 4;
+
+const DUTY_PATTERNS: [u8; 4] = [
+    0b0000_0001, // 12.5%
+    0b1000_0001, // 25%
+    0b1000_0111, // 50%
+    0b0111_1110, // 75%
+];
+
+const NOISE_DIVISORS: [u64; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
 fn set_low_frequency_param(fparam: u32, low: u32) -> u32 {
     (fparam & 0x700) | (low & 0x0FF)
 }
@@ -16,64 +44,6 @@ fn calculate_frequency(f: u32) -> u32 {
     131_072 / (2048 - f)
 }
 
-fn calculate_phase_duty(d: u8) -> f32 {
-    match d {
-        0 => 0.125,
-        1 => 0.25,
-        2 => 0.5,
-        3 => 0.75,
-        _ => 0.5
-    }
-}
-
-fn calculate_volume(v: u8) -> i8 {
-    let v: f32 = v as f32;
-    let coef: f32 = 1.0 / 15.0;
-    let maxv: f32 = 127.0;
-    (v * coef * maxv) as i8
-}
-
-fn calculate_sample(s: u8) -> i8 {
-    /*
-    F |               **
-    E |              *  *
-    D |             *    *
-    C |            *      *
-    B |           *        *
-    A |          *          *
-    9 |         *            *
-    8 |        *              *
-    7 |       *                *
-    6 |      *                  *
-    5 |     *                    *
-    4 |    *                      *
-    3 |   *                        *
-    2 |  *                          *
-    1 | *                            *
-    0 |*                              *
-    *  --------------------------------
-    */
-    match s {
-        0 => -127,
-        1 => -109,
-        2 => -90,
-        3 => -72,
-        4 => -54,
-        5 => -36,
-        6 => -18,
-        7 => 0,
-        8 => 0,
-        9 => 18,
-        10 => 36,
-        11 => 54,
-        12 => 72,
-        13 => 90,
-        14 => 109,
-        15 => 127,
-        _ => panic!(),
-    }
-}
-
 /*
     Name Addr 7654 3210 Function
     -----------------------------------------------------------------
@@ -121,41 +91,86 @@ fn calculate_sample(s: u8) -> i8 {
         FF3F 0000 1111 Samples 30 and 31
 */
 
+fn new_blip_buffer() -> BlipBuffer {
+    BlipBuffer::new(TICKS_PER_SECOND, DEFAULT_SAMPLE_RATE, Quality::default())
+}
+
+// Volume Envelope (NRx2) shared by square and noise channels
+#[derive(Default)]
+struct Envelope {
+    start_volume: u8,
+    direction: bool,
+    sweep_number: u8,
+
+    volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    // DAC is powered while any of the upper 5 bits of NRx2 is set
+    fn dac_enable(&self) -> bool {
+        self.start_volume != 0 || self.direction
+    }
+
+    fn bits(&self) -> u8 {
+        self.start_volume << 4 | (self.direction as u8) << 3 | self.sweep_number
+    }
+
+    fn trigger(&mut self) {
+        self.volume = self.start_volume;
+        self.timer = self.sweep_number;
+    }
+
+    fn clock(&mut self) {
+        if self.sweep_number == 0 {
+            return;
+        }
+
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+
+        if self.timer == 0 {
+            self.timer = self.sweep_number;
+            if self.direction && self.volume < 0xF {
+                self.volume += 1;
+            } else if !self.direction && self.volume > 0 {
+                self.volume -= 1;
+            }
+        }
+    }
+}
+
 // NR10 FF10 -PPP NSSS Sweep period, negate, shift
 // NR11 FF11 DDLL LLLL Duty, Length load (64-L)
 // NR12 FF12 VVVV APPP Starting volume, Envelope add mode, period
 // NR13 FF13 FFFF FFFF Frequency LSB
 // NR14 FF14 TL-- -FFF Trigger, Length enable, Frequency MSB
-#[allow(dead_code)]
 pub struct SquareChannel {
     left_enable: bool,
     right_enable: bool,
 
     playing: bool,
-    restart: bool,
-    repeat: bool,
+    dac_enable: bool,
+    length_enable: bool,
+    length_counter: u16,
 
-    frequency: u32,
     fparam: u32,
 
-    envelope_direction: bool,
-    envelope_start_volume: u8,
-    envelope_sweep_number: u8,
-
+    envelope: Envelope,
 
     sweep_inverse: bool,
     sweep_period: u8,
     sweep_shift: u8,
+    sweep_enable: bool,
+    sweep_timer: u8,
+    sweep_shadow: u32,
 
     wave_duty: u8,
-    wave_length: u8,
-
-    buffer: Box<[i8; 8192]>,
-    phase_duty: f32,
-    phase_pos: f32,
-    step_counter: f32,
-    volume_step: u8,
-    volume: i8,
+    duty_step: u8,
+
+    timer: u64,
+    blip: BlipBuffer,
 }
 
 impl Default for SquareChannel {
@@ -165,52 +180,248 @@ impl Default for SquareChannel {
             right_enable: false,
 
             playing: false,
-            restart: false,
-            repeat: false,
-            frequency: calculate_frequency(0),
-            fparam: 0,
+            dac_enable: false,
+            length_enable: false,
+            length_counter: 0,
 
-            envelope_start_volume: 0,
-            envelope_sweep_number: 0,
-            envelope_direction: true,
+            fparam: 0,
 
+            envelope: Envelope::default(),
 
             sweep_inverse: false,
             sweep_period: 0,
             sweep_shift: 0,
+            sweep_enable: false,
+            sweep_timer: 0,
+            sweep_shadow: 0,
+
             wave_duty: 0,
-            wave_length: 0,
+            duty_step: 0,
 
-            buffer: Box::new([0; 8192]),
-            phase_duty: 0.5,
-            phase_pos: 0.0,
-            step_counter: 0.0,
-            volume_step: 0,
-            volume: 0,
+            timer: 0,
+            blip: new_blip_buffer(),
         }
     }
 }
 
+impl SquareChannel {
+    fn period(&self) -> u64 {
+        (2048 - self.fparam as u64) * 4
+    }
+
+    fn amplitude(&self) -> i32 {
+        if !self.playing {
+            return 0;
+        }
+
+        let volume = self.envelope.volume as i32;
+        if DUTY_PATTERNS[self.wave_duty as usize] & (0x80 >> self.duty_step) != 0 {
+            volume
+        } else {
+            -volume
+        }
+    }
+
+    fn trigger(&mut self, time: u64) {
+        self.playing = self.dac_enable;
+        if self.length_counter == 0 {
+            self.length_counter = 64;
+        }
+
+        self.timer = self.period();
+        self.envelope.trigger();
+
+        self.sweep_shadow = self.fparam;
+        self.sweep_timer = if self.sweep_period == 0 { 8 } else { self.sweep_period };
+        self.sweep_enable = self.sweep_period != 0 || self.sweep_shift != 0;
+        if self.sweep_shift != 0 {
+            self.sweep_calculate();
+        }
+
+        self.blip.set_amplitude(time, self.amplitude());
+    }
+
+    // Next sweep frequency, disables the channel on overflow
+    fn sweep_calculate(&mut self) -> u32 {
+        let delta = self.sweep_shadow >> self.sweep_shift;
+        let fparam = if self.sweep_inverse {
+            self.sweep_shadow - delta
+        } else {
+            self.sweep_shadow + delta
+        };
+
+        if fparam > 2047 {
+            self.playing = false;
+        }
+        fparam
+    }
+
+    fn clock_sweep(&mut self, time: u64) {
+        if self.sweep_timer > 0 {
+            self.sweep_timer -= 1;
+        }
+
+        if self.sweep_timer == 0 {
+            self.sweep_timer = if self.sweep_period == 0 { 8 } else { self.sweep_period };
+
+            if self.sweep_enable && self.sweep_period != 0 {
+                let fparam = self.sweep_calculate();
+                if fparam <= 2047 && self.sweep_shift != 0 {
+                    self.fparam = fparam;
+                    self.sweep_shadow = fparam;
+                    self.sweep_calculate();
+                }
+            }
+        }
+
+        self.blip.set_amplitude(time, self.amplitude());
+    }
+
+    fn clock_length(&mut self, time: u64) {
+        if self.length_enable && self.length_counter > 0 {
+            self.length_counter -= 1;
+            if self.length_counter == 0 {
+                self.playing = false;
+            }
+        }
+
+        self.blip.set_amplitude(time, self.amplitude());
+    }
+
+    fn clock_envelope(&mut self, time: u64) {
+        self.envelope.clock();
+        self.blip.set_amplitude(time, self.amplitude());
+    }
+
+    fn power_off(&mut self, time: u64) {
+        self.left_enable = false;
+        self.right_enable = false;
+
+        self.playing = false;
+        self.dac_enable = false;
+        self.length_enable = false;
+
+        self.fparam = 0;
+        self.envelope = Envelope::default();
+
+        self.sweep_inverse = false;
+        self.sweep_period = 0;
+        self.sweep_shift = 0;
+        self.sweep_enable = false;
+        self.sweep_timer = 0;
+        self.sweep_shadow = 0;
+
+        self.wave_duty = 0;
+        self.duty_step = 0;
+
+        self.blip.set_amplitude(time, 0);
+    }
+}
+
 //         Wave
 // NR30 FF1A E--- ---- DAC power
 // NR31 FF1B LLLL LLLL Length load (256-L)
 // NR32 FF1C -VV- ---- Volume code (00=0%, 01=100%, 10=50%, 11=25%)
 // NR33 FF1D FFFF FFFF Frequency LSB
 // NR34 FF1E TL-- -FFF Trigger, Length enable, Frequency MSB
-#[allow(dead_code)]
-#[derive(Default)]
 pub struct WaveChannel {
     left_enable: bool,
     right_enable: bool,
 
     playing: bool,
-    restart: bool,
-    repeat: bool,
-    frequency: u16,
+    dac_enable: bool,
+    length_enable: bool,
+    length_counter: u16,
+
+    fparam: u32,
 
-    wave_length_load: u8,
     wave_volume: u8,
-    wave_samples: [i8; 32],
+    wave_position: usize,
+    wave_ram: [u8; 16],
+
+    timer: u64,
+    blip: BlipBuffer,
+}
+
+impl Default for WaveChannel {
+    fn default() -> Self {
+        Self {
+            left_enable: false,
+            right_enable: false,
+
+            playing: false,
+            dac_enable: false,
+            length_enable: false,
+            length_counter: 0,
+
+            fparam: 0,
+
+            wave_volume: 0,
+            wave_position: 0,
+            wave_ram: [0; 16],
+
+            timer: 0,
+            blip: new_blip_buffer(),
+        }
+    }
+}
+
+impl WaveChannel {
+    fn period(&self) -> u64 {
+        (2048 - self.fparam as u64) * 2
+    }
+
+    fn amplitude(&self) -> i32 {
+        if !self.playing || self.wave_volume == 0 {
+            return 0;
+        }
+
+        let data = self.wave_ram[self.wave_position / 2];
+        let sample = if self.wave_position & 1 == 0 { data >> 4 } else { data & 0xF };
+
+        // 100%, 50% and 25% volume, centered around zero
+        let shift = self.wave_volume - 1;
+        ((sample >> shift) as i32) * 2 - (0xF >> shift)
+    }
+
+    fn trigger(&mut self, time: u64) {
+        self.playing = self.dac_enable;
+        if self.length_counter == 0 {
+            self.length_counter = 256;
+        }
+
+        self.timer = self.period();
+        self.wave_position = 0;
+
+        self.blip.set_amplitude(time, self.amplitude());
+    }
+
+    fn clock_length(&mut self, time: u64) {
+        if self.length_enable && self.length_counter > 0 {
+            self.length_counter -= 1;
+            if self.length_counter == 0 {
+                self.playing = false;
+            }
+        }
+
+        self.blip.set_amplitude(time, self.amplitude());
+    }
+
+    fn power_off(&mut self, time: u64) {
+        self.left_enable = false;
+        self.right_enable = false;
+
+        self.playing = false;
+        self.dac_enable = false;
+        self.length_enable = false;
+
+        self.fparam = 0;
+
+        self.wave_volume = 0;
+        self.wave_position = 0;
+
+        self.blip.set_amplitude(time, 0);
+    }
 }
 
 //         Noise
@@ -219,382 +430,721 @@ pub struct WaveChannel {
 // NR42 FF21 VVVV APPP Starting volume, Envelope add mode, period
 // NR43 FF22 SSSS WDDD Clock shift, Width mode of LFSR, Divisor code
 // NR44 FF23 TL-- ---- Trigger, Length enable
-#[allow(dead_code)]
-#[derive(Default)]
 pub struct NoiseChannel {
     left_enable: bool,
     right_enable: bool,
 
     playing: bool,
-    repeat: bool,
+    dac_enable: bool,
+    length_enable: bool,
+    length_counter: u16,
 
-    envelope_add_mode: bool,
-    envelope_start_volume: u8,
-    envelope_sweep_number: u8,
+    envelope: Envelope,
 
     clock_shift: u8,
-    clock_width_mode: u8,
+    clock_width_mode: bool,
     clock_divisor_code: u8,
+
+    lfsr: u16,
+
+    timer: u64,
+    blip: BlipBuffer,
 }
 
-#[allow(dead_code)]
-#[derive(Default)]
-pub struct Sounder {
-    enable: bool,
+impl Default for NoiseChannel {
+    fn default() -> Self {
+        Self {
+            left_enable: false,
+            right_enable: false,
 
-    // SO2
-    left_volume: u8,
+            playing: false,
+            dac_enable: false,
+            length_enable: false,
+            length_counter: 0,
 
-    // SO1
-    right_volume: u8,
+            envelope: Envelope::default(),
 
-    // TONE & SWEEP
-    channel1: SquareChannel,
+            clock_shift: 0,
+            clock_width_mode: false,
+            clock_divisor_code: 0,
 
-    // TONE
-    channel2: SquareChannel,
+            lfsr: 0x7FFF,
 
-    // WAVE
-    channel3: WaveChannel,
+            timer: 0,
+            blip: new_blip_buffer(),
+        }
+    }
+}
 
-    // NOISE
-    channel4: NoiseChannel,
+impl NoiseChannel {
+    fn period(&self) -> u64 {
+        NOISE_DIVISORS[self.clock_divisor_code as usize] << self.clock_shift
+    }
+
+    fn amplitude(&self) -> i32 {
+        if !self.playing {
+            return 0;
+        }
+
+        let volume = self.envelope.volume as i32;
+        if self.lfsr & 1 == 0 { volume } else { -volume }
+    }
+
+    fn trigger(&mut self, time: u64) {
+        self.playing = self.dac_enable;
+        if self.length_counter == 0 {
+            self.length_counter = 64;
+        }
+
+        self.timer = self.period();
+        self.lfsr = 0x7FFF;
+        self.envelope.trigger();
+
+        self.blip.set_amplitude(time, self.amplitude());
+    }
+
+    fn clock_length(&mut self, time: u64) {
+        if self.length_enable && self.length_counter > 0 {
+            self.length_counter -= 1;
+            if self.length_counter == 0 {
+                self.playing = false;
+            }
+        }
+
+        self.blip.set_amplitude(time, self.amplitude());
+    }
+
+    fn clock_envelope(&mut self, time: u64) {
+        self.envelope.clock();
+        self.blip.set_amplitude(time, self.amplitude());
+    }
+
+    fn power_off(&mut self, time: u64) {
+        self.left_enable = false;
+        self.right_enable = false;
+
+        self.playing = false;
+        self.dac_enable = false;
+        self.length_enable = false;
+
+        self.envelope = Envelope::default();
+
+        self.clock_shift = 0;
+        self.clock_width_mode = false;
+        self.clock_divisor_code = 0;
+
+        self.blip.set_amplitude(time, 0);
+    }
 }
 
 pub trait SampleGenerator {
-    fn enqueue_audio_samples(&mut self, queue: &mut AudioQueue<i8>);
+    // Run the channel for `ticks`, starting `time` ticks after the frame begin
+    fn step(&mut self, time: u64, ticks: u64);
+
+    // Band-limited channel output
+    fn blip(&mut self) -> &mut BlipBuffer;
+
+    // Output terminals the channel is routed to (left, right)
+    fn terminals(&self) -> (bool, bool);
 }
 
 impl SampleGenerator for SquareChannel {
-    fn enqueue_audio_samples(&mut self, queue: &mut AudioQueue<i8>) {
-        if self.restart {
-            self.restart = false;
-            self.playing = true;
-            self.phase_pos = 0.0;
-            queue.clear();
-        }
-
+    fn step(&mut self, time: u64, ticks: u64) {
         if !self.playing {
             return;
         }
 
-        if !self.left_enable && !self.right_enable {
-            return;
+        let mut time = time;
+        let mut ticks = ticks;
+        while self.timer <= ticks {
+            time += self.timer;
+            ticks -= self.timer;
+
+            self.timer = self.period();
+            self.duty_step = (self.duty_step + 1) % 8;
+            self.blip.set_amplitude(time, self.amplitude());
         }
+        self.timer -= ticks;
+    }
 
-        let phase_inc = self.frequency as f32 / queue.spec().freq as f32;
-        let step_size = self.envelope_sweep_number as f32 * (queue.spec().freq as f32 / 64.0);
-
-        let length = self.buffer.len();
-        if (queue.size() as usize) < length {
-            let length = length / 2;
-            for i in 0..length {
-                // envelope
-                if step_size > 0.0 {
-                    if self.step_counter >= step_size {
-                        self.step_counter -= step_size;
-                        if self.envelope_direction {
-                            if self.volume_step < 0xF {
-                                self.volume_step += 1;
-                            }
-                        } else {
-                            if self.volume_step >= 0x1 {
-                                self.volume_step -= 1;
-                            }
-                        }
-                        self.volume = calculate_volume(self.volume_step);
-                        if self.volume_step == 0 {
-                            self.playing = false;
-                        }
-                    }
-                    self.step_counter += 1.0;
-                }
+    fn blip(&mut self) -> &mut BlipBuffer {
+        &mut self.blip
+    }
+
+    fn terminals(&self) -> (bool, bool) {
+        (self.left_enable, self.right_enable)
+    }
+}
 
-                let sample = self.volume * if self.phase_pos < self.phase_duty { 1 } else { -1 };
+impl SampleGenerator for NoiseChannel {
+    fn step(&mut self, time: u64, ticks: u64) {
+        // Shift clock 14 and 15 stop the LFSR
+        if !self.playing || self.clock_shift >= 14 {
+            return;
+        }
 
-                self.phase_pos += phase_inc;
-                self.phase_pos %= 1.0;
+        let mut time = time;
+        let mut ticks = ticks;
+        while self.timer <= ticks {
+            time += self.timer;
+            ticks -= self.timer;
 
-                // left
-                self.buffer[i * 2] = if self.left_enable { sample } else { 0 };
+            self.timer = self.period();
 
-                // right
-                self.buffer[i * 2 + 1] = if self.right_enable { sample } else { 0 };
+            let xor = (self.lfsr ^ (self.lfsr >> 1)) & 1;
+            self.lfsr = (self.lfsr >> 1) | (xor << 14);
+            if self.clock_width_mode {
+                self.lfsr = (self.lfsr & !(1 << 6)) | (xor << 6);
             }
-            queue.queue(&*self.buffer);
+            self.blip.set_amplitude(time, self.amplitude());
         }
+        self.timer -= ticks;
     }
-}
 
-impl SampleGenerator for NoiseChannel {
-    fn enqueue_audio_samples(&mut self, _queue: &mut AudioQueue<i8>) {
+    fn blip(&mut self) -> &mut BlipBuffer {
+        &mut self.blip
+    }
 
+    fn terminals(&self) -> (bool, bool) {
+        (self.left_enable, self.right_enable)
     }
 }
 
 impl SampleGenerator for WaveChannel {
-    fn enqueue_audio_samples(&mut self, _queue: &mut AudioQueue<i8>) {
+    fn step(&mut self, time: u64, ticks: u64) {
+        if !self.playing {
+            return;
+        }
+
+        let mut time = time;
+        let mut ticks = ticks;
+        while self.timer <= ticks {
+            time += self.timer;
+            ticks -= self.timer;
+
+            self.timer = self.period();
+            self.wave_position = (self.wave_position + 1) % 32;
+            self.blip.set_amplitude(time, self.amplitude());
+        }
+        self.timer -= ticks;
+    }
+
+    fn blip(&mut self) -> &mut BlipBuffer {
+        &mut self.blip
+    }
 
+    fn terminals(&self) -> (bool, bool) {
+        (self.left_enable, self.right_enable)
     }
 }
 
+#[derive(Default)]
+pub struct Sounder {
+    enable: bool,
+
+    // Vin to SO2 / SO1
+    left_vin_enable: bool,
+    right_vin_enable: bool,
+
+    // SO2
+    left_volume: u8,
+
+    // SO1
+    right_volume: u8,
+
+    // TONE & SWEEP
+    channel1: SquareChannel,
+
+    // TONE
+    channel2: SquareChannel,
+
+    // WAVE
+    channel3: WaveChannel,
+
+    // NOISE
+    channel4: NoiseChannel,
+
+    // Ticks elapsed since the begin of the current frame
+    clock: u64,
+
+    sequencer_ticks: u64,
+    sequencer_step: u8,
+
+    // Interleaved stereo samples of each channel produced by the last frame
+    samples: [Vec<i16>; 4],
+
+    recorder: Option<Recorder>,
+
+    // Soundtrack of the video recording, runs apart from `recorder`
+    video_recorder: Option<Recorder>,
+
+    // Frames are produced silent and left out of the recording
+    muted: bool,
+
+    // Register writes aren't logged, headless runs keep stdout for their report
+    quiet: bool,
+}
+
 impl Sounder {
     pub fn channel1_r0(&self) -> u8 {
-        0
+        let mut r = Channel1SweepControl::UNUSED_BIT7;
+        r.set(Channel1SweepControl::SWEEP_DIRECTION_SELECT, self.channel1.sweep_inverse);
+        r.bits() | self.channel1.sweep_period << 4 | self.channel1.sweep_shift
     }
 
     pub fn set_channel1_r0(&mut self, data: u8) {
+        if !self.enable {
+            return;
+        }
+
         let r = Channel1SweepControl::from_bits(data).unwrap();
         self.channel1.sweep_inverse = r.contains(Channel1SweepControl::SWEEP_DIRECTION_SELECT);
         self.channel1.sweep_period = (r & Channel1SweepControl::SWEEP_PERIOD_MASK).bits() >> 4;
         self.channel1.sweep_shift = (r & Channel1SweepControl::SWEEP_SHIFT_MASK).bits();
-        println!("NR10 ch1_sweep_inv={} ch1_sweep_period={} ch1_sweep_shift={}",
-            self.channel1.sweep_inverse,
-            self.channel1.sweep_period,
-            self.channel1.sweep_shift);
+        if !self.quiet {
+            println!("NR10 ch1_sweep_inv={} ch1_sweep_period={} ch1_sweep_shift={}",
+                self.channel1.sweep_inverse,
+                self.channel1.sweep_period,
+                self.channel1.sweep_shift);
+        }
     }
 
     pub fn channel1_r1(&self) -> u8 {
-        0
+        Channel1SequenceControl::SOUND_SEQUENCE_LENGTH_MASK.bits() | self.channel1.wave_duty << 6
     }
 
     pub fn set_channel1_r1(&mut self, data: u8) {
         let r = Channel1SequenceControl::from_bits(data).unwrap();
-        self.channel1.wave_duty = (r & Channel1SequenceControl::SOUND_SEQUENCE_DUTY_MASK).bits() >> 6;
-        self.channel1.wave_length = (r & Channel1SequenceControl::SOUND_SEQUENCE_LENGTH_MASK).bits();
 
-        self.channel1.phase_duty = calculate_phase_duty(self.channel1.wave_duty);
+        // DMG keeps the length counters writable while powered off
+        self.channel1.length_counter = 64 - (r & Channel1SequenceControl::SOUND_SEQUENCE_LENGTH_MASK).bits() as u16;
+        if !self.enable {
+            return;
+        }
+
+        self.channel1.wave_duty = (r & Channel1SequenceControl::SOUND_SEQUENCE_DUTY_MASK).bits() >> 6;
 
-        println!("NR11 ch1_duty={} ch1_len={} ch1_duty={}",
-            self.channel1.wave_duty,
-            self.channel1.wave_length,
-            self.channel1.phase_duty);
+        if !self.quiet {
+            println!("NR11 ch1_duty={} ch1_len={}",
+                self.channel1.wave_duty,
+                self.channel1.length_counter);
+        }
     }
 
     pub fn channel1_r2(&self) -> u8 {
-        0
+        self.channel1.envelope.bits()
     }
 
     pub fn set_channel1_r2(&mut self, data: u8) {
+        if !self.enable {
+            return;
+        }
+
         let r = Channel1EnvelopeControl::from_bits(data).unwrap();
-        self.channel1.envelope_start_volume = (r & Channel1EnvelopeControl::ENVELOPE_INITIAL_VOLUME_MASK).bits() >> 4;
-        self.channel1.envelope_direction = r.contains(Channel1EnvelopeControl::ENVELOPE_DIRECTION_SELECT);
-        self.channel1.envelope_sweep_number = (r & Channel1EnvelopeControl::ENVELOPE_SWEEP_NUMBER_MASK).bits();
-        self.channel1.volume_step = self.channel1.envelope_start_volume;
-        self.channel1.volume = calculate_volume(self.channel1.volume_step);
+        let envelope = &mut self.channel1.envelope;
+        envelope.start_volume = (r & Channel1EnvelopeControl::ENVELOPE_INITIAL_VOLUME_MASK).bits() >> 4;
+        envelope.direction = r.contains(Channel1EnvelopeControl::ENVELOPE_DIRECTION_SELECT);
+        envelope.sweep_number = (r & Channel1EnvelopeControl::ENVELOPE_SWEEP_NUMBER_MASK).bits();
+
+        self.channel1.dac_enable = envelope.dac_enable();
+        if !self.channel1.dac_enable {
+            self.channel1.playing = false;
+            self.channel1.blip.set_amplitude(self.clock, 0);
+        }
 
-        println!("NR12 ch1_env_start_vol={} ch1_env_dir={} ch1_env_num={} ch1_vol={}",
-            self.channel1.envelope_start_volume,
-            self.channel1.envelope_direction,
-            self.channel1.envelope_sweep_number,
-            self.channel1.volume);
+        if !self.quiet {
+            println!("NR12 ch1_env_start_vol={} ch1_env_dir={} ch1_env_num={}",
+                self.channel1.envelope.start_volume,
+                self.channel1.envelope.direction,
+                self.channel1.envelope.sweep_number);
+        }
     }
 
     pub fn channel1_r3(&self) -> u8 {
-        0
+        // Write Only
+        0xFF
     }
 
     pub fn set_channel1_r3(&mut self, data: u8) {
+        if !self.enable {
+            return;
+        }
+
         self.channel1.fparam = set_low_frequency_param(self.channel1.fparam, data as u32);
-        self.channel1.frequency = calculate_frequency(self.channel1.fparam);
-        println!("NR13 ch1_fparam={} ch1_freq={}", self.channel1.fparam, self.channel1.frequency);
+        if !self.quiet {
+            println!("NR13 ch1_fparam={} ch1_freq={}", self.channel1.fparam, calculate_frequency(self.channel1.fparam));
+        }
     }
 
     pub fn channel1_r4(&self) -> u8 {
-        0
+        let mut r = !Channel1FrequencyHigherData::STOP_ON_SEQUENCE_COMPLETE;
+        r.set(Channel1FrequencyHigherData::STOP_ON_SEQUENCE_COMPLETE, self.channel1.length_enable);
+        r.bits()
     }
 
     pub fn set_channel1_r4(&mut self, data: u8) {
+        if !self.enable {
+            return;
+        }
+
         let r = Channel1FrequencyHigherData::from_bits(data).unwrap();
         self.channel1.fparam = set_high_frequency_param(self.channel1.fparam, data as u32);
-        self.channel1.frequency = calculate_frequency(self.channel1.fparam);
-        self.channel1.repeat = !r.contains(Channel1FrequencyHigherData::STOP_ON_SEQUENCE_COMPLETE);
-        self.channel1.restart = r.contains(Channel1FrequencyHigherData::RESTART_SEQUENCE);
-        println!("NR14 ch1_fparam={} ch1_freq={} ch1_repeat={} ch1_restart={}",
-            self.channel1.fparam,
-            self.channel1.frequency,
-            self.channel1.repeat,
-            self.channel1.restart);
+        self.channel1.length_enable = r.contains(Channel1FrequencyHigherData::STOP_ON_SEQUENCE_COMPLETE);
+        if r.contains(Channel1FrequencyHigherData::RESTART_SEQUENCE) {
+            self.channel1.trigger(self.clock);
+        }
+        if !self.quiet {
+            println!("NR14 ch1_fparam={} ch1_freq={} ch1_len_enable={} ch1_playing={}",
+                self.channel1.fparam,
+                calculate_frequency(self.channel1.fparam),
+                self.channel1.length_enable,
+                self.channel1.playing);
+        }
     }
 
     pub fn channel2_r1(&self) -> u8 {
-        0
+        Channel2SequenceControl::SOUND_SEQUENCE_LENGTH_MASK.bits() | self.channel2.wave_duty << 6
     }
 
     pub fn set_channel2_r1(&mut self, data: u8) {
         let r = Channel2SequenceControl::from_bits(data).unwrap();
 
+        self.channel2.length_counter = 64 - (r & Channel2SequenceControl::SOUND_SEQUENCE_LENGTH_MASK).bits() as u16;
+        if !self.enable {
+            return;
+        }
+
         self.channel2.wave_duty = (r & Channel2SequenceControl::SOUND_SEQUENCE_DUTY_MASK).bits() >> 6;
-        self.channel2.wave_length = (r & Channel2SequenceControl::SOUND_SEQUENCE_LENGTH_MASK).bits();
-        self.channel2.phase_duty = calculate_phase_duty(self.channel2.wave_duty);
 
-        println!("NR21 ch2_duty={} ch2_len={} ch2_duty={}",
-            self.channel2.wave_duty,
-            self.channel2.wave_length,
-            self.channel2.phase_duty);
+        if !self.quiet {
+            println!("NR21 ch2_duty={} ch2_len={}",
+                self.channel2.wave_duty,
+                self.channel2.length_counter);
+        }
     }
 
     pub fn channel2_r2(&self) -> u8 {
-        0
+        self.channel2.envelope.bits()
     }
 
     pub fn set_channel2_r2(&mut self, data: u8) {
-        let r = Channel2EnvelopeControl::from_bits(data).unwrap();
+        if !self.enable {
+            return;
+        }
 
-        self.channel2.envelope_start_volume = (r & Channel2EnvelopeControl::ENVELOPE_INITIAL_VOLUME_MASK).bits() >> 4;
-        self.channel2.envelope_direction = r.contains(Channel2EnvelopeControl::ENVELOPE_DIRECTION_SELECT);
-        self.channel2.envelope_sweep_number = (r & Channel2EnvelopeControl::ENVELOPE_SWEEP_NUMBER_MASK).bits();
-        self.channel2.volume = calculate_volume(self.channel2.envelope_start_volume);
+        let r = Channel2EnvelopeControl::from_bits(data).unwrap();
+        let envelope = &mut self.channel2.envelope;
+        envelope.start_volume = (r & Channel2EnvelopeControl::ENVELOPE_INITIAL_VOLUME_MASK).bits() >> 4;
+        envelope.direction = r.contains(Channel2EnvelopeControl::ENVELOPE_DIRECTION_SELECT);
+        envelope.sweep_number = (r & Channel2EnvelopeControl::ENVELOPE_SWEEP_NUMBER_MASK).bits();
+
+        self.channel2.dac_enable = envelope.dac_enable();
+        if !self.channel2.dac_enable {
+            self.channel2.playing = false;
+            self.channel2.blip.set_amplitude(self.clock, 0);
+        }
 
-        println!("NR22 ch2_env_start_vol={} ch2_env_dir={} ch2_env_num={} ch2_vol={}",
-            self.channel1.envelope_start_volume,
-            self.channel1.envelope_direction,
-            self.channel1.envelope_sweep_number,
-            self.channel1.volume);
+        if !self.quiet {
+            println!("NR22 ch2_env_start_vol={} ch2_env_dir={} ch2_env_num={}",
+                self.channel2.envelope.start_volume,
+                self.channel2.envelope.direction,
+                self.channel2.envelope.sweep_number);
+        }
     }
 
     pub fn channel2_r3(&self) -> u8 {
-        0
+        // Write Only
+        0xFF
     }
 
     pub fn set_channel2_r3(&mut self, data: u8) {
+        if !self.enable {
+            return;
+        }
+
         self.channel2.fparam = set_low_frequency_param(self.channel2.fparam, data as u32);
-        self.channel2.frequency = calculate_frequency(self.channel2.fparam);
 
-        println!("NR23 ch2_fparam={} ch2_freq={}", self.channel2.fparam, self.channel2.frequency);
+        if !self.quiet {
+            println!("NR23 ch2_fparam={} ch2_freq={}", self.channel2.fparam, calculate_frequency(self.channel2.fparam));
+        }
     }
 
     pub fn channel2_r4(&self) -> u8 {
-        0
+        let mut r = !Channel2FrequencyHigherData::STOP_ON_SEQUENCE_COMPLETE;
+        r.set(Channel2FrequencyHigherData::STOP_ON_SEQUENCE_COMPLETE, self.channel2.length_enable);
+        r.bits()
     }
 
     pub fn set_channel2_r4(&mut self, data: u8) {
+        if !self.enable {
+            return;
+        }
+
         let r = Channel2FrequencyHigherData::from_bits(data).unwrap();
 
         self.channel2.fparam = set_high_frequency_param(self.channel2.fparam, data as u32);
-        self.channel2.repeat = !r.contains(Channel2FrequencyHigherData::STOP_ON_SEQUENCE_COMPLETE);
-        self.channel2.restart = r.contains(Channel2FrequencyHigherData::RESTART_SEQUENCE);
+        self.channel2.length_enable = r.contains(Channel2FrequencyHigherData::STOP_ON_SEQUENCE_COMPLETE);
+        if r.contains(Channel2FrequencyHigherData::RESTART_SEQUENCE) {
+            self.channel2.trigger(self.clock);
+        }
 
-        println!("NR24 ch2_fparam={} ch2_freq={} ch2_repeat={} ch2_restart={}",
-            self.channel2.fparam,
-            self.channel2.frequency,
-            self.channel2.repeat,
-            self.channel2.restart);
+        if !self.quiet {
+            println!("NR24 ch2_fparam={} ch2_freq={} ch2_len_enable={} ch2_playing={}",
+                self.channel2.fparam,
+                calculate_frequency(self.channel2.fparam),
+                self.channel2.length_enable,
+                self.channel2.playing);
+        }
     }
 
     pub fn channel3_r0(&self) -> u8 {
-        0
+        let mut r = Channel3SoundOnOffStatus::UNUSED_MASK;
+        r.set(Channel3SoundOnOffStatus::CHANNEL_3_ENABLE, self.channel3.dac_enable);
+        r.bits()
     }
 
     pub fn set_channel3_r0(&mut self, data: u8) {
-        let _r = Channel3SoundOnOffStatus::from_bits(data);
-        println!("NR30 {:?}", _r);
+        if !self.enable {
+            return;
+        }
+
+        let r = Channel3SoundOnOffStatus::from_bits(data).unwrap();
+
+        self.channel3.dac_enable = r.contains(Channel3SoundOnOffStatus::CHANNEL_3_ENABLE);
+        if !self.channel3.dac_enable {
+            self.channel3.playing = false;
+            self.channel3.blip.set_amplitude(self.clock, 0);
+        }
+
+        if !self.quiet {
+            println!("NR30 ch3_dac={}", self.channel3.dac_enable);
+        }
     }
 
     pub fn channel3_r1(&self) -> u8 {
-        0
+        // Write Only
+        0xFF
     }
 
     pub fn set_channel3_r1(&mut self, data: u8) {
-        let _r = Channel3SoundSequenceLength::from_bits(data);
-        println!("NR31 {:?}", _r);
+        self.channel3.length_counter = 256 - data as u16;
+        if !self.enable {
+            return;
+        }
+
+        if !self.quiet {
+            println!("NR31 ch3_len={}", self.channel3.length_counter);
+        }
     }
 
     pub fn channel3_r2(&self) -> u8 {
-        0
+        !Channel3VolumeSelection::VOLUME_MASK.bits() | self.channel3.wave_volume << 5
     }
 
     pub fn set_channel3_r2(&mut self, data: u8) {
-        let _r = Channel3VolumeSelection::from_bits(data);
-        println!("NR32 {:?}", _r);
+        if !self.enable {
+            return;
+        }
+
+        let r = Channel3VolumeSelection::from_bits(data).unwrap();
+        self.channel3.wave_volume = (r & Channel3VolumeSelection::VOLUME_MASK).bits() >> 5;
+        if !self.quiet {
+            println!("NR32 ch3_vol={}", self.channel3.wave_volume);
+        }
     }
 
     pub fn channel3_r3(&self) -> u8 {
-        0
+        // Write Only
+        0xFF
     }
 
     pub fn set_channel3_r3(&mut self, data: u8) {
-        let _r = Channel3FrequencyLowerData::from_bits(data);
-        println!("NR33 {:?}", _r);
+        if !self.enable {
+            return;
+        }
+
+        self.channel3.fparam = set_low_frequency_param(self.channel3.fparam, data as u32);
+        if !self.quiet {
+            println!("NR33 ch3_fparam={}", self.channel3.fparam);
+        }
     }
 
     pub fn channel3_r4(&self) -> u8 {
-        0
+        let mut r = !Channel3FrequencyHigherData::STOP_ON_SEQUENCE_COMPLETE;
+        r.set(Channel3FrequencyHigherData::STOP_ON_SEQUENCE_COMPLETE, self.channel3.length_enable);
+        r.bits()
     }
 
     pub fn set_channel3_r4(&mut self, data: u8) {
-        let _r = Channel3FrequencyHigherData::from_bits(data);
-        println!("NR34 {:?}", _r);
+        if !self.enable {
+            return;
+        }
+
+        let r = Channel3FrequencyHigherData::from_bits(data).unwrap();
+
+        self.channel3.fparam = set_high_frequency_param(self.channel3.fparam, data as u32);
+        self.channel3.length_enable = r.contains(Channel3FrequencyHigherData::STOP_ON_SEQUENCE_COMPLETE);
+        if r.contains(Channel3FrequencyHigherData::RESTART_SEQUENCE) {
+            self.channel3.trigger(self.clock);
+        }
+
+        if !self.quiet {
+            println!("NR34 ch3_fparam={} ch3_len_enable={} ch3_playing={}",
+                self.channel3.fparam,
+                self.channel3.length_enable,
+                self.channel3.playing);
+        }
     }
 
-    pub fn channel3_sample(&self, _index: u8) -> u8 {
-        0
+    pub fn channel3_sample(&self, index: u8) -> u8 {
+        self.channel3.wave_ram[index as usize]
     }
 
     pub fn set_channel3_sample(&mut self, index: u8, data: u8) {
-        let index = index as usize;
-        let sample1 = calculate_sample((data & 0xF0) >> 4);
-        let sample2 = calculate_sample(data &0x0F);
-        println!("CH3 S[{}]={} S[{}]={}", 2 * index, sample1, 2 * index + 1, sample2);
-
-        self.channel3.wave_samples[2 * index] = sample1;
-        self.channel3.wave_samples[2 * index + 1] = sample2;
+        // Wave RAM is not affected by the power control
+        self.channel3.wave_ram[index as usize] = data;
     }
 
     pub fn channel4_r1(&self) -> u8 {
-        0
+        // Write Only
+        0xFF
     }
 
     pub fn set_channel4_r1(&mut self, data: u8) {
-        let _r = Channel4SoundSequenceLength::from_bits(data);
-        println!("NR41 {:?}", _r);
+        let r = Channel4SoundSequenceLength::from_bits(data).unwrap();
+        self.channel4.length_counter = 64 - (r & Channel4SoundSequenceLength::SOUND_SEQUENCE_LENGTH_MASK).bits() as u16;
+        if !self.enable {
+            return;
+        }
+
+        if !self.quiet {
+            println!("NR41 ch4_len={}", self.channel4.length_counter);
+        }
     }
 
     pub fn channel4_r2(&self) -> u8 {
-        0
+        self.channel4.envelope.bits()
     }
 
     pub fn set_channel4_r2(&mut self, data: u8) {
-        let _r = Channel4EnvelopeControl::from_bits(data);
-        println!("NR42 {:?}", _r);
+        if !self.enable {
+            return;
+        }
+
+        let r = Channel4EnvelopeControl::from_bits(data).unwrap();
+        let envelope = &mut self.channel4.envelope;
+        envelope.start_volume = (r & Channel4EnvelopeControl::ENVELOPE_INITIAL_VOLUME_MASK).bits() >> 4;
+        envelope.direction = r.contains(Channel4EnvelopeControl::ENVELOPE_DIRECTION_SELECT);
+        envelope.sweep_number = (r & Channel4EnvelopeControl::ENVELOPE_SWEEP_NUMBER_MASK).bits();
+
+        self.channel4.dac_enable = envelope.dac_enable();
+        if !self.channel4.dac_enable {
+            self.channel4.playing = false;
+            self.channel4.blip.set_amplitude(self.clock, 0);
+        }
+
+        if !self.quiet {
+            println!("NR42 ch4_env_start_vol={} ch4_env_dir={} ch4_env_num={}",
+                self.channel4.envelope.start_volume,
+                self.channel4.envelope.direction,
+                self.channel4.envelope.sweep_number);
+        }
     }
 
     pub fn channel4_r3(&self) -> u8 {
-        0
+        let mut r = Channel4PolynomialCounterParameterControl::empty();
+        r.set(Channel4PolynomialCounterParameterControl::COUNTER_STEP_SELECT, self.channel4.clock_width_mode);
+        r.bits() | self.channel4.clock_shift << 4 | self.channel4.clock_divisor_code
     }
 
     pub fn set_channel4_r3(&mut self, data: u8) {
-        let _r = Channel4PolynomialCounterParameterControl::from_bits(data);
-        println!("NR43 {:?}", _r);
+        if !self.enable {
+            return;
+        }
+
+        let r = Channel4PolynomialCounterParameterControl::from_bits(data).unwrap();
+        self.channel4.clock_shift = (r & Channel4PolynomialCounterParameterControl::FREQUENCY_SHIFT_MASK).bits() >> 4;
+        self.channel4.clock_width_mode = r.contains(Channel4PolynomialCounterParameterControl::COUNTER_STEP_SELECT);
+        self.channel4.clock_divisor_code = (r & Channel4PolynomialCounterParameterControl::FREQUENCY_DIVIDER_MASK).bits();
+
+        if !self.quiet {
+            println!("NR43 ch4_shift={} ch4_width={} ch4_divisor={}",
+                self.channel4.clock_shift,
+                self.channel4.clock_width_mode,
+                self.channel4.clock_divisor_code);
+        }
     }
 
     pub fn channel4_r4(&self) -> u8 {
-        0
+        let mut r = !Channel4PolynomialCounterSequenceControl::STOP_ON_SEQUENCE_COMPLETE;
+        r.set(Channel4PolynomialCounterSequenceControl::STOP_ON_SEQUENCE_COMPLETE, self.channel4.length_enable);
+        r.bits()
     }
 
     pub fn set_channel4_r4(&mut self, data: u8) {
-        let _r = Channel4PolynomialCounterSequenceControl::from_bits(data);
-        println!("NR44 {:?}", _r);
+        if !self.enable {
+            return;
+        }
+
+        let r = Channel4PolynomialCounterSequenceControl::from_bits(data).unwrap();
+
+        self.channel4.length_enable = r.contains(Channel4PolynomialCounterSequenceControl::STOP_ON_SEQUENCE_COMPLETE);
+        if r.contains(Channel4PolynomialCounterSequenceControl::RESTART_SEQUENCE) {
+            self.channel4.trigger(self.clock);
+        }
+
+        if !self.quiet {
+            println!("NR44 ch4_len_enable={} ch4_playing={}",
+                self.channel4.length_enable,
+                self.channel4.playing);
+        }
     }
 
     pub fn master_r0(&self) -> u8 {
-        0
+        let mut r = MasterVolumeControl::empty();
+        r.set(MasterVolumeControl::LEFT_CHANNEL_5_ENABLE, self.left_vin_enable);
+        r.set(MasterVolumeControl::RIGHT_CHANNEL_5_ENABLE, self.right_vin_enable);
+        r.bits() | self.left_volume << 4 | self.right_volume
     }
 
     pub fn set_master_r0(&mut self, data: u8) {
+        if !self.enable {
+            return;
+        }
+
         let r = MasterVolumeControl::from_bits(data).unwrap();
 
+        self.left_vin_enable = r.contains(MasterVolumeControl::LEFT_CHANNEL_5_ENABLE);
+        self.right_vin_enable = r.contains(MasterVolumeControl::RIGHT_CHANNEL_5_ENABLE);
         self.left_volume = (r & MasterVolumeControl::LEFT_CHANNEL_VOLUME_MASK).bits() >> 4;
-        self.right_volume = (r & MasterVolumeControl::RIGHT_CHANNEL_VOLUME_MASK).bits() >> 0;
+        self.right_volume = (r & MasterVolumeControl::RIGHT_CHANNEL_VOLUME_MASK).bits();
 
-        println!("NR50 volume=({}, {})", self.left_volume, self.right_volume);
+        if !self.quiet {
+            println!("NR50 volume=({}, {})", self.left_volume, self.right_volume);
+        }
     }
 
     pub fn master_r1(&self) -> u8 {
-        0
+        let mut r = MasterOutputControl::empty();
+
+        r.set(MasterOutputControl::LEFT_CHANNEL_4_ENABLE, self.channel4.left_enable);
+        r.set(MasterOutputControl::LEFT_CHANNEL_3_ENABLE, self.channel3.left_enable);
+        r.set(MasterOutputControl::LEFT_CHANNEL_2_ENABLE, self.channel2.left_enable);
+        r.set(MasterOutputControl::LEFT_CHANNEL_1_ENABLE, self.channel1.left_enable);
+
+        r.set(MasterOutputControl::RIGHT_CHANNEL_4_ENABLE, self.channel4.right_enable);
+        r.set(MasterOutputControl::RIGHT_CHANNEL_3_ENABLE, self.channel3.right_enable);
+        r.set(MasterOutputControl::RIGHT_CHANNEL_2_ENABLE, self.channel2.right_enable);
+        r.set(MasterOutputControl::RIGHT_CHANNEL_1_ENABLE, self.channel1.right_enable);
+
+        r.bits()
     }
 
     pub fn set_master_r1(&mut self, data: u8) {
+        if !self.enable {
+            return;
+        }
+
         let r = MasterOutputControl::from_bits(data).unwrap();
 
         self.channel4.left_enable = r.contains(MasterOutputControl::LEFT_CHANNEL_4_ENABLE);
@@ -607,27 +1157,464 @@ impl Sounder {
         self.channel2.right_enable = r.contains(MasterOutputControl::RIGHT_CHANNEL_2_ENABLE);
         self.channel1.right_enable = r.contains(MasterOutputControl::RIGHT_CHANNEL_1_ENABLE);
 
-        println!("NR51 ch1_on=({}, {}) ch2_on=({}, {}) ch3_on=({}, {}) ch4_on=({}, {}))",
-            self.channel1.left_enable, self.channel1.right_enable,
-            self.channel2.left_enable, self.channel2.right_enable,
-            self.channel3.left_enable, self.channel3.right_enable,
-            self.channel4.left_enable, self.channel4.right_enable);
+        if !self.quiet {
+            println!("NR51 ch1_on=({}, {}) ch2_on=({}, {}) ch3_on=({}, {}) ch4_on=({}, {}))",
+                self.channel1.left_enable, self.channel1.right_enable,
+                self.channel2.left_enable, self.channel2.right_enable,
+                self.channel3.left_enable, self.channel3.right_enable,
+                self.channel4.left_enable, self.channel4.right_enable);
+        }
     }
 
     pub fn master_r2(&self) -> u8 {
-        0
+        let mut r = MasterOnOffControl::UNUSED_BIT6 | MasterOnOffControl::UNUSED_BIT5 | MasterOnOffControl::UNUSED_BIT4;
+        r.set(MasterOnOffControl::CHANNEL_ALL_ENABLE, self.enable);
+        r.set(MasterOnOffControl::CHANNEL_4_ENABLE, self.channel4.playing);
+        r.set(MasterOnOffControl::CHANNEL_3_ENABLE, self.channel3.playing);
+        r.set(MasterOnOffControl::CHANNEL_2_ENABLE, self.channel2.playing);
+        r.set(MasterOnOffControl::CHANNEL_1_ENABLE, self.channel1.playing);
+        r.bits()
     }
 
     pub fn set_master_r2(&mut self, data: u8) {
         let r = MasterOnOffControl::from_bits(data).unwrap();
-        self.enable = r.contains(MasterOnOffControl::CHANNEL_ALL_ENABLE);
-        println!("NR52 sound_on={}", self.enable);
+        let enable = r.contains(MasterOnOffControl::CHANNEL_ALL_ENABLE);
+
+        if self.enable && !enable {
+            self.power_off();
+        } else if !self.enable && enable {
+            self.sequencer_ticks = 0;
+            self.sequencer_step = 0;
+        }
+
+        self.enable = enable;
+        if !self.quiet {
+            println!("NR52 sound_on={}", self.enable);
+        }
+    }
+
+    // Powering off clears every register but the wave RAM and (on DMG) the length counters
+    fn power_off(&mut self) {
+        let time = self.clock;
+
+        self.left_vin_enable = false;
+        self.right_vin_enable = false;
+        self.left_volume = 0;
+        self.right_volume = 0;
+
+        self.channel1.power_off(time);
+        self.channel2.power_off(time);
+        self.channel3.power_off(time);
+        self.channel4.power_off(time);
+    }
+
+    fn channels(&mut self) -> [&mut dyn SampleGenerator; 4] {
+        [&mut self.channel1, &mut self.channel2, &mut self.channel3, &mut self.channel4]
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.channel1.blip.sample_rate()
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        let quality = self.channel1.blip.quality();
+        for channel in self.channels().iter_mut() {
+            channel.blip().reset(TICKS_PER_SECOND, sample_rate, quality);
+        }
+    }
+
+    pub fn set_quality(&mut self, quality: Quality) {
+        let sample_rate = self.sample_rate();
+        for channel in self.channels().iter_mut() {
+            channel.blip().reset(TICKS_PER_SECOND, sample_rate, quality);
+        }
+    }
+
+    fn clock_frame_sequencer(&mut self) {
+        let time = self.clock;
+        let step = self.sequencer_step;
+        self.sequencer_step = (step + 1) % 8;
+
+        if step & 1 == 0 {
+            self.channel1.clock_length(time);
+            self.channel2.clock_length(time);
+            self.channel3.clock_length(time);
+            self.channel4.clock_length(time);
+        }
+
+        if step == 2 || step == 6 {
+            self.channel1.clock_sweep(time);
+        }
+
+        if step == 7 {
+            self.channel1.clock_envelope(time);
+            self.channel2.clock_envelope(time);
+            self.channel4.clock_envelope(time);
+        }
+    }
+
+    pub fn step(&mut self, ticks: u64) {
+        if !self.enable {
+            self.clock += ticks;
+            return;
+        }
+
+        let mut ticks = ticks;
+        while ticks > 0 {
+            let run = ticks.min(FRAME_SEQUENCER_TICKS - self.sequencer_ticks);
+
+            let time = self.clock;
+            for channel in self.channels().iter_mut() {
+                channel.step(time, run);
+            }
+
+            self.clock += run;
+            self.sequencer_ticks += run;
+            ticks -= run;
+
+            if self.sequencer_ticks == FRAME_SEQUENCER_TICKS {
+                self.sequencer_ticks = 0;
+                self.clock_frame_sequencer();
+            }
+        }
     }
 
-    pub fn enqueue_audio_samples(&mut self, channels: &mut [AudioQueue<i8>; 4]) {
-        self.channel1.enqueue_audio_samples(&mut channels[0]);
-        self.channel2.enqueue_audio_samples(&mut channels[1]);
-        self.channel3.enqueue_audio_samples(&mut channels[2]);
-        self.channel4.enqueue_audio_samples(&mut channels[3]);
+    /// Resample everything generated since the last call into the per-channel stereo buffers
+    pub fn end_frame(&mut self) {
+        let clock = self.clock;
+        self.clock = 0;
+
+        let left_gain = (self.left_volume + 1) as f32 / 8.0 * SAMPLE_SCALE;
+        let right_gain = (self.right_volume + 1) as f32 / 8.0 * SAMPLE_SCALE;
+
+        let mut samples = std::mem::take(&mut self.samples);
+        let mut mono: Vec<f32> = Vec::new();
+        for (channel, stereo) in self.channels().iter_mut().zip(samples.iter_mut()) {
+            let (left_enable, right_enable) = channel.terminals();
+            let left_gain = if left_enable { left_gain } else { 0.0 };
+            let right_gain = if right_enable { right_gain } else { 0.0 };
+
+            let blip = channel.blip();
+            blip.end_frame(clock);
+
+            mono.clear();
+            blip.read_samples(usize::MAX, &mut mono);
+
+            stereo.clear();
+            for sample in mono.iter() {
+                stereo.push((sample * left_gain) as i16);
+                stereo.push((sample * right_gain) as i16);
+            }
+        }
+        self.samples = samples;
+
+        if self.muted {
+            self.silence_frame();
+            return;
+        }
+
+        let samples = &self.samples;
+        for recorder in [&mut self.recorder, &mut self.video_recorder] {
+            if let Some(Err(error)) = recorder.as_mut().map(|recorder| recorder.write_frame(samples)) {
+                println!("Audio recording failed: {}", error);
+                *recorder = None;
+            }
+        }
     }
-}
\ No newline at end of file
+
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+    }
+
+    pub fn set_register_log(&mut self, enabled: bool) {
+        self.quiet = !enabled;
+    }
+
+    /// Replace the output of the last frame by one frame of silence
+    pub fn silence_frame(&mut self) {
+        let len = self.sample_rate() as usize / 60 * 2;
+        for samples in self.samples.iter_mut() {
+            samples.clear();
+            samples.resize(len, 0);
+        }
+    }
+
+    /// Record the mixed output (and optionally each channel) to WAV files
+    pub fn start_recording(&mut self, filename: &str, stems: bool) -> std::io::Result<()> {
+        self.stop_recording()?;
+        self.recorder = Some(Recorder::create(filename, self.sample_rate(), stems)?);
+        Ok(())
+    }
+
+    pub fn stop_recording(&mut self) -> std::io::Result<()> {
+        match self.recorder.take() {
+            Some(recorder) => recorder.finish(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    /// Record the mixed output to a WAV file for the video recording
+    pub fn start_video_soundtrack(&mut self, filename: &str) -> std::io::Result<()> {
+        self.stop_video_soundtrack()?;
+        self.video_recorder = Some(Recorder::create(filename, self.sample_rate(), false)?);
+        Ok(())
+    }
+
+    pub fn stop_video_soundtrack(&mut self) -> std::io::Result<()> {
+        match self.video_recorder.take() {
+            Some(recorder) => recorder.finish(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn enqueue_audio_samples(&mut self, channels: &mut [AudioQueue<i16>; 4]) {
+        for (queue, samples) in channels.iter_mut().zip(self.samples.iter()) {
+            let bytes_per_frame = queue.spec().freq as u32 * queue.spec().channels as u32 * 2 / 60;
+            if queue.size() < bytes_per_frame * MAX_QUEUED_FRAMES {
+                queue.queue(samples);
+            }
+        }
+    }
+}
+
+impl Snapshot for Envelope {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bytes(&[self.start_volume, self.direction as u8, self.sweep_number, self.volume, self.timer]);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> std::io::Result<()> {
+        let mut r = [0; 5];
+        state.read_bytes(&mut r)?;
+        let [start_volume, direction, sweep_number, volume, timer] = r;
+        *self = Self { start_volume, direction: direction != 0, sweep_number, volume, timer };
+        Ok(())
+    }
+}
+
+// Pending output samples aren't saved, the buffers restart on load
+impl Snapshot for SquareChannel {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.left_enable);
+        state.write_bool(self.right_enable);
+        state.write_bool(self.playing);
+        state.write_bool(self.dac_enable);
+        state.write_bool(self.length_enable);
+        state.write_u16(self.length_counter);
+        state.write_u32(self.fparam);
+        self.envelope.save_state(state);
+        state.write_bool(self.sweep_inverse);
+        state.write_u8(self.sweep_period);
+        state.write_u8(self.sweep_shift);
+        state.write_bool(self.sweep_enable);
+        state.write_u8(self.sweep_timer);
+        state.write_u32(self.sweep_shadow);
+        state.write_u8(self.wave_duty);
+        state.write_u8(self.duty_step);
+        state.write_u64(self.timer);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> std::io::Result<()> {
+        self.left_enable = state.read_bool()?;
+        self.right_enable = state.read_bool()?;
+        self.playing = state.read_bool()?;
+        self.dac_enable = state.read_bool()?;
+        self.length_enable = state.read_bool()?;
+        self.length_counter = state.read_u16()?;
+        self.fparam = state.read_u32()? & 0x7FF;
+        self.envelope.load_state(state)?;
+        self.sweep_inverse = state.read_bool()?;
+        self.sweep_period = state.read_u8()?;
+        self.sweep_shift = state.read_u8()?;
+        self.sweep_enable = state.read_bool()?;
+        self.sweep_timer = state.read_u8()?;
+        self.sweep_shadow = state.read_u32()?;
+        self.wave_duty = state.read_u8()? & 0x3;
+        self.duty_step = state.read_u8()? & 0x7;
+        self.timer = state.read_u64()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for WaveChannel {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.left_enable);
+        state.write_bool(self.right_enable);
+        state.write_bool(self.playing);
+        state.write_bool(self.dac_enable);
+        state.write_bool(self.length_enable);
+        state.write_u16(self.length_counter);
+        state.write_u32(self.fparam);
+        state.write_u8(self.wave_volume);
+        state.write_u8(self.wave_position as u8);
+        state.write_bytes(&self.wave_ram);
+        state.write_u64(self.timer);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> std::io::Result<()> {
+        self.left_enable = state.read_bool()?;
+        self.right_enable = state.read_bool()?;
+        self.playing = state.read_bool()?;
+        self.dac_enable = state.read_bool()?;
+        self.length_enable = state.read_bool()?;
+        self.length_counter = state.read_u16()?;
+        self.fparam = state.read_u32()? & 0x7FF;
+        self.wave_volume = state.read_u8()? & 0x3;
+        self.wave_position = (state.read_u8()? & 0x1F) as usize;
+        state.read_bytes(&mut self.wave_ram)?;
+        self.timer = state.read_u64()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for NoiseChannel {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.left_enable);
+        state.write_bool(self.right_enable);
+        state.write_bool(self.playing);
+        state.write_bool(self.dac_enable);
+        state.write_bool(self.length_enable);
+        state.write_u16(self.length_counter);
+        self.envelope.save_state(state);
+        state.write_u8(self.clock_shift);
+        state.write_bool(self.clock_width_mode);
+        state.write_u8(self.clock_divisor_code);
+        state.write_u16(self.lfsr);
+        state.write_u64(self.timer);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> std::io::Result<()> {
+        self.left_enable = state.read_bool()?;
+        self.right_enable = state.read_bool()?;
+        self.playing = state.read_bool()?;
+        self.dac_enable = state.read_bool()?;
+        self.length_enable = state.read_bool()?;
+        self.length_counter = state.read_u16()?;
+        self.envelope.load_state(state)?;
+        self.clock_shift = state.read_u8()?;
+        self.clock_width_mode = state.read_bool()?;
+        self.clock_divisor_code = state.read_u8()? & 0x7;
+        self.lfsr = state.read_u16()?;
+        self.timer = state.read_u64()?;
+        Ok(())
+    }
+}
+
+// The recorders keep running across loads
+impl Snapshot for Sounder {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_bool(self.enable);
+        state.write_bool(self.left_vin_enable);
+        state.write_bool(self.right_vin_enable);
+        state.write_u8(self.left_volume);
+        state.write_u8(self.right_volume);
+        self.channel1.save_state(state);
+        self.channel2.save_state(state);
+        self.channel3.save_state(state);
+        self.channel4.save_state(state);
+        state.write_u64(self.clock);
+        state.write_u64(self.sequencer_ticks);
+        state.write_u8(self.sequencer_step);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> std::io::Result<()> {
+        self.enable = state.read_bool()?;
+        self.left_vin_enable = state.read_bool()?;
+        self.right_vin_enable = state.read_bool()?;
+        self.left_volume = state.read_u8()? & 0x7;
+        self.right_volume = state.read_u8()? & 0x7;
+        self.channel1.load_state(state)?;
+        self.channel2.load_state(state)?;
+        self.channel3.load_state(state)?;
+        self.channel4.load_state(state)?;
+        self.clock = state.read_u64()?;
+        self.sequencer_ticks = state.read_u64()?;
+        self.sequencer_step = state.read_u8()? & 0x7;
+
+        self.set_sample_rate(self.sample_rate());
+        let time = self.clock;
+        let amplitude = if self.channel1.playing { self.channel1.amplitude() } else { 0 };
+        self.channel1.blip.set_amplitude(time, amplitude);
+        let amplitude = if self.channel2.playing { self.channel2.amplitude() } else { 0 };
+        self.channel2.blip.set_amplitude(time, amplitude);
+        let amplitude = if self.channel3.playing { self.channel3.amplitude() } else { 0 };
+        self.channel3.blip.set_amplitude(time, amplitude);
+        let amplitude = if self.channel4.playing { self.channel4.amplitude() } else { 0 };
+        self.channel4.blip.set_amplitude(time, amplitude);
+        for samples in self.samples.iter_mut() {
+            samples.clear();
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn sounder_register_read_mask_test() {
+    let mut sounder = Sounder::default();
+    sounder.set_master_r2(0x80);
+
+    // register read and the value it reads with every writable bit cleared
+    type ReadMask = (fn(&Sounder) -> u8, u8);
+    let regs: [ReadMask; 21] = [
+        (Sounder::channel1_r0, 0x80), (Sounder::channel1_r1, 0x3F), (Sounder::channel1_r2, 0x00),
+        (Sounder::channel1_r3, 0xFF), (Sounder::channel1_r4, 0xBF),
+        (Sounder::channel2_r1, 0x3F), (Sounder::channel2_r2, 0x00),
+        (Sounder::channel2_r3, 0xFF), (Sounder::channel2_r4, 0xBF),
+        (Sounder::channel3_r0, 0x7F), (Sounder::channel3_r1, 0xFF), (Sounder::channel3_r2, 0x9F),
+        (Sounder::channel3_r3, 0xFF), (Sounder::channel3_r4, 0xBF),
+        (Sounder::channel4_r1, 0xFF), (Sounder::channel4_r2, 0x00),
+        (Sounder::channel4_r3, 0x00), (Sounder::channel4_r4, 0xBF),
+        (Sounder::master_r0, 0x00), (Sounder::master_r1, 0x00), (Sounder::master_r2, 0xF0),
+    ];
+
+    for (read, mask) in regs.iter() {
+        assert_eq!(*mask, read(&sounder));
+    }
+
+    sounder.set_channel1_r0(0x7F);
+    sounder.set_channel1_r1(0xFF);
+    sounder.set_channel3_r2(0x40);
+    sounder.set_master_r0(0x77);
+    assert_eq!(0xFF, sounder.channel1_r0());
+    assert_eq!(0xFF, sounder.channel1_r1());
+    assert_eq!(0xDF, sounder.channel3_r2());
+    assert_eq!(0x77, sounder.master_r0());
+}
+
+#[test]
+fn sounder_power_control_test() {
+    let mut sounder = Sounder::default();
+    sounder.set_master_r2(0x80);
+
+    // Trigger channel 2 with length 1
+    sounder.set_channel2_r1(0x3F);
+    sounder.set_channel2_r2(0xF0);
+    sounder.set_channel2_r4(0xC0);
+    sounder.set_channel3_sample(0, 0x12);
+    assert_eq!(0xF2, sounder.master_r2());
+
+    // The next length clock expires the channel
+    sounder.step(FRAME_SEQUENCER_TICKS);
+    assert_eq!(0xF0, sounder.master_r2());
+
+    sounder.set_master_r1(0xFF);
+    sounder.set_master_r2(0x00);
+    assert_eq!(0x70, sounder.master_r2());
+    assert_eq!(0x00, sounder.master_r1());
+    assert_eq!(0x00, sounder.channel2_r2());
+
+    // Writes are ignored while powered off, except for length and wave RAM
+    sounder.set_master_r1(0xFF);
+    sounder.set_channel2_r1(0x3E);
+    sounder.set_channel3_sample(0, 0x34);
+    assert_eq!(0x00, sounder.master_r1());
+    assert_eq!(2, sounder.channel2.length_counter);
+    assert_eq!(0x34, sounder.channel3_sample(0));
+
+    sounder.set_master_r2(0x80);
+    sounder.set_master_r1(0xFF);
+    assert_eq!(0xFF, sounder.master_r1());
+}
@@ -0,0 +1,177 @@
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+const WAV_HEADER_SIZE: u32 = 44;
+const WAV_CHANNELS: u16 = 2;
+const WAV_BITS_PER_SAMPLE: u16 = 16;
+
+/// 16 bit stereo PCM WAV file writer
+///
+/// The RIFF and data chunk sizes are only known once the recording stops,
+/// they are patched in by `finish` (or on drop, ignoring errors).
+pub struct WavWriter {
+    writer: BufWriter<File>,
+    data_size: u32,
+    finished: bool,
+}
+
+impl WavWriter {
+    pub fn create<P: AsRef<Path>>(filename: P, sample_rate: u32) -> std::io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(filename)?);
+
+        let block_align = WAV_CHANNELS * WAV_BITS_PER_SAMPLE / 8;
+        let byte_rate = sample_rate * block_align as u32;
+
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&(WAV_HEADER_SIZE - 8).to_le_bytes())?;
+        writer.write_all(b"WAVE")?;
+
+        writer.write_all(b"fmt ")?;
+        writer.write_all(&16u32.to_le_bytes())?;
+        writer.write_all(&1u16.to_le_bytes())?; // PCM
+        writer.write_all(&WAV_CHANNELS.to_le_bytes())?;
+        writer.write_all(&sample_rate.to_le_bytes())?;
+        writer.write_all(&byte_rate.to_le_bytes())?;
+        writer.write_all(&block_align.to_le_bytes())?;
+        writer.write_all(&WAV_BITS_PER_SAMPLE.to_le_bytes())?;
+
+        writer.write_all(b"data")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+
+        Ok(Self {
+            writer,
+            data_size: 0,
+            finished: false,
+        })
+    }
+
+    /// Append interleaved stereo samples
+    pub fn write_samples(&mut self, samples: &[i16]) -> std::io::Result<()> {
+        for sample in samples {
+            self.writer.write_all(&sample.to_le_bytes())?;
+        }
+        self.data_size += (samples.len() * 2) as u32;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> std::io::Result<()> {
+        self.patch_header()
+    }
+
+    fn patch_header(&mut self) -> std::io::Result<()> {
+        self.finished = true;
+
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer.write_all(&(WAV_HEADER_SIZE - 8 + self.data_size).to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(40))?;
+        self.writer.write_all(&self.data_size.to_le_bytes())?;
+        self.writer.flush()
+    }
+}
+
+impl Drop for WavWriter {
+    fn drop(&mut self) {
+        if !self.finished {
+            let _ = self.patch_header();
+        }
+    }
+}
+
+// "music.wav" => "music.ch1.wav"
+fn stem_filename(filename: &Path, channel: usize) -> PathBuf {
+    let stem = filename.file_stem().unwrap_or_default().to_string_lossy();
+    filename.with_file_name(format!("{}.ch{}.wav", stem, channel + 1))
+}
+
+/// Sounder output recorder
+///
+/// Writes the mixed stereo output to a WAV file and, optionally, each of the
+/// four channels to its own stem file next to it.
+pub struct Recorder {
+    mix: WavWriter,
+    stems: Option<[WavWriter; 4]>,
+    buffer: Vec<i16>,
+}
+
+impl Recorder {
+    pub fn create<P: AsRef<Path>>(filename: P, sample_rate: u32, stems: bool) -> std::io::Result<Self> {
+        let filename = filename.as_ref();
+
+        let stems = if stems {
+            Some([
+                WavWriter::create(stem_filename(filename, 0), sample_rate)?,
+                WavWriter::create(stem_filename(filename, 1), sample_rate)?,
+                WavWriter::create(stem_filename(filename, 2), sample_rate)?,
+                WavWriter::create(stem_filename(filename, 3), sample_rate)?,
+            ])
+        } else {
+            None
+        };
+
+        Ok(Self {
+            mix: WavWriter::create(filename, sample_rate)?,
+            stems,
+            buffer: Vec::new(),
+        })
+    }
+
+    /// Record one frame worth of interleaved stereo samples of each channel
+    pub fn write_frame(&mut self, channels: &[Vec<i16>; 4]) -> std::io::Result<()> {
+        let len = channels.iter().map(|samples| samples.len()).min().unwrap_or(0);
+
+        self.buffer.clear();
+        for index in 0..len {
+            let sample: i32 = channels.iter().map(|samples| samples[index] as i32).sum();
+            self.buffer.push(sample.max(i16::MIN as i32).min(i16::MAX as i32) as i16);
+        }
+        self.mix.write_samples(&self.buffer)?;
+
+        if let Some(stems) = self.stems.as_mut() {
+            for (stem, samples) in stems.iter_mut().zip(channels.iter()) {
+                stem.write_samples(&samples[..len])?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> std::io::Result<()> {
+        self.mix.finish()?;
+        if let Some(stems) = self.stems {
+            for stem in stems {
+                stem.finish()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn recorder_wav_file_test() {
+    use crate::emulator::test_fixture::TempFile;
+
+    let file = TempFile::new("recorder", "wav");
+    let stems: Vec<TempFile> = (1..=4).map(|channel| file.with_extension(&format!("ch{}.wav", channel))).collect();
+
+    let mut recorder = Recorder::create(file.path(), 48_000, true).unwrap();
+    let channels = [vec![100, -100], vec![200, -200], vec![i16::MAX, 0], vec![0, 0]];
+    recorder.write_frame(&channels).unwrap();
+    recorder.write_frame(&channels).unwrap();
+    recorder.finish().unwrap();
+
+    let wav = std::fs::read(file.path()).unwrap();
+    assert_eq!(44 + 8, wav.len());
+    assert_eq!(b"RIFF", &wav[0..4]);
+    assert_eq!(44 - 8 + 8, u32::from_le_bytes([wav[4], wav[5], wav[6], wav[7]]));
+    assert_eq!(48_000, u32::from_le_bytes([wav[24], wav[25], wav[26], wav[27]]));
+    assert_eq!(8, u32::from_le_bytes([wav[40], wav[41], wav[42], wav[43]]));
+
+    // mixed output saturates instead of wrapping around
+    assert_eq!(i16::MAX, i16::from_le_bytes([wav[44], wav[45]]));
+    assert_eq!(-300, i16::from_le_bytes([wav[46], wav[47]]));
+
+    assert_eq!(stems[1].path(), stem_filename(file.path(), 1));
+    let wav = std::fs::read(stems[1].path()).unwrap();
+    assert_eq!(200, i16::from_le_bytes([wav[44], wav[45]]));
+    assert!(stems.iter().all(|stem| stem.path().exists()));
+}
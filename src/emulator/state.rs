@@ -0,0 +1,143 @@
+use std::io::{Error, ErrorKind};
+
+/// Save State File Identifier
+pub const STATE_MAGIC: &[u8; 8] = b"KIWISAVE";
+
+/// Save State Format Version
+/// - bump on any change of the layout written by `Snapshot::save_state`
+pub const STATE_VERSION: u32 = 4;
+
+/// Machine component that can be saved and restored
+///
+/// Components write their fields in a fixed order, `load_state` must read
+/// them back in the same order. Values are little endian.
+pub trait Snapshot {
+    fn save_state(&self, state: &mut StateWriter);
+
+    fn load_state(&mut self, state: &mut StateReader) -> std::io::Result<()>;
+}
+
+#[derive(Default)]
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.data.push(value as u8);
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// Fixed size data, the reader must know the length
+    pub fn write_bytes(&mut self, value: &[u8]) {
+        self.data.extend_from_slice(value);
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.position >= self.data.len()
+    }
+
+    fn take(&mut self, len: usize) -> std::io::Result<&'a [u8]> {
+        if self.position + len > self.data.len() {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "truncated save state"));
+        }
+        let bytes = &self.data[self.position..self.position + len];
+        self.position += len;
+        Ok(bytes)
+    }
+
+    pub fn read_bool(&mut self) -> std::io::Result<bool> {
+        Ok(self.read_u8()? != 0)
+    }
+
+    pub fn read_u8(&mut self) -> std::io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn read_u16(&mut self) -> std::io::Result<u16> {
+        let mut bytes = [0; 2];
+        bytes.copy_from_slice(self.take(2)?);
+        Ok(u16::from_le_bytes(bytes))
+    }
+
+    pub fn read_u32(&mut self) -> std::io::Result<u32> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    pub fn read_u64(&mut self) -> std::io::Result<u64> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    pub fn read_bytes(&mut self, value: &mut [u8]) -> std::io::Result<()> {
+        value.copy_from_slice(self.take(value.len())?);
+        Ok(())
+    }
+}
+
+/// Error for a save state that doesn't belong to this machine
+pub fn invalid_state(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+#[test]
+fn state_round_trip_test() {
+    let mut writer = StateWriter::new();
+    writer.write_bool(true);
+    writer.write_u8(0x12);
+    writer.write_u16(0x3456);
+    writer.write_u32(0x789A_BCDE);
+    writer.write_u64(u64::MAX - 1);
+    writer.write_bytes(b"kiwi");
+    let data = writer.into_bytes();
+
+    let mut reader = StateReader::new(&data);
+    assert!(reader.read_bool().unwrap());
+    assert_eq!(0x12, reader.read_u8().unwrap());
+    assert_eq!(0x3456, reader.read_u16().unwrap());
+    assert_eq!(0x789A_BCDE, reader.read_u32().unwrap());
+    assert_eq!(u64::MAX - 1, reader.read_u64().unwrap());
+    let mut bytes = [0; 4];
+    reader.read_bytes(&mut bytes).unwrap();
+    assert_eq!(b"kiwi", &bytes);
+    assert!(reader.is_empty());
+
+    assert_eq!(ErrorKind::UnexpectedEof, reader.read_u8().unwrap_err().kind());
+}
@@ -0,0 +1,175 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::emulator::cpu::asm::{disassemble, instruction_size};
+
+// Start of the memory areas, symbols don't reach past the end of theirs
+const AREAS: [u16; 10] = [0x0000, 0x4000, 0x8000, 0xA000, 0xC000, 0xD000, 0xE000, 0xFE00, 0xFF00, 0xFF80];
+
+/// RGBDS Symbol File
+///
+/// `BB:AAAA Name` lines as written by `rgblink -n`, `;` starts a comment.
+/// The bank only tells ROM banks (and WRAM/SRAM banks) apart, addresses are
+/// the ones the CPU sees.
+#[derive(Debug, Default)]
+pub struct Symbols {
+    names: BTreeMap<(u16, u16), String>,
+}
+
+impl Symbols {
+    pub fn load<P: AsRef<Path>>(filename: P) -> std::io::Result<Self> {
+        let text = std::fs::read_to_string(filename)?;
+        Self::parse(&text).map_err(|error| std::io::Error::new(std::io::ErrorKind::InvalidData, error))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut symbols = Symbols::default();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split(';').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let symbol = line.split_once(char::is_whitespace).and_then(|(location, name)| {
+                let (bank, addr) = location.split_once(':')?;
+                Some((u16::from_str_radix(bank, 16).ok()?, u16::from_str_radix(addr, 16).ok()?, name.trim()))
+            });
+            match symbol {
+                Some((bank, addr, name)) if !name.is_empty() => symbols.insert(bank, addr, name),
+                _ => return Err(format!("line {}: expected `BB:AAAA Name`", number + 1)),
+            }
+        }
+
+        Ok(symbols)
+    }
+
+    /// First name given to an address is kept
+    pub fn insert(&mut self, bank: u16, addr: u16, name: &str) {
+        self.names.entry((bank, addr)).or_insert_with(|| name.to_string());
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    /// Nearest symbol at or before `addr` and the distance to it
+    ///
+    /// `rom_bank` is the bank mapped at $4000-$7FFF. Banks of RAM symbols
+    /// aren't told apart.
+    pub fn lookup(&self, rom_bank: u16, addr: u16) -> Option<(&str, u16)> {
+        let area = *AREAS.iter().rev().find(|area| **area <= addr).unwrap();
+        let (symbol_addr, name) = match addr {
+            0x0000..=0x7FFF => {
+                let bank = if addr < 0x4000 { 0 } else { rom_bank };
+                self.names.range((bank, area)..=(bank, addr)).next_back().map(|((_, addr), name)| (*addr, name))?
+            }
+            _ => self.names.iter()
+                .map(|((_, addr), name)| (*addr, name))
+                .filter(|(symbol_addr, _)| (area..=addr).contains(symbol_addr))
+                .max_by_key(|(symbol_addr, _)| *symbol_addr)?,
+        };
+        Some((name.as_str(), addr - symbol_addr))
+    }
+
+    /// `Name` or `Name+$offset`
+    pub fn label(&self, rom_bank: u16, addr: u16) -> Option<String> {
+        self.lookup(rom_bank, addr).map(|(name, offset)| match offset {
+            0 => name.to_string(),
+            _ => format!("{}+${:X}", name, offset),
+        })
+    }
+
+    /// Bank and address of a symbol
+    pub fn find(&self, name: &str) -> Option<(u16, u16)> {
+        self.names.iter().find(|(_, symbol)| *symbol == name).map(|(location, _)| *location)
+    }
+
+    /// `disassemble` with the jump targets and addresses named after the symbols
+    ///
+    /// 16-bit immediates loaded into registers are only named on an exact
+    /// match, they are as often plain numbers.
+    pub fn disassemble(&self, rom_bank: u16, addr: u16, bytes: [u8; 3]) -> String {
+        let [opcode, immediate8, _] = bytes;
+        let immediate16 = u16::from_le_bytes([bytes[1], bytes[2]]);
+        let text = disassemble(opcode, immediate8, immediate16);
+
+        let (operand, label) = match opcode {
+            0x18 | 0x20 | 0x28 | 0x30 | 0x38 => {
+                let target = addr.wrapping_add(2).wrapping_add(immediate8 as i8 as u16);
+                (format!("${:02X}", immediate8), self.label(rom_bank, target))
+            }
+            0xE0 | 0xF0 => (format!("$FF${:02X}", immediate8), self.label(rom_bank, 0xFF00 | immediate8 as u16)),
+            0x01 | 0x11 | 0x21 | 0x31 => {
+                let label = self.lookup(rom_bank, immediate16).filter(|(_, offset)| *offset == 0);
+                (format!("${:04X}", immediate16), label.map(|(name, _)| name.to_string()))
+            }
+            _ if opcode != 0xCB && instruction_size(opcode) == 3 => (format!("${:04X}", immediate16), self.label(rom_bank, immediate16)),
+            _ => return text,
+        };
+        match label {
+            Some(label) => text.replacen(&operand, &label, 1),
+            None => text,
+        }
+    }
+
+    /// Symbols ordered by bank then address
+    pub fn iter(&self) -> impl Iterator<Item = (u16, u16, &str)> {
+        self.names.iter().map(|((bank, addr), name)| (*bank, *addr, name.as_str()))
+    }
+}
+
+#[test]
+fn symbols_parse_test() {
+    let symbols = Symbols::parse("
+        ; File generated by rgblink
+        00:0150 Start
+        00:0150 Start.alias
+        01:4000 Bank1Code ; trailing comment
+        00:C000 wBuffer
+    ").unwrap();
+
+    assert_eq!(3, symbols.len());
+    assert_eq!(Some(("Start", 0)), symbols.lookup(0, 0x0150));
+    assert_eq!(Some(("Bank1Code", 0)), symbols.lookup(1, 0x4000));
+    assert_eq!(None, symbols.lookup(2, 0x4000));
+    assert_eq!(vec![(0, 0x0150), (0, 0xC000), (1, 0x4000)],
+        symbols.iter().map(|(bank, addr, _)| (bank, addr)).collect::<Vec<_>>());
+
+    assert!(Symbols::parse("0150 Start").is_err());
+    assert!(Symbols::parse("00:0150").is_err());
+}
+
+#[test]
+fn symbols_lookup_test() {
+    let symbols = Symbols::parse("
+        00:0150 Start
+        00:0160 Main
+        01:4000 Bank1Code
+        02:4000 Bank2Code
+        00:C000 wBuffer
+        00:FF80 hTransfer
+    ").unwrap();
+
+    assert_eq!(Some("Start".to_string()), symbols.label(1, 0x0150));
+    assert_eq!(Some("Start+$F".to_string()), symbols.label(1, 0x015F));
+    assert_eq!(Some("Main+$20".to_string()), symbols.label(1, 0x0180));
+    assert_eq!(None, symbols.label(1, 0x0100));
+
+    // the switchable bank picks the label, bank 0 labels don't reach it
+    assert_eq!(Some("Bank1Code+$10".to_string()), symbols.label(1, 0x4010));
+    assert_eq!(Some("Bank2Code+$10".to_string()), symbols.label(2, 0x4010));
+    assert_eq!(None, symbols.label(3, 0x4010));
+
+    assert_eq!(Some("wBuffer+$5".to_string()), symbols.label(1, 0xC005));
+    assert_eq!(None, symbols.label(1, 0xD000));
+    assert_eq!(Some((0, 0xC000)), symbols.find("wBuffer"));
+    assert_eq!(None, symbols.find("wMissing"));
+
+    assert_eq!("JP NZ Main+$2", symbols.disassemble(1, 0x0150, [0xC2, 0x62, 0x01]));
+    assert_eq!("JR Start", symbols.disassemble(1, 0x0160, [0x18, 0xEE, 0x00]));
+    assert_eq!("LD HL, wBuffer", symbols.disassemble(1, 0x0150, [0x21, 0x00, 0xC0]));
+    assert_eq!("LD BC, $C001", symbols.disassemble(1, 0x0150, [0x01, 0x01, 0xC0]));
+    assert_eq!("LDH (hTransfer+$1),A;", symbols.disassemble(1, 0x0150, [0xE0, 0x81, 0x00]));
+    assert_eq!("CALL Bank2Code", symbols.disassemble(2, 0x0150, [0xCD, 0x00, 0x40]));
+}
@@ -0,0 +1,67 @@
+//! Test Fixtures
+//!
+//! Files the tests write to the temporary directory, ROMs mostly. They are
+//! named after the test and the process so that tests running in parallel
+//! don't share them, and removed when dropped, also when the test panics.
+
+use std::path::{Path, PathBuf};
+
+use crate::emulator::bios::DMG_BIOS;
+
+/// Smallest ROM, two banks without a mapper
+pub const ROM_SIZE: usize = 0x8000;
+
+/// ROM the boot ROM hands over to, with the logo and header checksum it
+/// checks and `NOP; JP $0150` at the entry point
+pub fn bootable_rom() -> Vec<u8> {
+    let mut rom = vec![0; ROM_SIZE];
+    rom[0x0104..0x0134].copy_from_slice(&DMG_BIOS[0xA8..0xD8]);
+    rom[0x014D] = rom[0x0134..0x014D].iter().fold(0u8, |checksum, byte| checksum.wrapping_sub(*byte).wrapping_sub(1));
+    rom[0x0100..0x0104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+    rom
+}
+
+/// File in the temporary directory, removed when dropped
+pub struct TempFile {
+    path: PathBuf,
+}
+
+impl TempFile {
+    /// `kiwi-<name>-<pid>.<extension>`, not created
+    pub fn new(name: &str, extension: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("kiwi-{}-{}.{}", name, std::process::id(), extension));
+        Self { path }
+    }
+
+    /// ROM file holding `rom`
+    pub fn rom(name: &str, rom: &[u8]) -> Self {
+        let file = Self::new(name, "gb");
+        std::fs::write(&file.path, rom).unwrap();
+        file
+    }
+
+    /// ROM file of zeros, `NOP`s running into the boot ROM lock up
+    pub fn blank_rom(name: &str) -> Self {
+        Self::rom(name, &[0; ROM_SIZE])
+    }
+
+    /// File next to this one with another extension, as the emulator names
+    /// the files it writes after the ROM
+    pub fn with_extension(&self, extension: &str) -> Self {
+        Self { path: self.path.with_extension(extension) }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn to_str(&self) -> &str {
+        self.path.to_str().unwrap()
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
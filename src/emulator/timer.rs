@@ -1,5 +1,11 @@
-const COUNTER_DIV: [u64; 4] = [1024, 16, 64, 256];
-const DIVIDER_DIV: u64 = 256;
+use crate::emulator::state::{Snapshot, StateReader, StateWriter};
+
+// System counter bit watched by the counter for each input clock select
+// - 4096Hz, 262144Hz, 65536Hz and 16384Hz
+const COUNTER_BITS: [u16; 4] = [1 << 9, 1 << 3, 1 << 5, 1 << 7];
+
+// Counter and divider advance on machine cycles
+const TICKS_PER_CYCLE: u64 = 4;
 
 pub struct Timer {
     enable: bool,
@@ -7,12 +13,20 @@ pub struct Timer {
 
     control: u8,
     counter: u8,
-    divider: u8,
     modulo: u8,
 
-    counter_acc: u64,
-    counter_div: u64,
-    divider_acc: u64,
+    // Internal 16 bit system counter, DIV is its upper byte
+    system_counter: u16,
+
+    // System counter bit selected by TAC
+    counter_bit: u16,
+
+    // Counter overflowed in the last cycle, it stays at 0 until the reload
+    reload_pending: bool,
+
+    // Counter was reloaded from modulo in the last cycle
+    // - counter writes are ignored, modulo writes go through to the counter
+    reloaded: bool,
 }
 
 impl Default for Timer {
@@ -23,25 +37,32 @@ impl Default for Timer {
 
             control: 0xff,
             counter: 0,
-            divider: 0,
             modulo: 0,
 
-            counter_acc: 0,
-            counter_div: COUNTER_DIV[3],
-            divider_acc: 0,
+            system_counter: 0,
+            counter_bit: COUNTER_BITS[3],
+            reload_pending: false,
+            reloaded: false,
         }
     }
 }
 
 impl Timer {
     pub fn control(&self) -> u8 {
-        self.control
+        self.control | 0b1111_1000
     }
 
     pub fn set_control(&mut self, control: u8) {
-        self.enable = control & 1 << 3 != 0;
-        self.counter_div = COUNTER_DIV[(control & 0x3) as usize];
-        self.control = control
+        let signal = self.counter_signal();
+
+        self.enable = control & 1 << 2 != 0;
+        self.counter_bit = COUNTER_BITS[(control & 0x3) as usize];
+        self.control = control;
+
+        // Disabling the timer or selecting another bit can look like a falling edge
+        if signal && !self.counter_signal() {
+            self.increment_counter();
+        }
     }
 
     pub fn counter(&self) -> u8 {
@@ -49,15 +70,26 @@ impl Timer {
     }
 
     pub fn set_counter(&mut self, counter: u8) {
+        if self.reloaded {
+            return;
+        }
+
+        // Writing during the overflow cycle cancels the reload and its interruption
         self.counter = counter;
+        self.reload_pending = false;
     }
 
     pub fn divider(&self) -> u8 {
-        self.divider
+        (self.system_counter >> 8) as u8
     }
 
     pub fn reset_divider(&mut self) {
-        self.divider = 0
+        self.set_system_counter(0);
+    }
+
+    /// Start the system counter at a divider value, as after the boot ROM
+    pub fn set_divider(&mut self, divider: u8) {
+        self.system_counter = (divider as u16) << 8;
     }
 
     pub fn modulo(&self) -> u8 {
@@ -65,81 +97,189 @@ impl Timer {
     }
 
     pub fn set_modulo(&mut self, modulo: u8) {
-        self.modulo = modulo
+        self.modulo = modulo;
+
+        if self.reloaded {
+            self.counter = modulo;
+        }
     }
 
     pub fn overflow_interrupt_requested(&self) -> bool {
         self.overflow_interrupt_requested
     }
 
-    pub fn step(&mut self, ticks: u64) {
-        self.overflow_interrupt_requested = false;
+    // Counter input, it increments on the falling edge
+    fn counter_signal(&self) -> bool {
+        self.enable && self.system_counter & self.counter_bit != 0
+    }
 
-        if self.enable {
-            self.counter_acc += ticks;
-            self.divider_acc += ticks;
+    fn set_system_counter(&mut self, system_counter: u16) {
+        let signal = self.counter_signal();
+        self.system_counter = system_counter;
 
-            if self.counter_acc >= self.counter_div {
-                self.counter_acc -= self.counter_div;
+        if signal && !self.counter_signal() {
+            self.increment_counter();
+        }
+    }
 
-                let (counter, overflow) = self.counter.overflowing_add(1);
+    fn increment_counter(&mut self) {
+        let (counter, overflow) = self.counter.overflowing_add(1);
+        self.counter = counter;
+        self.reload_pending = overflow;
+    }
 
-                self.counter = if overflow { self.modulo } else { counter };
-                self.overflow_interrupt_requested = overflow;
-            }
+    pub fn step(&mut self, ticks: u64) {
+        self.overflow_interrupt_requested = false;
+
+        for _ in 0..ticks / TICKS_PER_CYCLE {
+            self.reloaded = false;
 
-            if self.divider_acc >= DIVIDER_DIV {
-                self.divider_acc -= DIVIDER_DIV;
-                self.divider = self.divider.wrapping_add(1);
+            if self.reload_pending {
+                self.reload_pending = false;
+                self.reloaded = true;
+                self.counter = self.modulo;
+                self.overflow_interrupt_requested = true;
             }
+
+            self.set_system_counter(self.system_counter.wrapping_add(TICKS_PER_CYCLE as u16));
         }
     }
 }
 
+impl Snapshot for Timer {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.write_u8(self.control);
+        state.write_u8(self.counter);
+        state.write_u8(self.modulo);
+        state.write_u16(self.system_counter);
+        state.write_bool(self.reload_pending);
+        state.write_bool(self.reloaded);
+        state.write_bool(self.overflow_interrupt_requested);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> std::io::Result<()> {
+        self.control = state.read_u8()?;
+        self.enable = self.control & 1 << 2 != 0;
+        self.counter_bit = COUNTER_BITS[(self.control & 0x3) as usize];
+        self.counter = state.read_u8()?;
+        self.modulo = state.read_u8()?;
+        self.system_counter = state.read_u16()?;
+        self.reload_pending = state.read_bool()?;
+        self.reloaded = state.read_bool()?;
+        self.overflow_interrupt_requested = state.read_bool()?;
+        Ok(())
+    }
+}
+
 #[test]
 fn sync_test() {
     let mut timer = Timer::default();
 
     for _ in 0..64 {
         assert_eq!(0, timer.counter);
-        assert_eq!(0, timer.divider);
+        assert_eq!(0, timer.divider());
         timer.step(4)
     }
     assert_eq!(1, timer.counter);
-    assert_eq!(1, timer.divider);
-    assert_eq!(0, timer.counter_acc);
-    assert_eq!(0, timer.divider_acc);
+    assert_eq!(1, timer.divider());
+    assert_eq!(256, timer.system_counter);
 
     timer.set_control(0b1111_1100 | 2);
 
     for i in 0..64 {
         assert_eq!(1 + i / 16, timer.counter);
-        assert_eq!(1, timer.divider);
+        assert_eq!(1, timer.divider());
         timer.step(4)
     }
 
     assert_eq!(5, timer.counter);
-    assert_eq!(2, timer.divider);
+    assert_eq!(2, timer.divider());
 
     timer.set_control(0b1111_1100 | 1);
 
     for i in 0..64 {
         assert_eq!(5 + i / 4, timer.counter);
-        assert_eq!(2, timer.divider);
+        assert_eq!(2, timer.divider());
         timer.step(4)
     }
 
     assert_eq!(21, timer.counter);
-    assert_eq!(3, timer.divider);
+    assert_eq!(3, timer.divider());
 
     timer.set_control(0b1111_1100 | 0);
 
+    // bit 9 of the system counter is already set, it falls at $400
     for i in 0..=255 {
-        assert_eq!(21, timer.counter);
-        assert_eq!(3 + i / 64, timer.divider);
+        assert_eq!(if i < 64 { 21 } else { 22 }, timer.counter);
+        assert_eq!(3 + i / 64, timer.divider());
         timer.step(4)
     }
 
     assert_eq!(22, timer.counter);
-    assert_eq!(7, timer.divider);
-}
\ No newline at end of file
+    assert_eq!(7, timer.divider());
+}
+
+#[test]
+fn divider_runs_while_disabled_test() {
+    let mut timer = Timer::default();
+    timer.set_control(0b0000_0001);
+
+    timer.step(1024);
+    assert_eq!(0, timer.counter());
+    assert_eq!(4, timer.divider());
+    assert_eq!(0xF9, timer.control());
+}
+
+#[test]
+fn delayed_reload_test() {
+    let mut timer = Timer::default();
+    timer.set_control(0b0000_0101);
+    timer.set_modulo(0x80);
+    timer.set_counter(0xFF);
+
+    timer.step(16);
+    assert_eq!(0x00, timer.counter());
+    assert!(!timer.overflow_interrupt_requested());
+
+    timer.step(4);
+    assert_eq!(0x80, timer.counter());
+    assert!(timer.overflow_interrupt_requested());
+
+    // counter writes are ignored during the reload cycle, modulo writes go through
+    timer.set_counter(0x10);
+    timer.set_modulo(0x90);
+    assert_eq!(0x90, timer.counter());
+
+    // writing the counter while it is 0 cancels the reload
+    timer.step(4);
+    timer.set_counter(0xFF);
+    timer.step(8);
+    assert_eq!(0x00, timer.counter());
+    timer.set_counter(0x42);
+    timer.step(4);
+    assert_eq!(0x42, timer.counter());
+    assert!(!timer.overflow_interrupt_requested());
+}
+
+#[test]
+fn falling_edge_glitch_test() {
+    let mut timer = Timer::default();
+    timer.set_control(0b0000_0101);
+
+    // bit 3 set, resetting DIV makes it fall
+    timer.step(8);
+    assert_eq!(0, timer.counter());
+    timer.reset_divider();
+    assert_eq!(1, timer.counter());
+    assert_eq!(0, timer.divider());
+
+    // bit 3 set, disabling the timer makes it fall
+    timer.step(8);
+    timer.set_control(0b0000_0001);
+    assert_eq!(2, timer.counter());
+
+    // bit 3 set but the timer disabled, enabling it doesn't count
+    timer.step(16);
+    timer.set_control(0b0000_0101);
+    assert_eq!(2, timer.counter());
+}
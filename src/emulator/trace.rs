@@ -0,0 +1,156 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::ops::RangeInclusive;
+
+use crate::emulator::cpu::asm::disassemble;
+use crate::emulator::cpu::regs::Regs;
+use crate::emulator::symbols::Symbols;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TraceFormat {
+    /// `A:00 F:11 B:22 C:33 D:44 E:55 H:66 L:77 SP:8888 PC:9999 PCMEM:AA,BB,CC,DD`
+    /// - as the logs Gameboy Doctor compares against
+    Doctor,
+
+    /// `0150  LD A, $00            A:00 F:11 B:22 C:33 D:44 E:55 H:66 L:77 SP:8888  Start+$3`
+    /// - the label is there when the ROM has symbols
+    Disassembly,
+}
+
+impl TraceFormat {
+    pub fn parse(name: &str) -> Option<TraceFormat> {
+        match name {
+            "doctor" => Some(TraceFormat::Doctor),
+            "disasm" => Some(TraceFormat::Disassembly),
+            _ => None,
+        }
+    }
+}
+
+/// Instruction Tracer
+///
+/// Writes one line per executed instruction, before it runs. Interrupt
+/// dispatches aren't instructions and don't show up.
+pub struct Tracer {
+    out: Box<dyn Write>,
+    format: TraceFormat,
+
+    // Only instructions in this range are traced
+    range: Option<RangeInclusive<u16>>,
+
+    // Nothing is traced while the boot ROM is mapped
+    skip_boot: bool,
+}
+
+impl Tracer {
+    pub fn new(out: Box<dyn Write>, format: TraceFormat) -> Self {
+        Self {
+            out,
+            format,
+            range: None,
+            skip_boot: false,
+        }
+    }
+
+    pub fn create(filename: &str, format: TraceFormat) -> std::io::Result<Self> {
+        let file = File::create(filename)?;
+        Ok(Self::new(Box::new(BufWriter::new(file)), format))
+    }
+
+    pub fn set_range(&mut self, range: Option<RangeInclusive<u16>>) {
+        self.range = range;
+    }
+
+    pub fn set_skip_boot(&mut self, skip_boot: bool) {
+        self.skip_boot = skip_boot;
+    }
+
+    /// `regs` with F holding the flags, `pcmem` the 4 bytes from PC
+    /// - `symbols` with the bank mapped at $4000-$7FFF, none for the boot ROM
+    pub fn trace(&mut self, regs: &Regs, pcmem: [u8; 4], booting: bool, symbols: Option<(&Symbols, u16)>) -> std::io::Result<()> {
+        if self.skip_boot && booting {
+            return Ok(());
+        }
+        if let Some(range) = self.range.as_ref() {
+            if !range.contains(&regs.pc()) {
+                return Ok(());
+            }
+        }
+
+        match self.format {
+            TraceFormat::Doctor => writeln!(self.out,
+                "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+                regs.a(), regs.f(), regs.b(), regs.c(), regs.d(), regs.e(), regs.h(), regs.l(), regs.sp(), regs.pc(),
+                pcmem[0], pcmem[1], pcmem[2], pcmem[3]),
+            TraceFormat::Disassembly => {
+                let (instruction, label) = match symbols {
+                    Some((symbols, rom_bank)) => (
+                        symbols.disassemble(rom_bank, regs.pc(), [pcmem[0], pcmem[1], pcmem[2]]),
+                        symbols.label(rom_bank, regs.pc()).map(|label| format!("  {}", label)),
+                    ),
+                    None => (disassemble(pcmem[0], pcmem[1], u16::from_le_bytes([pcmem[1], pcmem[2]])), None),
+                };
+                writeln!(self.out,
+                    "{:04X}  {:<20} A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X}{}",
+                    regs.pc(), instruction,
+                    regs.a(), regs.f(), regs.b(), regs.c(), regs.d(), regs.e(), regs.h(), regs.l(), regs.sp(),
+                    label.unwrap_or_default())
+            }
+        }
+    }
+}
+
+/// `0150-01FF` or `$0150-$01FF`
+pub fn parse_range(text: &str) -> Option<RangeInclusive<u16>> {
+    let (begin, end) = text.split_once('-')?;
+    let parse = |addr: &str| u16::from_str_radix(addr.trim().trim_start_matches('$'), 16).ok();
+    Some(parse(begin)?..=parse(end)?)
+}
+
+#[test]
+fn trace_test() {
+    use crate::emulator::engine::Engine;
+    use crate::emulator::test_fixture::TempFile;
+
+    let rom = TempFile::blank_rom("trace");
+    let (doctor_file, disassembly_file) = (rom.with_extension("log"), rom.with_extension("asm"));
+
+    let mut engine = Engine::default();
+    engine.open_rom_file(rom.to_str());
+    engine.set_tracer(Some(Tracer::create(doctor_file.to_str(), TraceFormat::Doctor).unwrap()));
+    for _ in 0..4 {
+        engine.run_next_step();
+    }
+
+    let mut tracer = Tracer::create(disassembly_file.to_str(), TraceFormat::Disassembly).unwrap();
+    tracer.set_range(parse_range("$0004-0008"));
+    engine.set_tracer(Some(tracer));
+    for _ in 0..4 {
+        engine.run_next_step();
+    }
+    engine.set_tracer(None);
+
+    let doctor = std::fs::read_to_string(doctor_file.path()).unwrap();
+    assert_eq!(vec![
+        "A:00 F:00 B:00 C:00 D:00 E:00 H:00 L:00 SP:0000 PC:0000 PCMEM:31,FE,FF,AF",
+        "A:00 F:00 B:00 C:00 D:00 E:00 H:00 L:00 SP:FFFE PC:0003 PCMEM:AF,21,FF,9F",
+        "A:00 F:80 B:00 C:00 D:00 E:00 H:00 L:00 SP:FFFE PC:0004 PCMEM:21,FF,9F,32",
+        "A:00 F:80 B:00 C:00 D:00 E:00 H:9F L:FF SP:FFFE PC:0007 PCMEM:32,CB,7C,20",
+    ], doctor.lines().collect::<Vec<_>>());
+
+    // the clear loop runs $0008, $000A (out of range), $0007 and $0008
+    let disassembly = std::fs::read_to_string(disassembly_file.path()).unwrap();
+    let pcs: Vec<&str> = disassembly.lines().map(|line| &line[..4]).collect();
+    assert_eq!(vec!["0008", "0007", "0008"], pcs);
+
+    // past the boot ROM, labels and named operands
+    let symbols_file = rom.with_extension("sym.log");
+    let symbols = Symbols::parse("00:0150 Start\n00:C000 wBuffer").unwrap();
+    let mut tracer = Tracer::create(symbols_file.to_str(), TraceFormat::Disassembly).unwrap();
+    let mut regs = Regs::default();
+    regs.set_pc(0x0153);
+    tracer.trace(&regs, [0xEA, 0x00, 0xC0, 0x00], false, Some((&symbols, 1))).unwrap();
+    drop(tracer);
+    assert_eq!("0153  LD (wBuffer), A      A:00 F:00 B:00 C:00 D:00 E:00 H:00 L:00 SP:0000  Start+$3",
+        std::fs::read_to_string(symbols_file.path()).unwrap().trim_end());
+}
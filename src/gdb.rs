@@ -0,0 +1,486 @@
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+
+use crate::emulator::debugger::{Access, Break};
+use crate::emulator::engine::Engine;
+
+pub const DEFAULT_GDB_PORT: u16 = 2159;
+
+// Register layout reported to GDB, 16 bit little endian each
+// - named as the Z80 registers they share with the SM83
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <architecture>z80</architecture>
+  <feature name="org.gnu.gdb.z80.cpu">
+    <reg name="af" bitsize="16" type="int"/>
+    <reg name="bc" bitsize="16" type="int"/>
+    <reg name="de" bitsize="16" type="int"/>
+    <reg name="hl" bitsize="16" type="int"/>
+    <reg name="sp" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+const REGISTER_COUNT: usize = 6;
+
+// Breakpoint or watchpoint inserted by GDB and its debugger index
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Point {
+    kind: u8,
+    addr: u16,
+    len: u16,
+    index: usize,
+}
+
+/// GDB Remote Serial Protocol Server
+///
+/// Listens on a loopback TCP port for one GDB client at a time and drives the
+/// engine debugger: `target remote localhost:2159`. Registers are AF, BC, DE,
+/// HL, SP and PC, memory goes through the `Memory` trait. Software and
+/// hardware breakpoints (`Z0`/`Z1`) and write, read and access watchpoints
+/// (`Z2`/`Z3`/`Z4`) are supported, as are `c`, `s` and Ctrl-C.
+///
+/// Polled once per frame, it never blocks the emulation.
+pub struct GdbServer {
+    listener: TcpListener,
+    client: Option<TcpStream>,
+
+    // Bytes received but not handled yet
+    input: Vec<u8>,
+
+    // Client waits for a stop reply since `c` or `s`
+    running: bool,
+
+    points: Vec<Point>,
+}
+
+impl GdbServer {
+    /// Listen on 127.0.0.1, port 0 picks a free one
+    pub fn bind(port: u16) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            client: None,
+            input: Vec::new(),
+            running: false,
+            points: Vec::new(),
+        })
+    }
+
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn is_attached(&self) -> bool {
+        self.client.is_some()
+    }
+
+    /// Accept a client, handle its packets and report stops
+    pub fn poll(&mut self, engine: &mut Engine) -> std::io::Result<()> {
+        if self.client.is_none() {
+            match self.listener.accept() {
+                Ok((stream, addr)) => {
+                    println!("GDB attached from {}", addr);
+                    stream.set_nonblocking(true)?;
+                    stream.set_nodelay(true)?;
+                    self.client = Some(stream);
+                    self.input.clear();
+                    self.running = false;
+                    engine.debugger_mut().set_enabled(true);
+                    engine.debugger_mut().pause();
+                }
+                Err(error) if error.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(error) => return Err(error),
+            }
+        }
+
+        let mut buffer = [0; 1024];
+        loop {
+            let result = self.client.as_mut().unwrap().read(&mut buffer);
+            match result {
+                Ok(0) => {
+                    self.detach(engine);
+                    return Ok(());
+                }
+                Ok(len) => self.input.extend_from_slice(&buffer[..len]),
+                Err(error) if error.kind() == ErrorKind::WouldBlock => break,
+                Err(error) => {
+                    self.detach(engine);
+                    return Err(error);
+                }
+            }
+        }
+
+        while let Some(packet) = self.next_packet(engine)? {
+            if let Some(reply) = self.handle_packet(engine, &packet) {
+                self.send_packet(&reply)?;
+            }
+            if self.client.is_none() {
+                return Ok(());
+            }
+        }
+
+        if self.running {
+            if let Some(reason) = engine.debugger().stopped() {
+                self.running = false;
+                let reply = self.stop_reply(reason);
+                self.send_packet(&reply)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn detach(&mut self, engine: &mut Engine) {
+        for point in self.points.drain(..) {
+            remove_point(engine, point);
+        }
+        engine.debugger_mut().resume();
+        self.client = None;
+        self.running = false;
+        println!("GDB detached");
+    }
+
+    // Acknowledges `$...#xx` packets, Ctrl-C pauses the emulation
+    fn next_packet(&mut self, engine: &mut Engine) -> std::io::Result<Option<String>> {
+        loop {
+            match self.input.first() {
+                None => return Ok(None),
+                Some(0x03) => {
+                    self.input.remove(0);
+                    engine.debugger_mut().pause();
+                    continue;
+                }
+                Some(b'$') => {}
+                Some(_) => {
+                    self.input.remove(0);
+                    continue;
+                }
+            }
+
+            let end = match self.input.iter().position(|byte| *byte == b'#') {
+                Some(end) if end + 2 < self.input.len() => end,
+                _ => return Ok(None),
+            };
+            let packet: Vec<u8> = self.input.drain(..end + 3).collect();
+            let data = &packet[1..end];
+            let checksum = std::str::from_utf8(&packet[end + 1..]).ok().and_then(|checksum| u8::from_str_radix(checksum, 16).ok());
+
+            // a corrupted packet is sent again by the client
+            let stream = self.client.as_mut().unwrap();
+            if checksum != Some(packet_checksum(data)) {
+                stream.write_all(b"-")?;
+                continue;
+            }
+            stream.write_all(b"+")?;
+            return Ok(Some(String::from_utf8_lossy(data).into_owned()));
+        }
+    }
+
+    fn send_packet(&mut self, data: &str) -> std::io::Result<()> {
+        let packet = format!("${}#{:02x}", data, packet_checksum(data.as_bytes()));
+        let stream = self.client.as_mut().unwrap();
+
+        // the client is local, a short wait for room is fine
+        stream.set_nonblocking(false)?;
+        let result = stream.write_all(packet.as_bytes());
+        stream.set_nonblocking(true)?;
+        result
+    }
+
+    fn stop_reply(&self, reason: Break) -> String {
+        match reason {
+            Break::Pause => "S02".to_string(),
+            Break::Watchpoint { index, addr, .. } => {
+                let kind = self.points.iter().find(|point| point.kind >= 2 && point.index == index).map_or(2, |point| point.kind);
+                let name = match kind {
+                    3 => "rwatch",
+                    4 => "awatch",
+                    _ => "watch",
+                };
+                format!("T05{}:{:04x};", name, addr)
+            }
+            _ => "S05".to_string(),
+        }
+    }
+
+    // None when the reply comes later (`c`, `s`) or never (`D`)
+    fn handle_packet(&mut self, engine: &mut Engine, packet: &str) -> Option<String> {
+        let (command, args) = packet.split_at(packet.len().min(1));
+        let reply = match command {
+            "?" => self.stop_reply(engine.debugger().stopped().unwrap_or(Break::Pause)),
+            "g" => {
+                (0..REGISTER_COUNT).map(|register| encode_u16(register_value(engine, register))).collect()
+            }
+            "G" => {
+                let values: Option<Vec<u16>> = (0..REGISTER_COUNT)
+                    .map(|register| args.get(register * 4..register * 4 + 4).and_then(decode_u16))
+                    .collect();
+                match values {
+                    Some(values) => {
+                        for (register, value) in values.into_iter().enumerate() {
+                            set_register_value(engine, register, value);
+                        }
+                        "OK".to_string()
+                    }
+                    None => "E01".to_string(),
+                }
+            }
+            "p" => match usize::from_str_radix(args, 16) {
+                Ok(register) if register < REGISTER_COUNT => encode_u16(register_value(engine, register)),
+                _ => "E01".to_string(),
+            },
+            "P" => {
+                let register = args.split_once('=')
+                    .and_then(|(register, value)| Some((usize::from_str_radix(register, 16).ok()?, decode_u16(value)?)));
+                match register {
+                    Some((register, value)) if register < REGISTER_COUNT => {
+                        set_register_value(engine, register, value);
+                        "OK".to_string()
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            "m" => match parse_addr_len(args) {
+                Some((addr, len)) => {
+                    (0..len).map(|offset| format!("{:02x}", engine.peek(addr.wrapping_add(offset)))).collect()
+                }
+                None => "E01".to_string(),
+            },
+            "M" => {
+                let write = args.split_once(':')
+                    .and_then(|(range, data)| Some((parse_addr_len(range)?, decode_bytes(data)?)));
+                match write {
+                    Some(((addr, len), data)) if data.len() == len as usize => {
+                        // ROM and hardware registers are left alone, writing them has side effects
+                        let poked = data.into_iter().enumerate()
+                            .fold(true, |poked, (offset, byte)| engine.poke(addr.wrapping_add(offset as u16), byte) && poked);
+                        if poked { "OK" } else { "E0E" }.to_string()
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            "Z" | "z" => self.handle_point(engine, command == "Z", args),
+            "c" | "s" => {
+                if let Ok(addr) = u16::from_str_radix(args, 16) {
+                    let mut regs = engine.cpu_regs();
+                    regs.set_pc(addr);
+                    engine.set_cpu_regs(regs);
+                }
+                if command == "c" {
+                    engine.debugger_mut().resume();
+                } else {
+                    engine.debugger_mut().step_into();
+                }
+                self.running = true;
+                return None;
+            }
+            "D" => {
+                self.send_packet("OK").ok();
+                self.detach(engine);
+                return None;
+            }
+            "k" => {
+                self.detach(engine);
+                return None;
+            }
+            "H" => "OK".to_string(),
+            "q" => handle_query(packet),
+            _ => String::new(),
+        };
+        Some(reply)
+    }
+
+    // `Z<kind>,<addr>,<len>` inserts, `z` removes
+    fn handle_point(&mut self, engine: &mut Engine, insert: bool, args: &str) -> String {
+        let mut fields = args.split(',');
+        let point = (|| {
+            let kind: u8 = fields.next()?.parse().ok()?;
+            let addr = u16::from_str_radix(fields.next()?, 16).ok()?;
+            let len = u16::from_str_radix(fields.next()?, 16).ok()?;
+            Some((kind, addr, len.max(1)))
+        })();
+
+        let (kind, addr, len) = match point {
+            Some(point) if point.0 <= 4 => point,
+            Some(_) => return String::new(),
+            None => return "E01".to_string(),
+        };
+
+        if insert {
+            let debugger = engine.debugger_mut();
+            let end = addr.saturating_add(len - 1);
+            let index = match kind {
+                0 | 1 => debugger.add_breakpoint(addr, None),
+                2 => debugger.add_watchpoint(addr, end, Access::Write),
+                3 => debugger.add_watchpoint(addr, end, Access::Read),
+                _ => debugger.add_watchpoint(addr, end, Access::ReadWrite),
+            };
+            self.points.push(Point { kind, addr, len, index });
+        } else {
+            let position = self.points.iter().position(|point| point.kind == kind && point.addr == addr && point.len == len);
+            if let Some(position) = position {
+                remove_point(engine, self.points.remove(position));
+            }
+        }
+        "OK".to_string()
+    }
+}
+
+fn remove_point(engine: &mut Engine, point: Point) {
+    if point.kind <= 1 {
+        engine.debugger_mut().remove_breakpoint(point.index);
+    } else {
+        engine.debugger_mut().remove_watchpoint(point.index);
+    }
+}
+
+fn handle_query(packet: &str) -> String {
+    if packet.starts_with("qSupported") {
+        "PacketSize=1000;qXfer:features:read+".to_string()
+    } else if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+        let (offset, len) = match range.split_once(',') {
+            Some((offset, len)) => (usize::from_str_radix(offset, 16).unwrap_or(0), usize::from_str_radix(len, 16).unwrap_or(0)),
+            None => return "E01".to_string(),
+        };
+        let xml = TARGET_XML.as_bytes();
+        let begin = offset.min(xml.len());
+        let end = (offset + len).min(xml.len());
+        let chunk = String::from_utf8_lossy(&xml[begin..end]);
+        format!("{}{}", if end == xml.len() { 'l' } else { 'm' }, chunk)
+    } else {
+        match packet {
+            "qAttached" => "1".to_string(),
+            "qC" => "QC1".to_string(),
+            "qfThreadInfo" => "m1".to_string(),
+            "qsThreadInfo" => "l".to_string(),
+            _ => String::new(),
+        }
+    }
+}
+
+fn register_value(engine: &Engine, register: usize) -> u16 {
+    let regs = engine.cpu_regs();
+    [regs.af(), regs.bc(), regs.de(), regs.hl(), regs.sp(), regs.pc()][register]
+}
+
+fn set_register_value(engine: &mut Engine, register: usize, value: u16) {
+    let mut regs = engine.cpu_regs();
+    match register {
+        0 => regs.set_af(value),
+        1 => regs.set_bc(value),
+        2 => regs.set_de(value),
+        3 => regs.set_hl(value),
+        4 => regs.set_sp(value),
+        _ => regs.set_pc(value),
+    }
+    engine.set_cpu_regs(regs);
+}
+
+fn packet_checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |checksum, byte| checksum.wrapping_add(*byte))
+}
+
+// Registers go little endian
+fn encode_u16(value: u16) -> String {
+    let bytes = value.to_le_bytes();
+    format!("{:02x}{:02x}", bytes[0], bytes[1])
+}
+
+fn decode_u16(text: &str) -> Option<u16> {
+    let bytes = decode_bytes(text)?;
+    if bytes.len() != 2 {
+        return None;
+    }
+    Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn decode_bytes(text: &str) -> Option<Vec<u8>> {
+    if text.len() % 2 != 0 {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|index| u8::from_str_radix(text.get(index..index + 2)?, 16).ok()).collect()
+}
+
+fn parse_addr_len(text: &str) -> Option<(u16, u16)> {
+    let (addr, len) = text.split_once(',')?;
+    Some((u16::from_str_radix(addr, 16).ok()?, u16::from_str_radix(len, 16).ok()?))
+}
+
+#[test]
+fn gdb_server_test() {
+    use crate::emulator::test_fixture::TempFile;
+
+    let rom = TempFile::blank_rom("gdb");
+    let mut engine = Engine::default();
+    engine.open_rom_file(rom.to_str());
+
+    let mut server = GdbServer::bind(0).unwrap();
+    let mut client = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+    let mut ticks = 0;
+
+    // send a packet, run the emulator until the server answered
+    let mut request = |server: &mut GdbServer, engine: &mut Engine, packet: &str| -> String {
+        let data = format!("${}#{:02x}", packet, packet_checksum(packet.as_bytes()));
+        client.write_all(data.as_bytes()).unwrap();
+        client.set_read_timeout(Some(std::time::Duration::from_millis(10))).unwrap();
+
+        let mut received = Vec::new();
+        loop {
+            server.poll(engine).unwrap();
+            engine.run_next_frame(&mut ticks);
+
+            let mut buffer = [0; 1024];
+            if let Ok(len) = client.read(&mut buffer) {
+                received.extend_from_slice(&buffer[..len]);
+            }
+            let text = String::from_utf8_lossy(&received).into_owned();
+            if let Some(begin) = text.find('$') {
+                if let Some(end) = text[begin..].find('#') {
+                    if text.len() >= begin + end + 3 {
+                        assert!(text.starts_with('+'));
+                        return text[begin + 1..begin + end].to_string();
+                    }
+                }
+            }
+        }
+    };
+
+    assert_eq!("S02", request(&mut server, &mut engine, "?"));
+    assert!(server.is_attached());
+    assert_eq!("000000000000000000000000", request(&mut server, &mut engine, "g"));
+    assert_eq!("31feff", request(&mut server, &mut engine, "m0,3"));
+
+    // the boot ROM sets SP up first, then clears VRAM
+    assert_eq!("OK", request(&mut server, &mut engine, "Z0,3,1"));
+    assert_eq!("S05", request(&mut server, &mut engine, "c"));
+    assert_eq!("feff", request(&mut server, &mut engine, "p4"));
+    assert_eq!("0300", request(&mut server, &mut engine, "p5"));
+    assert_eq!("OK", request(&mut server, &mut engine, "z0,3,1"));
+
+    assert_eq!("S05", request(&mut server, &mut engine, "s"));
+    assert_eq!("0400", request(&mut server, &mut engine, "p5"));
+
+    assert_eq!("OK", request(&mut server, &mut engine, "Z2,9ffe,2"));
+    assert_eq!("T05watch:9fff;", request(&mut server, &mut engine, "c"));
+    assert_eq!("OK", request(&mut server, &mut engine, "z2,9ffe,2"));
+
+    // memory writes skip the watchpoints and the hardware registers
+    assert_eq!("OK", request(&mut server, &mut engine, "Z2,c000,1"));
+    assert_eq!("OK", request(&mut server, &mut engine, "Mc000,2:1234"));
+    assert_eq!("1234", request(&mut server, &mut engine, "mc000,2"));
+    assert_eq!("T05watch:9fff;", request(&mut server, &mut engine, "?"));
+    assert_eq!("OK", request(&mut server, &mut engine, "z2,c000,1"));
+    assert_eq!("E0E", request(&mut server, &mut engine, "Mff40,1:00"));
+    assert_ne!(0, engine.peek(0xFF40));
+    assert_eq!("OK", request(&mut server, &mut engine, "P3=00c0"));
+    assert_eq!(0xC000, engine.cpu_regs().hl());
+    assert_eq!("", request(&mut server, &mut engine, "vMustReplyEmpty"));
+    assert!(request(&mut server, &mut engine, "qXfer:features:read:target.xml:0,1000").starts_with("l<?xml"));
+
+    assert_eq!("OK", request(&mut server, &mut engine, "D"));
+    assert!(!server.is_attached());
+    assert_eq!(None, engine.debugger().stopped());
+}
@@ -0,0 +1,305 @@
+use crate::emulator::Emulator;
+use crate::emulator::capture::DEFAULT_CAPTURE_SCALE;
+use crate::emulator::debugger::{parse_number, Break};
+use crate::emulator::movie::Movie;
+use crate::emulator::serial::SerialSink;
+use crate::options::Boot;
+
+pub const DEFAULT_HEADLESS_FRAMES: u64 = 60 * 60;
+
+// Registers Mooneye test ROMs leave on success before `LD B,B`
+const MOONEYE_PASS: [u8; 6] = [3, 5, 8, 13, 21, 34];
+
+pub const EXIT_PASSED: i32 = 0;
+pub const EXIT_FAILED: i32 = 1;
+pub const EXIT_ERROR: i32 = 2;
+
+const USAGE: &str = "\
+usage: kiwi run --headless <rom> [options]
+  --frames <count>       frames to run at most (default 3600)
+  --serial <text>        pass once the serial output contains the text
+  --serial-fail <text>   fail once the serial output contains the text
+  --pc <addr>            pass once PC reaches the address
+  --mooneye              stop at `LD B,B`, pass when BCDEHL hold 3 5 8 13 21 34
+  --movie <file>         replay a movie, pass when its last frame is the one recorded
+  --boot-rom <file>      boot from a boot ROM dump
+  --skip-boot            start at $0100 in the state the boot ROM leaves
+  --model <model>        dmg0, dmg, mgb, sgb or cgb boot state (default dmg)
+  --screenshot <file>    save the last frame as PNG
+  --record <file>        record the run as GIF (.gif) or raw RGB frames, and WAV
+  --record-audio <file>  record the sound alone as WAV
+  --stems                with --record-audio, a WAV per channel next to it too
+Exits with 0 on pass, 1 on failure or time out, 2 on errors. Without stop
+conditions the run passes after the frame count.";
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Options {
+    pub frames: u64,
+    pub serial_pass: Option<String>,
+    pub serial_fail: Option<String>,
+    pub pc: Option<u16>,
+    pub mooneye: bool,
+    pub movie: Option<String>,
+    pub boot: Boot,
+    pub screenshot: Option<String>,
+    pub record: Option<String>,
+    pub record_audio: Option<String>,
+    pub stems: bool,
+}
+
+impl Options {
+    /// Options and ROM filename of `run --headless`
+    pub fn parse(args: &[String]) -> Result<(String, Options), String> {
+        let mut options = Options { frames: DEFAULT_HEADLESS_FRAMES, ..Options::default() };
+        let mut rom_filename = None;
+        let mut headless = false;
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if options.boot.parse_arg(arg, &mut args)? {
+                continue;
+            }
+            let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+            match arg.as_str() {
+                "--headless" => headless = true,
+                "--frames" => options.frames = value()?.parse().map_err(|_| "--frames needs a count".to_string())?,
+                "--serial" => options.serial_pass = Some(value()?.clone()),
+                "--serial-fail" => options.serial_fail = Some(value()?.clone()),
+                "--pc" => options.pc = Some(parse_number(value()?)?),
+                "--mooneye" => options.mooneye = true,
+                "--movie" => options.movie = Some(value()?.clone()),
+                "--screenshot" => options.screenshot = Some(value()?.clone()),
+                "--record" => options.record = Some(value()?.clone()),
+                "--record-audio" => options.record_audio = Some(value()?.clone()),
+                "--stems" => options.stems = true,
+                _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+                _ => rom_filename = Some(arg.clone()),
+            }
+        }
+
+        if options.stems && options.record_audio.is_none() {
+            return Err("--stems needs --record-audio".to_string());
+        }
+        if !headless {
+            return Err("the window opens without `run`, use `run --headless`".to_string());
+        }
+        Ok((rom_filename.ok_or("missing ROM filename")?, options))
+    }
+
+    fn has_conditions(&self) -> bool {
+        self.serial_pass.is_some() || self.serial_fail.is_some() || self.pc.is_some() || self.mooneye || self.movie.is_some()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome {
+    Passed(String),
+    Failed(String),
+}
+
+/// Run without video or audio output until a stop condition is met
+///
+/// The serial port output is captured for the serial conditions, the
+/// debugger stops at the PC or at `LD B,B` for the others. A movie ends the
+/// run after its last frame.
+pub fn run_headless(emulator: &mut Emulator, options: &Options) -> Outcome {
+    emulator.set_rewind(0, 0);
+
+    // final frame hash of the movie, if it has one
+    let movie_end = match options.movie.as_ref() {
+        Some(filename) => {
+            let played = Movie::load(filename).and_then(|movie| {
+                let final_frame = movie.final_frame;
+                emulator.play_movie(movie).map(|_| final_frame)
+            });
+            match played {
+                Ok(final_frame) => Some(final_frame),
+                Err(error) => return Outcome::Failed(format!("movie {}: {}", filename, error)),
+            }
+        }
+        None => None,
+    };
+
+    emulator.set_serial_sink(Some(SerialSink::Buffer(Vec::new())));
+
+    let debugger = emulator.engine_mut().debugger_mut();
+    debugger.set_enabled(options.pc.is_some() || options.mooneye);
+    debugger.set_break_on_ld_b_b(options.mooneye);
+    if let Some(pc) = options.pc {
+        debugger.add_breakpoint(pc, None);
+    }
+
+    // frames stopped by the debugger count once they're completed
+    let mut serial_length = 0;
+    let mut frame = 0;
+    while frame < options.frames {
+        let frame_complete = emulator.run_next_frame();
+        if !frame_complete {
+            let engine = emulator.engine_mut();
+            match engine.debugger().stopped() {
+                // the boot ROM runs over the cartridge at $0000-$00FF
+                Some(Break::Breakpoint(_)) if !engine.is_booting() => return Outcome::Passed(format!("PC reached ${:04X} in frame {}", engine.cpu_regs().pc(), frame)),
+                Some(Break::SoftwareBreakpoint) => {
+                    let regs = engine.cpu_regs();
+                    let registers = [regs.b(), regs.c(), regs.d(), regs.e(), regs.h(), regs.l()];
+                    let message = format!("LD B,B in frame {} with BCDEHL {:?}", frame, registers);
+                    return match registers == MOONEYE_PASS {
+                        true => Outcome::Passed(message),
+                        false => Outcome::Failed(message),
+                    };
+                }
+                _ => engine.debugger_mut().resume(),
+            }
+        }
+
+        let output = emulator.serial_output();
+        if output.len() != serial_length {
+            serial_length = output.len();
+            let text = String::from_utf8_lossy(output);
+            if let Some(fail) = options.serial_fail.as_ref().filter(|fail| text.contains(fail.as_str())) {
+                return Outcome::Failed(format!("serial output `{}` in frame {}", fail, frame));
+            }
+            if let Some(pass) = options.serial_pass.as_ref().filter(|pass| text.contains(pass.as_str())) {
+                return Outcome::Passed(format!("serial output `{}` in frame {}", pass, frame));
+            }
+        }
+
+        if let Some(final_frame) = movie_end.filter(|_| !emulator.is_movie_playing()) {
+            return match final_frame {
+                Some(hash) if hash != emulator.frame_hash() => Outcome::Failed(format!("movie desynchronized, frame {} differs", frame)),
+                _ => Outcome::Passed(format!("movie replayed up to frame {}", frame)),
+            };
+        }
+
+        if frame_complete {
+            frame += 1;
+        }
+    }
+
+    match options.has_conditions() {
+        true => Outcome::Failed(format!("timed out after {} frames", options.frames)),
+        false => Outcome::Passed(format!("ran {} frames", options.frames)),
+    }
+}
+
+/// `kiwi run --headless <rom> [options]`, returns the exit code
+pub fn run(args: &[String]) -> i32 {
+    let (rom_filename, options) = match Options::parse(args) {
+        Ok(parsed) => parsed,
+        Err(error) => {
+            eprintln!("kiwi run: {}\n{}", error, USAGE);
+            return EXIT_ERROR;
+        }
+    };
+    if !std::path::Path::new(&rom_filename).exists() {
+        eprintln!("kiwi run: {} not found", rom_filename);
+        return EXIT_ERROR;
+    }
+
+    // stdout is left to the report and the serial output
+    let mut emulator = Emulator::default();
+    emulator.set_audio_register_log(false);
+    if let Err(error) = options.boot.start(&mut emulator, &rom_filename) {
+        eprintln!("kiwi run: {}", error);
+        return EXIT_ERROR;
+    }
+    if let Some(filename) = options.record.as_ref() {
+        if let Err(error) = emulator.start_video_recording(filename, DEFAULT_CAPTURE_SCALE) {
+            eprintln!("kiwi run: {}: {}", filename, error);
+            return EXIT_ERROR;
+        }
+    }
+    if let Some(filename) = options.record_audio.as_ref() {
+        if let Err(error) = emulator.start_audio_recording(filename, options.stems) {
+            eprintln!("kiwi run: {}: {}", filename, error);
+            return EXIT_ERROR;
+        }
+    }
+    let outcome = run_headless(&mut emulator, &options);
+    if let Err(error) = emulator.stop_video_recording() {
+        eprintln!("kiwi run: recording failed: {}", error);
+    }
+    if let Err(error) = emulator.stop_audio_recording() {
+        eprintln!("kiwi run: audio recording failed: {}", error);
+    }
+    if let Some(filename) = options.screenshot.as_ref() {
+        if let Err(error) = emulator.save_screenshot(filename, DEFAULT_CAPTURE_SCALE) {
+            eprintln!("kiwi run: {}: {}", filename, error);
+        }
+    }
+
+    let output = emulator.serial_output();
+    if !output.is_empty() {
+        println!("{}", String::from_utf8_lossy(output));
+    }
+    match outcome {
+        Outcome::Passed(message) => {
+            println!("Passed: {}", message);
+            EXIT_PASSED
+        }
+        Outcome::Failed(message) => {
+            println!("Failed: {}", message);
+            EXIT_FAILED
+        }
+    }
+}
+
+#[test]
+fn headless_test() {
+    use crate::emulator::test_fixture::{bootable_rom, TempFile};
+
+    // the Mooneye registers, `P` out of the serial port and `LD B,B`
+    let mut rom = bootable_rom();
+    rom[0x0150..0x016B].copy_from_slice(&[
+        0x06, 3, 0x0E, 5, 0x16, 8, 0x1E, 13, 0x26, 21, 0x2E, 34,    // LD B,3 ... LD L,34
+        0x3E, b'P', 0xE0, 0x01, 0x3E, 0x81, 0xE0, 0x02,             // serial transfer of `P`
+        0x00, 0x00, 0x00,                                           // $0164
+        0x40,                                                       // $0167 LD B,B
+        0x00,
+        0x18, 0xFE,                                                 // $0169 JR $0169
+    ]);
+    let rom = TempFile::rom("headless", &rom);
+
+    let args = |text: &str| -> Vec<String> {
+        format!("--headless {} {}", rom.to_str(), text).split_whitespace().map(str::to_string).collect()
+    };
+    let run = |text: &str| {
+        let (rom_filename, options) = Options::parse(&args(text)).unwrap();
+        let mut emulator = Emulator::default();
+        options.boot.start(&mut emulator, &rom_filename).unwrap();
+        run_headless(&mut emulator, &options)
+    };
+
+    assert!(matches!(run("--mooneye --frames 600"), Outcome::Passed(_)));
+    assert_eq!(Outcome::Passed("LD B,B in frame 0 with BCDEHL [3, 5, 8, 13, 21, 34]".to_string()), run("--mooneye --skip-boot --frames 1"));
+    assert!(matches!(run("--pc $0164 --frames 600"), Outcome::Passed(_)));
+    assert!(matches!(run("--serial P --frames 600"), Outcome::Passed(_)));
+    assert!(matches!(run("--serial-fail P --serial Q --frames 600"), Outcome::Failed(_)));
+    assert_eq!(Outcome::Failed("timed out after 2 frames".to_string()), run("--pc $0164 --frames 2"));
+    assert_eq!(Outcome::Passed("ran 2 frames".to_string()), run("--frames 2"));
+
+    // the boot ROM loop at $0007 neither matches nor eats up the frames
+    assert_eq!(Outcome::Failed("timed out after 2 frames".to_string()), run("--pc $0007 --frames 2"));
+    assert!(matches!(run("--pc $0007 --serial P --frames 600"), Outcome::Passed(message) if message.starts_with("serial output")));
+
+    // a movie passes when it ends on the frame recorded
+    let movie_file = rom.with_extension("kmv");
+    let mut emulator = Emulator::default();
+    emulator.open_rom_file(rom.to_str());
+    emulator.start_movie_recording().unwrap();
+    for _ in 0..3 {
+        emulator.run_next_frame();
+    }
+    let mut movie = emulator.stop_movie_recording().unwrap();
+    movie.save(movie_file.path()).unwrap();
+    assert_eq!(Outcome::Passed("movie replayed up to frame 2".to_string()), run(&format!("--movie {}", movie_file.to_str())));
+    movie.final_frame = Some([0; 16]);
+    movie.save(movie_file.path()).unwrap();
+    assert!(matches!(run(&format!("--movie {}", movie_file.to_str())), Outcome::Failed(_)));
+
+    assert!(Options::parse(&["rom.gb".to_string()]).is_err());
+    assert!(Options::parse(&args("--frames")).is_err());
+    let (_, options) = Options::parse(&args("--record-audio run.wav --stems")).unwrap();
+    assert_eq!((Some("run.wav"), true), (options.record_audio.as_deref(), options.stems));
+    assert!(Options::parse(&args("--stems")).is_err());
+}
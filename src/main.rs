@@ -2,44 +2,149 @@
 extern crate bitflags;
 extern crate sdl2;
 
+mod config;
+mod disasm;
 mod emulator;
+mod gdb;
+mod headless;
+mod memory_viewer;
+mod options;
+mod repl;
+#[cfg(test)]
+mod test_roms;
+mod vram_viewer;
 
+use config::Ini;
 use emulator::Emulator;
+use gdb::GdbServer;
+use memory_viewer::MemoryViewer;
+use options::{Link, Options, USAGE};
+use repl::Repl;
+use vram_viewer::VramViewer;
+use emulator::rewind::{DEFAULT_REWIND_BUDGET, DEFAULT_REWIND_INTERVAL};
+use emulator::joypad::bindings::Bindings;
+use emulator::model::Model;
+use emulator::movie::Movie;
+use emulator::serial::SerialSink;
+use emulator::trace::Tracer;
+use emulator::serial::link::{CableLink, LinkPort, LoopbackLink, StreamLink};
+use emulator::sound::blip::Quality;
 use emulator::ppu::SCREEN_PIXEL_WIDTH;
 use emulator::ppu::SCREEN_PIXEL_HEIGHT;
-use sdl2::audio::{AudioCallback, AudioSpecDesired, AudioQueue};
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
+use sdl2::audio::{AudioSpecDesired, AudioQueue};
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::{Keycode, Mod};
 use sdl2::pixels::PixelFormatEnum;
 use sdl2::render::TextureAccess;
+use std::io::Write;
 use std::time::{Instant, Duration};
 
 const FRAME_DURATION: Duration = Duration::from_nanos(1_000_000_000 / 60);
 
-struct SquareWaveChannel {
-    amplitude: i8,
-    phase_inc: f32,
-    phase: f32,
-}
+// F1-F8 load the save state slot, with Ctrl they save it, Shift is the B button
+const STATE_SLOT_KEYS: [Keycode; 8] = [
+    Keycode::F1, Keycode::F2, Keycode::F3, Keycode::F4,
+    Keycode::F5, Keycode::F6, Keycode::F7, Keycode::F8,
+];
+
+// Breaks into the debugger
+const DEBUGGER_KEY: Keycode = Keycode::F10;
+
+// Opens and closes the VRAM viewer windows
+const VRAM_VIEWER_KEY: Keycode = Keycode::F11;
+
+// Opens and closes the memory viewer, which takes the keys while focused
+const MEMORY_VIEWER_KEY: Keycode = Keycode::F12;
+
+// Saves a screenshot, with Shift starts and stops a GIF recording
+const CAPTURE_KEY: Keycode = Keycode::PrintScreen;
 
-impl AudioCallback for SquareWaveChannel {
-    type Channel = i8;
+// Starts and stops a WAV recording of the sound, with a file per channel
+const AUDIO_RECORD_KEY: Keycode = Keycode::F9;
 
-    fn callback(&mut self, samples: &mut [i8]) {
-        let length = samples.len() / 2;
-        for i in 0..length {
-            let sample = self.amplitude * if self.phase < 0.5 { 1 } else { -1 };
+// Held down, the emulation runs backward frame by frame
+const REWIND_KEY: Keycode = Keycode::Backquote;
 
-            self.phase += self.phase_inc;
-            self.phase %= 1.0;
+// 1-9 turn the first nine cheats on and off
+const CHEAT_KEYS: [Keycode; 9] = [
+    Keycode::Num1, Keycode::Num2, Keycode::Num3, Keycode::Num4, Keycode::Num5,
+    Keycode::Num6, Keycode::Num7, Keycode::Num8, Keycode::Num9,
+];
 
-            samples[i * 2]     = sample; // left
-            samples[i * 2 + 1] = sample; // right
+fn state_slot_filename(options: &Options, slot: usize) -> String {
+    options.save_filename(&format!("ss{}", slot)).to_string_lossy().into_owned()
+}
+
+// Configuration value, the default when missing or invalid
+fn config_value<T: std::str::FromStr + std::fmt::Display>(ini: &Ini, section: &str, key: &str, default: T) -> T {
+    match ini.get(section, key).map(str::parse) {
+        Some(Ok(value)) => value,
+        Some(Err(_)) => {
+            eprintln!("Invalid [{}] {} in the configuration, using {}", section, key, default);
+            default
         }
+        None => default,
     }
 }
 
+// Link cable partner of `--link-listen`, `--link-connect` or `--link-loopback`
+fn open_serial_link(link: &Link) -> std::io::Result<Box<dyn LinkPort>> {
+    Ok(match link {
+        Link::Loopback => Box::new(LoopbackLink::default()),
+        Link::Listen(addr) => match addr.strip_prefix("unix:") {
+            #[cfg(unix)]
+            Some(path) => Box::new(CableLink::listen_unix(path)?),
+            #[cfg(not(unix))]
+            Some(_) => return Err(unix_sockets_unsupported()),
+            None => Box::new(CableLink::listen(addr)?),
+        },
+        Link::Connect(addr) => match addr.strip_prefix("unix:") {
+            #[cfg(unix)]
+            Some(path) => Box::new(StreamLink::connect_unix(path)?),
+            #[cfg(not(unix))]
+            Some(_) => return Err(unix_sockets_unsupported()),
+            None => Box::new(StreamLink::connect(addr)?),
+        },
+    })
+}
+
+#[cfg(not(unix))]
+fn unix_sockets_unsupported() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::Other, "unix:<path> links need a Unix system")
+}
+
 fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("disasm") {
+        if let Err(error) = disasm::run(&args[2..]) {
+            eprintln!("kiwi disasm: {}", error);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if args.get(1).map(String::as_str) == Some("run") {
+        std::process::exit(headless::run(&args[2..]));
+    }
+
+    let options = match Options::parse(&args[1..]) {
+        Ok(options) if options.help => {
+            println!("{}", USAGE);
+            return;
+        }
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("kiwi: {}\n{}", error, USAGE);
+            std::process::exit(2);
+        }
+    };
+    if let Some(savedir) = options.savedir.as_ref() {
+        if let Err(error) = std::fs::create_dir_all(savedir) {
+            eprintln!("kiwi: {}: {}", savedir.display(), error);
+            std::process::exit(1);
+        }
+    }
+    let frame_duration = FRAME_DURATION.div_f64(options.speed);
+
     let sdl_context = sdl2::init().unwrap();
 
     let audio_subsystem = sdl_context.audio().unwrap();
@@ -49,37 +154,34 @@ fn main() {
         samples: Some(2048),
     };
 
-    let mut channels: [AudioQueue<i8>; 4] = [
+    let mut channels: [AudioQueue<i16>; 4] = [
         audio_subsystem.open_queue(None, &desired_spec).unwrap(),
         audio_subsystem.open_queue(None, &desired_spec).unwrap(),
         audio_subsystem.open_queue(None, &desired_spec).unwrap(),
         audio_subsystem.open_queue(None, &desired_spec).unwrap(),
     ];
 
-    channels[0].resume();
-    channels[1].resume();
-    channels[2].resume();
-    channels[3].resume();
+    if !options.mute {
+        for channel in channels.iter() {
+            channel.resume();
+        }
+    }
 
-    /*
-    let mut channel1_wave = SquareWaveChannel {
-        amplitude: 16,
-        phase_inc: 100.0 / channel1.spec().freq as f32,
-        phase: 0.0,
-    };
-    let mut buffer: [i8; 8192] = [0; 8192];
-    */
+    // the controllers plugged in at startup come as added events too
+    let controller_subsystem = sdl_context.game_controller().unwrap();
+    let mut controllers = Vec::new();
 
     let video_subsystem = sdl_context.video().unwrap();
 
-    let scale = 4;
-    let width = (SCREEN_PIXEL_WIDTH * scale) as u32;
-    let height = (SCREEN_PIXEL_HEIGHT * scale) as u32;
+    let width = SCREEN_PIXEL_WIDTH as u32 * options.scale;
+    let height = SCREEN_PIXEL_HEIGHT as u32 * options.scale;
 
-    let window = video_subsystem.window("Kiwi", width, height)
-        .position_centered()
-        .build()
-        .unwrap();
+    let mut window = video_subsystem.window("Kiwi", width, height);
+    window.position_centered();
+    if options.fullscreen {
+        window.fullscreen_desktop();
+    }
+    let window = window.build().unwrap();
 
     let mut canvas = window.into_canvas().build().unwrap();
 
@@ -91,43 +193,290 @@ fn main() {
         SCREEN_PIXEL_HEIGHT as u32).unwrap();
 
     let mut emulator = Emulator::default();
-    emulator.open_rom_file("/Users/emiliano/Downloads/Tetris/Tetris.gb");
+    emulator.set_audio_sample_rate(channels[0].spec().freq as u32);
+    let mut audio_quality = Quality::default();
+    if std::path::Path::new(&options.config).exists() {
+        let ini = Ini::load(&options.config).unwrap_or_else(|error| {
+            println!("Configuration {} ignored: {}", options.config, error);
+            Ini::default()
+        });
+        match Bindings::from_ini(&ini) {
+            Ok(bindings) => emulator.set_input_bindings(bindings),
+            Err(error) => println!("Input bindings in {} ignored, using the defaults: {}", options.config, error),
+        }
+        // [audio] quality = fast, low, medium or high
+        audio_quality = config_value(&ini, "audio", "quality", audio_quality);
+        if options.rewind {
+            // [rewind] interval = frames between snapshots, budget = megabytes, 0 disables it
+            let interval = config_value(&ini, "rewind", "interval", DEFAULT_REWIND_INTERVAL);
+            let budget = config_value(&ini, "rewind", "budget", DEFAULT_REWIND_BUDGET / (1024 * 1024));
+            emulator.set_rewind(interval, budget.saturating_mul(1024 * 1024));
+        }
+    } else if options.rewind {
+        emulator.set_rewind(DEFAULT_REWIND_INTERVAL, DEFAULT_REWIND_BUDGET);
+    }
+    emulator.set_audio_quality(options.audio_quality.unwrap_or(audio_quality));
+    if options.boot.model != Model::Dmg {
+        println!("Only the {} boot state is emulated, the hardware is a DMG", options.boot.model.name());
+    }
+    if let Err(error) = options.boot.start(&mut emulator, &options.rom_filename) {
+        eprintln!("kiwi: {}", error);
+        std::process::exit(1);
+    }
+    if let Some(filename) = options.movie_play.as_ref() {
+        if let Err(error) = Movie::load(filename).and_then(|movie| emulator.play_movie(movie)) {
+            eprintln!("kiwi: movie {}: {}", filename, error);
+            std::process::exit(1);
+        }
+    }
+    if let Some(filename) = options.movie_record.as_ref() {
+        if let Err(error) = emulator.start_movie_recording() {
+            eprintln!("kiwi: movie {}: {}", filename, error);
+            std::process::exit(1);
+        }
+    }
+
+    // `--sym <file>` when the symbols aren't next to the ROM
+    if let Some(filename) = options.sym.as_ref() {
+        if let Err(error) = emulator.load_symbols(filename) {
+            println!("Failed to load symbols {}: {}", filename, error);
+        }
+    }
+
+    // `--trace <file>` logs the executed instructions
+    if let Some(filename) = options.trace.as_ref() {
+        match Tracer::create(filename, options.trace_format) {
+            Ok(mut tracer) => {
+                tracer.set_range(options.trace_range.clone());
+                tracer.set_skip_boot(options.trace_skip_boot);
+                emulator.set_tracer(Some(tracer));
+            }
+            Err(error) => {
+                eprintln!("kiwi: trace {}: {}", filename, error);
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(link) = options.link.as_ref() {
+        match open_serial_link(link) {
+            Ok(link) => emulator.connect_serial_link(link),
+            Err(error) => {
+                eprintln!("kiwi: link cable: {}", error);
+                std::process::exit(1);
+            }
+        }
+    }
+    if options.serial_stdout {
+        emulator.set_serial_sink(Some(SerialSink::Callback(Box::new(|byte| {
+            let mut stdout = std::io::stdout();
+            stdout.write_all(&[byte]).unwrap();
+            stdout.flush().unwrap();
+        }))));
+    }
 
     let mut frame_begin_timestamp = Instant::now();
     let mut frame_overslept_duration = Duration::from_nanos(0);
 
     let mut event_pump = sdl_context.event_pump().unwrap();
     let mut frame_counter: u64 = 0;
+    let mut rewinding = false;
+    let mut vram_viewer: Option<VramViewer> = None;
+    let mut memory_viewer: Option<MemoryViewer> = None;
+
+    // `--debug` starts paused in the terminal debugger
+    let mut repl = Repl::default();
+    if options.debug {
+        emulator.engine_mut().debugger_mut().set_enabled(true);
+        emulator.engine_mut().debugger_mut().pause();
+    }
+
+    // `--gdb [port]` waits for GDB on a loopback port instead
+    let mut gdb = match options.gdb {
+        Some(port) => match GdbServer::bind(port) {
+            Ok(server) => {
+                println!("GDB server listening on {}", server.local_addr().unwrap());
+                Some(server)
+            }
+            Err(error) => {
+                eprintln!("kiwi: GDB server on port {} failed: {}", port, error);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
 
     'gameloop: loop {
         for event in event_pump.poll_iter() {
+            if let (Some(viewer), Event::KeyDown { window_id, keycode, .. }) = (memory_viewer.as_mut(), &event) {
+                if *window_id == viewer.window_id() && *keycode != Some(MEMORY_VIEWER_KEY) {
+                    viewer.process_event(&event, emulator.engine_mut());
+                    continue;
+                }
+            }
             emulator.process_event(&event);
             match event {
                 Event::KeyDown { keycode: Some(Keycode::Escape), .. } | Event::Quit {..} => break 'gameloop,
+                Event::Window { window_id, win_event: WindowEvent::Close, .. } => {
+                    if vram_viewer.as_ref().is_some_and(|viewer| viewer.owns_window(window_id)) {
+                        vram_viewer = None;
+                    } else if memory_viewer.as_ref().is_some_and(|viewer| viewer.window_id() == window_id) {
+                        memory_viewer = None;
+                    } else {
+                        break 'gameloop;
+                    }
+                }
+                Event::KeyDown { keycode: Some(VRAM_VIEWER_KEY), repeat: false, .. } => {
+                    vram_viewer = match vram_viewer {
+                        Some(_) => None,
+                        None => VramViewer::open(&video_subsystem)
+                            .map_err(|error| println!("Failed to open the VRAM viewer: {}", error))
+                            .ok(),
+                    };
+                }
+                Event::KeyDown { keycode: Some(MEMORY_VIEWER_KEY), repeat: false, .. } => {
+                    memory_viewer = match memory_viewer {
+                        Some(_) => None,
+                        None => MemoryViewer::open(&video_subsystem)
+                            .map_err(|error| println!("Failed to open the memory viewer: {}", error))
+                            .ok(),
+                    };
+                }
+                Event::ControllerDeviceAdded { which, .. } => {
+                    if let Ok(controller) = controller_subsystem.open(which) {
+                        println!("Controller {} connected", controller.name());
+                        controllers.push(controller);
+                    }
+                }
+                Event::ControllerDeviceRemoved { which, .. } => {
+                    controllers.retain(|controller| controller.instance_id() != which);
+                }
+                Event::KeyDown { keycode: Some(keycode), keymod, repeat: false, .. } if STATE_SLOT_KEYS.contains(&keycode) => {
+                    let slot = STATE_SLOT_KEYS.iter().position(|key| *key == keycode).unwrap() + 1;
+                    let filename = state_slot_filename(&options, slot);
+                    if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD) {
+                        match emulator.save_state_file(&filename) {
+                            Ok(()) => println!("State saved to slot {}", slot),
+                            Err(error) => println!("State save to slot {} failed: {}", slot, error),
+                        }
+                    } else {
+                        match emulator.load_state_file(&filename) {
+                            Ok(()) => println!("State loaded from slot {}", slot),
+                            Err(error) => println!("State load from slot {} failed: {}", slot, error),
+                        }
+                    }
+                }
+                Event::KeyDown { keycode: Some(keycode), repeat: false, .. } if CHEAT_KEYS.contains(&keycode) => {
+                    let index = CHEAT_KEYS.iter().position(|key| *key == keycode).unwrap();
+                    let cheat = emulator.engine().cheats().iter().nth(index).map(|cheat| (cheat.name.clone(), !cheat.enabled));
+                    match cheat {
+                        Some((name, enabled)) => {
+                            emulator.engine_mut().set_cheat_enabled(index, enabled);
+                            println!("Cheat {} {} {}", index + 1, if enabled { "on" } else { "off" }, name);
+                        }
+                        None => println!("No cheat {}", index + 1),
+                    }
+                }
+                Event::KeyDown { keycode: Some(DEBUGGER_KEY), repeat: false, .. } => {
+                    emulator.engine_mut().debugger_mut().set_enabled(true);
+                    emulator.engine_mut().debugger_mut().pause();
+                }
+                Event::KeyDown { keycode: Some(CAPTURE_KEY), keymod, repeat: false, .. } => {
+                    if !keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                        let filename = options.capture_filename(&format!("kiwi-{}.png", frame_counter));
+                        match emulator.save_screenshot(&filename, options.capture_scale) {
+                            Ok(()) => println!("Screenshot saved to {}", filename),
+                            Err(error) => println!("Screenshot failed: {}", error),
+                        }
+                    } else if emulator.is_video_recording() {
+                        match emulator.stop_video_recording() {
+                            Ok(()) => println!("Video recording stopped"),
+                            Err(error) => println!("Video recording failed: {}", error),
+                        }
+                    } else {
+                        let filename = options.capture_filename(&format!("kiwi-{}.gif", frame_counter));
+                        match emulator.start_video_recording(&filename, options.capture_scale) {
+                            Ok(()) => println!("Video recording {}", filename),
+                            Err(error) => println!("Video recording {} failed: {}", filename, error),
+                        }
+                    }
+                }
+                Event::KeyDown { keycode: Some(REWIND_KEY), repeat, .. } => {
+                    if emulator.is_movie_recording() || emulator.is_movie_playing() {
+                        if !repeat {
+                            println!("Rewinding stops while a movie records or plays");
+                        }
+                    } else if options.rewind {
+                        rewinding = true;
+                    } else if !repeat {
+                        println!("Rewinding needs --rewind");
+                    }
+                }
+                Event::KeyUp { keycode: Some(REWIND_KEY), .. } => rewinding = false,
+                Event::KeyDown { keycode: Some(AUDIO_RECORD_KEY), repeat: false, .. } => {
+                    if emulator.is_audio_recording() {
+                        match emulator.stop_audio_recording() {
+                            Ok(()) => println!("Audio recording stopped"),
+                            Err(error) => println!("Audio recording failed: {}", error),
+                        }
+                    } else {
+                        let filename = options.capture_filename(&format!("kiwi-{}.wav", frame_counter));
+                        match emulator.start_audio_recording(&filename, true) {
+                            Ok(()) => println!("Audio recording {}", filename),
+                            Err(error) => println!("Audio recording {} failed: {}", filename, error),
+                        }
+                    }
+                }
                 _ => {}
             }
         }
 
-        emulator.run_next_frame();
+        if let Some(gdb) = gdb.as_mut() {
+            if let Err(error) = gdb.poll(emulator.engine_mut()) {
+                println!("GDB connection failed: {}", error);
+            }
+        }
+
+        // the terminal debugger takes the stops GDB isn't attached for
+        let gdb_attached = gdb.as_ref().is_some_and(GdbServer::is_attached);
+        if !gdb_attached && emulator.engine().debugger().stopped().is_some() && !repl.run(&mut emulator) {
+            break 'gameloop;
+        }
+
+        let frame_complete = if rewinding {
+            emulator.rewind_frame();
+            true
+        } else {
+            emulator.run_next_frame()
+        };
         emulator.blit_frame_to_texture(&mut texture);
-        emulator.enqueue_audio_samples(&mut channels);
-        frame_counter += 1;
+        if frame_complete {
+            frame_counter += 1;
+        }
+        if frame_complete && !options.mute {
+            emulator.enqueue_audio_samples(&mut channels);
+        }
 
         canvas.clear();
         canvas.copy(&texture, None, None).unwrap();
         canvas.present();
 
-        /*
-        if channel1.size() < buffer.len() as u32 {
-            channel1_wave.callback(&mut buffer);
-            channel1.queue(&buffer);
+        if let Some(viewer) = vram_viewer.as_mut() {
+            if let Err(error) = viewer.update(emulator.engine().ppu()) {
+                println!("VRAM viewer closed: {}", error);
+                vram_viewer = None;
+            }
+        }
+        if let Some(viewer) = memory_viewer.as_mut() {
+            if let Err(error) = viewer.update(emulator.engine()) {
+                println!("Memory viewer closed: {}", error);
+                memory_viewer = None;
+            }
         }
-        */
 
         let frame_complete_timestamp = Instant::now();
         let frame_busy_duration = frame_complete_timestamp - frame_begin_timestamp;
 
-        match FRAME_DURATION.checked_sub(frame_busy_duration + frame_overslept_duration) {
+        match frame_duration.checked_sub(frame_busy_duration + frame_overslept_duration) {
             Some(frame_wait_duration) => {
                 std::thread::sleep(frame_wait_duration);
                 frame_begin_timestamp = Instant::now();
@@ -140,4 +489,16 @@ fn main() {
             }
         }
     }
+
+    // a GIF needs its trailer
+    if let Err(error) = emulator.stop_video_recording() {
+        println!("Video recording failed: {}", error);
+    }
+
+    if let (Some(filename), Some(movie)) = (options.movie_record.as_ref(), emulator.stop_movie_recording()) {
+        match movie.save(filename) {
+            Ok(()) => println!("Recorded {} frames to {}", movie.frames.len(), filename),
+            Err(error) => println!("Movie recording failed: {}", error),
+        }
+    }
 }
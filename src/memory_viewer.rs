@@ -0,0 +1,187 @@
+use std::ops::RangeInclusive;
+
+use crate::emulator::engine::Engine;
+use crate::emulator::mmu::Memory;
+use crate::emulator::ppu::viewer::Image;
+use crate::emulator::search::{Region, REGIONS};
+use crate::vram_viewer::{draw_text, ImageWindow, BACKGROUND_COLOR, GLYPH_ADVANCE, GLYPH_HEIGHT, TEXT_COLOR};
+
+use sdl2::event::Event;
+use sdl2::keyboard::{Keycode, Mod};
+use sdl2::pixels::Color;
+use sdl2::VideoSubsystem;
+
+const SCALE: usize = 3;
+
+const BYTES_PER_ROW: u16 = 16;
+const ROWS: u16 = 16;
+const ROW_HEIGHT: usize = GLYPH_HEIGHT + 2;
+
+// `C000 00 11 22 ...` under a header line
+const WINDOW_WIDTH: usize = 4 + (5 + 3 * BYTES_PER_ROW as usize) * GLYPH_ADVANCE;
+const WINDOW_HEIGHT: usize = 2 + ROW_HEIGHT * (ROWS as usize + 1);
+
+const CURSOR_COLOR: Color = Color::RGB(0x30, 0x60, 0xE0);
+const FROZEN_COLOR: Color = Color::RGB(0x40, 0xC0, 0xE0);
+
+/// Memory Viewer Window
+///
+/// Live hex dump of one memory region at a time, editable in place.
+/// - arrows move the cursor, Page Up/Down move it by a page
+/// - Tab and Shift+Tab switch region
+/// - two hex digits write a byte, Backspace drops a typed digit
+/// - Space freezes the byte under the cursor to its value, or unfreezes it
+pub struct MemoryViewer {
+    window: ImageWindow,
+    region: usize,
+    cursor: u16,
+
+    // First row shown
+    top: u16,
+
+    // High nibble typed, waiting for the low one
+    digit: Option<u8>,
+}
+
+impl MemoryViewer {
+    pub fn open(video_subsystem: &VideoSubsystem) -> Result<Self, String> {
+        let region = REGIONS.iter().position(|region| *region == Region::WorkRam).unwrap();
+
+        Ok(Self {
+            window: ImageWindow::open(video_subsystem, "Kiwi Memory", WINDOW_WIDTH, WINDOW_HEIGHT, SCALE)?,
+            region,
+            cursor: *REGIONS[region].range().start(),
+            top: *REGIONS[region].range().start(),
+            digit: None,
+        })
+    }
+
+    pub fn window_id(&self) -> u32 {
+        self.window.window_id()
+    }
+
+    /// Keys pressed while the window has the focus
+    pub fn process_event(&mut self, event: &Event, engine: &mut Engine) {
+        let (keycode, keymod) = match event {
+            Event::KeyDown { keycode: Some(keycode), keymod, .. } => (*keycode, *keymod),
+            _ => return,
+        };
+
+        let range = REGIONS[self.region].range();
+        let page = BYTES_PER_ROW * ROWS;
+        let move_cursor = |cursor: u16, offset: i32| -> u16 {
+            (cursor as i32 + offset).clamp(*range.start() as i32, *range.end() as i32) as u16
+        };
+
+        match keycode {
+            Keycode::Left => self.cursor = move_cursor(self.cursor, -1),
+            Keycode::Right => self.cursor = move_cursor(self.cursor, 1),
+            Keycode::Up => self.cursor = move_cursor(self.cursor, -(BYTES_PER_ROW as i32)),
+            Keycode::Down => self.cursor = move_cursor(self.cursor, BYTES_PER_ROW as i32),
+            Keycode::PageUp => self.cursor = move_cursor(self.cursor, -(page as i32)),
+            Keycode::PageDown => self.cursor = move_cursor(self.cursor, page as i32),
+            Keycode::Tab => {
+                self.region = match keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                    true => (self.region + REGIONS.len() - 1) % REGIONS.len(),
+                    false => (self.region + 1) % REGIONS.len(),
+                };
+                self.cursor = *REGIONS[self.region].range().start();
+            }
+            Keycode::Backspace => self.digit = None,
+            Keycode::Space => {
+                if !engine.unfreeze(self.cursor) {
+                    let value = engine.peek(self.cursor);
+                    engine.freeze(self.cursor, value);
+                }
+            }
+            _ => {
+                let digit = match keycode.name().chars().next().and_then(|digit| digit.to_digit(16)) {
+                    Some(digit) if keycode.name().len() == 1 => digit as u8,
+                    _ => return,
+                };
+                match self.digit.take() {
+                    None => self.digit = Some(digit),
+                    Some(high) => {
+                        let value = high << 4 | digit;
+                        if engine.is_frozen(self.cursor) {
+                            engine.freeze(self.cursor, value);
+                        } else {
+                            engine.write(self.cursor, value);
+                        }
+                        self.cursor = move_cursor(self.cursor, 1);
+                    }
+                }
+                return;
+            }
+        }
+        self.digit = None;
+    }
+
+    pub fn update(&mut self, engine: &Engine) -> Result<(), String> {
+        let range = REGIONS[self.region].range();
+        self.top = scroll(self.top, self.cursor, &range);
+
+        let mut image = Image::new(WINDOW_WIDTH, WINDOW_HEIGHT);
+        image.fill(0, 0, image.width, image.height, BACKGROUND_COLOR);
+
+        let value = engine.peek(self.cursor);
+        let header = match self.digit {
+            Some(digit) => format!("{} {:04X}: {:X}-", REGIONS[self.region].name(), self.cursor, digit),
+            None => format!("{} {:04X}: {:02X}", REGIONS[self.region].name(), self.cursor, value),
+        };
+        draw_text(&mut image, 2, 1, &header, TEXT_COLOR);
+
+        for row in 0..ROWS {
+            let row_addr = self.top as u32 + (row * BYTES_PER_ROW) as u32;
+            if row_addr > *range.end() as u32 {
+                break;
+            }
+            let y = 1 + ROW_HEIGHT * (row as usize + 1);
+            draw_text(&mut image, 2, y, &format!("{:04X}", row_addr), TEXT_COLOR);
+
+            for column in 0..BYTES_PER_ROW {
+                let addr = row_addr as u16 + column;
+                let x = 2 + (5 + 3 * column as usize) * GLYPH_ADVANCE;
+                if addr == self.cursor {
+                    image.fill(x - 1, y - 1, 2 * GLYPH_ADVANCE + 1, GLYPH_HEIGHT + 2, CURSOR_COLOR);
+                }
+                let color = if engine.is_frozen(addr) { FROZEN_COLOR } else { TEXT_COLOR };
+                draw_text(&mut image, x, y, &format!("{:02X}", engine.peek(addr)), color);
+            }
+        }
+
+        self.window.present(&image)
+    }
+}
+
+/// First row to show, scrolled just enough to keep the cursor visible
+fn scroll(top: u16, cursor: u16, range: &RangeInclusive<u16>) -> u16 {
+    // the last pages end at $FFFF, past them is out of u16
+    let cursor_row = (cursor - cursor % BYTES_PER_ROW) as u32;
+    if cursor_row < top as u32 || !range.contains(&top) {
+        cursor_row as u16
+    } else if cursor_row >= top as u32 + (BYTES_PER_ROW * ROWS) as u32 {
+        (cursor_row - (BYTES_PER_ROW * (ROWS - 1)) as u32) as u16
+    } else {
+        top
+    }
+}
+
+#[test]
+fn memory_viewer_scroll_test() {
+    let high_ram = 0xFF80..=0xFFFE;
+    let mut top = 0xC000;
+    for cursor in high_ram.clone() {
+        top = scroll(top, cursor, &high_ram);
+        assert_eq!(0xFF80, top);
+    }
+    for cursor in 0xFF00..=0xFF7F {
+        top = scroll(top, cursor, &(0xFF00..=0xFF7F));
+        assert_eq!(0xFF00, top);
+    }
+
+    let work_ram = 0xC000..=0xDFFF;
+    assert_eq!(0xC000, scroll(0xC000, 0xC0F5, &work_ram));
+    assert_eq!(0xC010, scroll(0xC000, 0xC100, &work_ram));
+    assert_eq!(0xC050, scroll(0xC080, 0xC05F, &work_ram));
+}
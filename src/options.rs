@@ -0,0 +1,289 @@
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+
+use crate::emulator::Emulator;
+use crate::emulator::capture::DEFAULT_CAPTURE_SCALE;
+use crate::emulator::model::Model;
+use crate::emulator::sound::blip::Quality;
+use crate::emulator::trace::{parse_range, TraceFormat};
+use crate::gdb::DEFAULT_GDB_PORT;
+
+pub const DEFAULT_SCALE: u32 = 4;
+pub const DEFAULT_CONFIG_FILENAME: &str = "kiwi.ini";
+
+pub const USAGE: &str = "\
+usage: kiwi [options] <rom>
+       kiwi run --headless <rom> [options]
+       kiwi disasm <rom> [--sym <file>] [-o <file>]
+
+options:
+  --boot-rom <file>        boot from a boot ROM dump (DMG0, DMG, MGB, SGB or CGB)
+  --skip-boot              start at $0100 in the state the boot ROM leaves
+  --scale <n>              window size in multiples of 160x144 (default 4)
+  --fullscreen             start full screen
+  --mute                   no sound output
+  --audio-quality <q>      fast, low, medium or high sound synthesis (default medium)
+  --speed <factor>         emulation speed, 2 runs twice as fast (default 1)
+  --model <model>          dmg0, dmg, mgb, sgb or cgb, for the boot state (default dmg)
+  --savedir <dir>          save states and captures go there, not next to the ROM
+  --capture-scale <n>      screenshot and recording size in multiples of 160x144 (default 1)
+  --rewind                 keep snapshots to run backward with the ` key
+  --movie-record <file>    record the keys of every frame, saved on exit
+  --movie-play <file>      replay a recorded movie, then hand the keys back
+  --config <file>          configuration file (default kiwi.ini)
+  --sym <file>             RGBDS symbols, when they aren't next to the ROM
+  --debug                  start paused in the terminal debugger
+  --gdb [port]             wait for GDB on a loopback port
+  --trace <file>           log the executed instructions
+  --trace-format <format>  doctor or disasm
+  --trace-range <a>-<b>    trace only within a PC range
+  --trace-skip-boot        trace only after the boot ROM
+  --serial-stdout          print the serial port output
+  --link-listen <addr>     wait for a link cable partner on addr or unix:<path>
+  --link-connect <addr>    connect the link cable to addr or unix:<path>
+  --link-loopback          plug the link cable output into its input
+  --help                   show this help";
+
+/// Link cable partner, `unix:<path>` addresses use a Unix socket instead of TCP
+#[derive(Clone, Debug, PartialEq)]
+pub enum Link {
+    /// Wait for the other instance on the address
+    Listen(String),
+
+    /// Join the other instance at the address
+    Connect(String),
+
+    /// The cable output plugged back into the input
+    Loopback,
+}
+
+/// Boot Options, the same for the window and `run --headless`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Boot {
+    pub boot_rom: Option<String>,
+    pub skip_boot: bool,
+    pub model: Model,
+}
+
+impl Boot {
+    /// Takes `--boot-rom`, `--skip-boot` or `--model` with its value, false for the other arguments
+    pub fn parse_arg<'a>(&mut self, arg: &str, args: &mut impl Iterator<Item = &'a String>) -> Result<bool, String> {
+        let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+        match arg {
+            "--boot-rom" => self.boot_rom = Some(value()?.clone()),
+            "--skip-boot" => self.skip_boot = true,
+            "--model" => self.model = Model::parse(value()?).ok_or("--model is dmg0, dmg, mgb, sgb or cgb")?,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Open the ROM to run through the boot ROM, or from $0100 with `--skip-boot`
+    pub fn start(&self, emulator: &mut Emulator, rom_filename: &str) -> Result<(), String> {
+        if let Some(boot_rom) = self.boot_rom.as_ref() {
+            emulator.load_boot_rom(boot_rom).map_err(|error| format!("{}: {}", boot_rom, error))?;
+        }
+        emulator.open_rom_file(rom_filename);
+        if self.skip_boot {
+            emulator.skip_boot(self.model);
+        }
+        Ok(())
+    }
+}
+
+/// Command Line Options of the windowed emulator
+#[derive(Clone, Debug, PartialEq)]
+pub struct Options {
+    pub rom_filename: String,
+    pub boot: Boot,
+    pub scale: u32,
+    pub fullscreen: bool,
+    pub mute: bool,
+    pub audio_quality: Option<Quality>,
+    pub speed: f64,
+    pub savedir: Option<PathBuf>,
+    pub capture_scale: usize,
+    pub rewind: bool,
+    pub movie_record: Option<String>,
+    pub movie_play: Option<String>,
+    pub config: String,
+    pub sym: Option<String>,
+    pub trace: Option<String>,
+    pub trace_format: TraceFormat,
+    pub trace_range: Option<RangeInclusive<u16>>,
+    pub trace_skip_boot: bool,
+    pub debug: bool,
+    pub gdb: Option<u16>,
+    pub serial_stdout: bool,
+    pub link: Option<Link>,
+    pub help: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            rom_filename: String::new(),
+            boot: Boot::default(),
+            scale: DEFAULT_SCALE,
+            fullscreen: false,
+            mute: false,
+            audio_quality: None,
+            speed: 1.0,
+            savedir: None,
+            capture_scale: DEFAULT_CAPTURE_SCALE,
+            rewind: false,
+            movie_record: None,
+            movie_play: None,
+            config: DEFAULT_CONFIG_FILENAME.to_string(),
+            sym: None,
+            trace: None,
+            trace_format: TraceFormat::Doctor,
+            trace_range: None,
+            trace_skip_boot: false,
+            debug: false,
+            gdb: None,
+            serial_stdout: false,
+            link: None,
+            help: false,
+        }
+    }
+}
+
+impl Options {
+    /// Options of the arguments after the program name
+    pub fn parse(args: &[String]) -> Result<Options, String> {
+        let mut options = Options::default();
+        let mut rom_filename = None;
+
+        let mut args = args.iter().peekable();
+        while let Some(arg) = args.next() {
+            if options.boot.parse_arg(arg, &mut args)? {
+                continue;
+            }
+            let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+            match arg.as_str() {
+                "--scale" => {
+                    options.scale = value()?.parse().ok()
+                        .filter(|scale| (1..=16).contains(scale))
+                        .ok_or("--scale is a number from 1 to 16")?;
+                }
+                "--fullscreen" => options.fullscreen = true,
+                "--mute" => options.mute = true,
+                "--audio-quality" => options.audio_quality = Some(value()?.parse()?),
+                "--speed" => {
+                    options.speed = value()?.parse().ok()
+                        .filter(|speed: &f64| *speed >= 0.1 && *speed <= 100.0)
+                        .ok_or("--speed is a factor from 0.1 to 100")?;
+                }
+                "--savedir" => options.savedir = Some(PathBuf::from(value()?)),
+                "--capture-scale" => {
+                    options.capture_scale = value()?.parse().ok()
+                        .filter(|scale| (1..=16).contains(scale))
+                        .ok_or("--capture-scale is a number from 1 to 16")?;
+                }
+                "--rewind" => options.rewind = true,
+                "--movie-record" => options.movie_record = Some(value()?.clone()),
+                "--movie-play" => options.movie_play = Some(value()?.clone()),
+                "--config" => options.config = value()?.clone(),
+                "--sym" => options.sym = Some(value()?.clone()),
+                "--trace" => options.trace = Some(value()?.clone()),
+                "--trace-format" => options.trace_format = TraceFormat::parse(value()?).ok_or("--trace-format is doctor or disasm")?,
+                "--trace-range" => options.trace_range = Some(parse_range(value()?).ok_or("--trace-range is <begin>-<end>")?),
+                "--trace-skip-boot" => options.trace_skip_boot = true,
+                "--debug" => options.debug = true,
+                "--serial-stdout" => options.serial_stdout = true,
+                "--link-listen" | "--link-connect" | "--link-loopback" if options.link.is_some() => {
+                    return Err("--link-listen, --link-connect and --link-loopback can't be combined".to_string());
+                }
+                "--link-listen" => options.link = Some(Link::Listen(value()?.clone())),
+                "--link-connect" => options.link = Some(Link::Connect(value()?.clone())),
+                "--link-loopback" => options.link = Some(Link::Loopback),
+                "--help" | "-h" => options.help = true,
+                "--gdb" => {
+                    // the port is optional
+                    let port = args.next_if(|port| port.parse::<u16>().is_ok());
+                    options.gdb = Some(port.map_or(DEFAULT_GDB_PORT, |port| port.parse().unwrap()));
+                }
+                _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+                _ if rom_filename.is_none() => rom_filename = Some(arg.clone()),
+                _ => return Err(format!("unexpected argument {}", arg)),
+            }
+        }
+
+        if options.movie_record.is_some() && options.movie_play.is_some() {
+            return Err("--movie-record and --movie-play can't be combined".to_string());
+        }
+
+        match rom_filename {
+            Some(rom_filename) => options.rom_filename = rom_filename,
+            None if options.help => {}
+            None => return Err("missing ROM filename".to_string()),
+        }
+        Ok(options)
+    }
+
+    /// Where files named after the ROM are written, `--savedir` or next to the ROM
+    pub fn save_filename(&self, extension: &str) -> PathBuf {
+        let rom = Path::new(&self.rom_filename);
+        let filename = format!("{}.{}", rom.file_name().map_or("kiwi".into(), |name| name.to_string_lossy()), extension);
+        match self.savedir.as_ref() {
+            Some(savedir) => savedir.join(filename),
+            None => rom.with_file_name(filename),
+        }
+    }
+
+    /// Where screenshots and recordings go, `--savedir` or the working directory
+    pub fn capture_filename(&self, filename: &str) -> String {
+        match self.savedir.as_ref() {
+            Some(savedir) => savedir.join(filename).to_string_lossy().into_owned(),
+            None => filename.to_string(),
+        }
+    }
+}
+
+#[test]
+fn options_parse_test() {
+    let parse = |text: &str| Options::parse(&text.split_whitespace().map(str::to_string).collect::<Vec<_>>());
+
+    let options = parse("--scale 2 --mute --trace out.txt game.gb --gdb --speed 1.5 --model CGB --savedir saves").unwrap();
+    assert_eq!("game.gb", options.rom_filename);
+    assert_eq!((2, true, false), (options.scale, options.mute, options.fullscreen));
+    assert_eq!((1.5, Model::Cgb), (options.speed, options.boot.model));
+    assert_eq!(PathBuf::from("saves/game.gb.ss1"), options.save_filename("ss1"));
+    assert_eq!(DEFAULT_CONFIG_FILENAME, options.config);
+    assert_eq!(("saves/kiwi-1.png", 1), (options.capture_filename("kiwi-1.png").as_str(), options.capture_scale));
+
+    assert_eq!(Some(DEFAULT_GDB_PORT), options.gdb);
+
+    let options = parse("--gdb 1234 roms/game.gb --boot-rom dmg.bin --config other.ini --skip-boot").unwrap();
+    assert_eq!(Some(1234), options.gdb);
+    assert_eq!(("roms/game.gb", Some("dmg.bin")), (options.rom_filename.as_str(), options.boot.boot_rom.as_deref()));
+    assert_eq!(PathBuf::from("roms/game.gb.ss1"), options.save_filename("ss1"));
+    assert_eq!(("other.ini", true), (options.config.as_str(), options.boot.skip_boot));
+
+    assert!(parse("--help").unwrap().help);
+    assert!(parse("").is_err());
+    assert!(parse("game.gb --scale 0").is_err());
+    assert_eq!(3, parse("game.gb --capture-scale 3").unwrap().capture_scale);
+    assert!(!options.rewind && parse("game.gb --rewind").unwrap().rewind);
+    assert_eq!(Some("run.kmv"), parse("game.gb --movie-play run.kmv").unwrap().movie_play.as_deref());
+    assert!(parse("game.gb --movie-record a.kmv --movie-play b.kmv").is_err());
+    assert!(parse("game.gb --speed").is_err());
+    assert!(parse("game.gb --model gba").is_err());
+    let options = parse("game.gb --trace-format disasm --trace-range $0150-01FF").unwrap();
+    assert_eq!((TraceFormat::Disassembly, Some(0x0150..=0x01FF)), (options.trace_format, options.trace_range));
+    assert!(parse("game.gb --trace-format gdb").is_err());
+    assert!(parse("game.gb --trace-range 0150").is_err());
+    assert_eq!(Some("game.sym"), parse("game.gb --sym game.sym").unwrap().sym.as_deref());
+    assert!(parse("game.gb --sym").is_err());
+    assert!(parse("game.gb --bogus").is_err());
+    assert_eq!(Some(Quality::Fast), parse("game.gb --audio-quality fast").unwrap().audio_quality);
+    assert!(parse("game.gb --audio-quality best").is_err());
+    let options = parse("game.gb --debug --serial-stdout --link-connect unix:/tmp/link").unwrap();
+    assert_eq!((true, true, None), (options.debug, options.serial_stdout, options.gdb));
+    assert_eq!(Some(Link::Connect("unix:/tmp/link".to_string())), options.link);
+    assert_eq!(Some(Link::Loopback), parse("game.gb --link-loopback").unwrap().link);
+    assert!(parse("game.gb --link-listen").is_err());
+    assert!(parse("game.gb --link-loopback --link-listen 127.0.0.1:8765").is_err());
+    assert!(parse("game.gb other.gb").is_err());
+}
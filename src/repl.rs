@@ -0,0 +1,345 @@
+use std::convert::TryFrom;
+use std::io::Write;
+
+use crate::emulator::Emulator;
+use crate::emulator::cheats::{Cheat, Code};
+use crate::emulator::cpu::asm::instruction_size;
+use crate::emulator::debugger::{parse_number, Access, Condition};
+use crate::emulator::engine::Engine;
+use crate::emulator::mmu::Memory;
+use crate::emulator::search::{Comparison, RamSearch, Region};
+
+const HELP: &str = "\
+Commands (an empty line repeats the last one, numbers are hexadecimal or #decimal,
+addresses can also be symbols):
+  c, continue                  run until a breakpoint or watchpoint
+  s, step                      run one instruction
+  n, next                      run one instruction, CALL and RST included
+  o, out                       run until the current subroutine returns
+  v, vblank                    run until the next vertical blank
+  b, break <addr> [if <cond>]  breakpoint, condition as in `a == $10` or `hl >= $C000`
+  w, watch <addr>[-<end>] [r|w|rw]
+                               watchpoint on reads and/or writes (default writes)
+  d, delete <b|w> <index>      remove a breakpoint or watchpoint
+  l, list                      breakpoints and watchpoints
+  r, regs                      registers and flags
+  i, int                       interrupt master enable, IE and IF
+  st, stack [count]            stack entries
+  x <addr> [count]             memory dump
+  dis [addr] [count]           disassembly
+  poke <addr> <value>          write memory
+  search start [region...]     RAM search over vram, sram, wram, oam, io, hram
+                               (default wram sram hram)
+  search <cmp> [value]         keep the addresses that compare to the value or to the
+                               last search, cmp is =, !=, <, >, changed or unchanged
+  search list                  addresses left
+  freeze <addr> [value]        write the value (default current) every frame
+  unfreeze <addr>              stop freezing
+  frozen                       frozen addresses
+  cheat [list]                 cheats, `+` when enabled
+  cheat on|off <index>         enable or disable a cheat
+  cheat add <code>[,<code>...] [name]
+                               Game Genie (ABC-DEF[-GHI]) or GameShark (TTVVLLHH) cheat
+  q, quit                      exit the emulator";
+
+/// Terminal Debugger
+///
+/// Prompt on stdin/stdout entered whenever the debugger stops the emulation.
+/// Blocks the emulation (and the window) until a run command is given.
+#[derive(Default)]
+pub struct Repl {
+    last_command: String,
+    search: RamSearch,
+}
+
+impl Repl {
+    /// Read commands until one resumes the emulation, false to quit
+    pub fn run(&mut self, emulator: &mut Emulator) -> bool {
+        let engine = emulator.engine_mut();
+        if let Some(reason) = engine.debugger().stopped() {
+            println!("Stopped ({})", reason);
+            print_instruction(engine, engine.cpu_regs().pc());
+        }
+
+        loop {
+            print!("(kiwi) ");
+            std::io::stdout().flush().unwrap();
+
+            let mut line = String::new();
+            if std::io::stdin().read_line(&mut line).unwrap_or(0) == 0 {
+                return false;
+            }
+            let line = match line.trim() {
+                "" => self.last_command.clone(),
+                line => line.to_string(),
+            };
+            self.last_command = line.clone();
+
+            match execute(engine, &mut self.search, &line) {
+                Ok(Command::Prompt) => {}
+                Ok(Command::Run) => return true,
+                Ok(Command::Quit) => return false,
+                Err(error) => println!("{}", error),
+            }
+        }
+    }
+}
+
+enum Command {
+    Prompt,
+    Run,
+    Quit,
+}
+
+fn execute(engine: &mut Engine, search: &mut RamSearch, line: &str) -> Result<Command, String> {
+    let mut args = line.split_whitespace();
+    let command = match args.next() {
+        Some(command) => command,
+        None => return Ok(Command::Prompt),
+    };
+    let args: Vec<&str> = args.collect();
+    let regs = engine.cpu_regs();
+
+    match command {
+        "h" | "help" => println!("{}", HELP),
+        "q" | "quit" => return Ok(Command::Quit),
+
+        "c" | "continue" => {
+            engine.debugger_mut().resume();
+            return Ok(Command::Run);
+        }
+        "s" | "step" => {
+            engine.debugger_mut().step_into();
+            return Ok(Command::Run);
+        }
+        "n" | "next" => {
+            let opcode = engine.peek(regs.pc());
+            engine.debugger_mut().step_over(regs.pc(), opcode, instruction_size(opcode), regs.sp());
+            return Ok(Command::Run);
+        }
+        "o" | "out" => {
+            engine.debugger_mut().step_out(regs.sp());
+            return Ok(Command::Run);
+        }
+        "v" | "vblank" => {
+            engine.debugger_mut().run_to_vertical_blank();
+            return Ok(Command::Run);
+        }
+
+        "b" | "break" => {
+            let addr = parse_address(engine, args.first().ok_or("usage: break <addr> [if <cond>]")?)?;
+            let condition = match args.get(1) {
+                Some(&"if") => Some(Condition::parse(&args[2..].join(" "))?),
+                Some(_) => return Err("usage: break <addr> [if <cond>]".to_string()),
+                None => None,
+            };
+            let index = engine.debugger_mut().add_breakpoint(addr, condition);
+            println!("Breakpoint {} at {}", index, location(engine, addr));
+        }
+        "w" | "watch" => {
+            let range = args.first().ok_or("usage: watch <addr>[-<end>] [r|w|rw]")?;
+            let (begin, end) = match range.split_once('-') {
+                Some((begin, end)) => (parse_address(engine, begin)?, parse_address(engine, end)?),
+                None => (parse_address(engine, range)?, parse_address(engine, range)?),
+            };
+            let access = match args.get(1).copied().unwrap_or("w") {
+                "r" => Access::Read,
+                "w" => Access::Write,
+                "rw" => Access::ReadWrite,
+                access => return Err(format!("unknown access `{}`", access)),
+            };
+            let index = engine.debugger_mut().add_watchpoint(begin, end.max(begin), access);
+            println!("Watchpoint {} at ${:04X}-${:04X}", index, begin, end.max(begin));
+        }
+        "d" | "delete" => {
+            let index = args.get(1).and_then(|index| index.parse().ok()).ok_or("usage: delete <b|w> <index>")?;
+            let removed = match args[0] {
+                "b" => engine.debugger_mut().remove_breakpoint(index),
+                "w" => engine.debugger_mut().remove_watchpoint(index),
+                _ => return Err("usage: delete <b|w> <index>".to_string()),
+            };
+            if !removed {
+                return Err(format!("no such {} {}", if args[0] == "b" { "breakpoint" } else { "watchpoint" }, index));
+            }
+        }
+        "l" | "list" => {
+            for (index, breakpoint) in engine.debugger().breakpoints() {
+                match breakpoint.condition {
+                    Some(condition) => println!("b{} {} if {}", index, location(engine, breakpoint.addr), condition),
+                    None => println!("b{} {}", index, location(engine, breakpoint.addr)),
+                }
+            }
+            for (index, watchpoint) in engine.debugger().watchpoints() {
+                println!("w{} ${:04X}-${:04X} {:?}", index, watchpoint.begin, watchpoint.end, watchpoint.access);
+            }
+        }
+
+        "r" | "regs" => {
+            let flag = |mask: u8, name: char| if regs.f() & mask != 0 { name } else { '-' };
+            println!("AF={:04X} BC={:04X} DE={:04X} HL={:04X} SP={:04X} PC={:04X} [{}{}{}{}]",
+                regs.af(), regs.bc(), regs.de(), regs.hl(), regs.sp(), regs.pc(),
+                flag(0x80, 'Z'), flag(0x40, 'N'), flag(0x20, 'H'), flag(0x10, 'C'));
+            print_instruction(engine, regs.pc());
+        }
+        "i" | "int" => {
+            let names = |interrupts: u8| -> String {
+                ["VBLANK", "LCDC", "TIMER", "SERIAL", "JOYPAD"].iter().enumerate()
+                    .filter(|(bit, _)| interrupts & 1 << bit != 0)
+                    .map(|(_, name)| *name)
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            };
+            let enabled: u8 = engine.interrupts_enabled().into();
+            let requested: u8 = engine.interrupts_requested().into();
+            println!("IME={}", engine.interrupt_master_enable() as u8);
+            println!("IE=${:02X} {}", enabled, names(enabled));
+            println!("IF=${:02X} {}", requested, names(requested));
+        }
+        "st" | "stack" => {
+            let count = args.first().map_or(Ok(8), |count| parse_number(count))?;
+            for index in 0..count {
+                println!("SP+{:02X} {}", index.wrapping_mul(2), location(engine, engine.stack_entry(index)));
+            }
+        }
+        "x" => {
+            let addr = parse_address(engine, args.first().ok_or("usage: x <addr> [count]")?)?;
+            let count = args.get(1).map_or(Ok(0x40), |count| parse_number(count))?;
+            for row in (0..count).step_by(16) {
+                let row_addr = addr.wrapping_add(row);
+                let bytes: Vec<String> = (row..count.min(row.saturating_add(16)))
+                    .map(|offset| format!("{:02X}", engine.peek(addr.wrapping_add(offset))))
+                    .collect();
+                println!("${:04X}: {}", row_addr, bytes.join(" "));
+            }
+        }
+        "dis" | "disasm" => {
+            let mut addr = args.first().map_or(Ok(regs.pc()), |addr| parse_address(engine, addr))?;
+            let count = args.get(1).map_or(Ok(10), |count| parse_number(count))?;
+            for _ in 0..count {
+                addr = print_instruction(engine, addr);
+            }
+        }
+
+        "poke" => {
+            let addr = parse_address(engine, args.first().ok_or("usage: poke <addr> <value>")?)?;
+            let value = parse_byte(args.get(1).ok_or("usage: poke <addr> <value>")?)?;
+            engine.write(addr, value);
+        }
+        "search" => match args.first().copied() {
+            Some("start") => {
+                let regions = match args.len() {
+                    1 => vec![Region::WorkRam, Region::CartridgeRam, Region::HighRam],
+                    _ => args[1..].iter()
+                        .map(|name| Region::parse(name).ok_or(format!("unknown region `{}`", name)))
+                        .collect::<Result<_, _>>()?,
+                };
+                search.start(engine, &regions);
+                println!("{} addresses", search.len());
+            }
+            Some("list") => {
+                for (addr, value) in search.candidates().iter().take(0x40) {
+                    println!("{} ${:02X} #{}", location(engine, *addr), value, value);
+                }
+                if search.len() > 0x40 {
+                    println!("... {} addresses", search.len());
+                }
+            }
+            Some(comparison) => {
+                let comparison = Comparison::parse(comparison).ok_or(format!("unknown comparison `{}`", comparison))?;
+                let value = args.get(1).map(|value| parse_byte(value)).transpose()?;
+                println!("{} addresses", search.filter(engine, comparison, value));
+            }
+            None => return Err("usage: search <start|list|cmp> ...".to_string()),
+        },
+        "freeze" => {
+            let addr = parse_address(engine, args.first().ok_or("usage: freeze <addr> [value]")?)?;
+            let value = args.get(1).map_or(Ok(engine.peek(addr)), |value| parse_byte(value))?;
+            engine.freeze(addr, value);
+            println!("{} frozen at ${:02X}", location(engine, addr), value);
+        }
+        "unfreeze" => {
+            let addr = parse_address(engine, args.first().ok_or("usage: unfreeze <addr>")?)?;
+            if !engine.unfreeze(addr) {
+                return Err(format!("{} isn't frozen", location(engine, addr)));
+            }
+        }
+        "frozen" => {
+            for (addr, value) in engine.frozen() {
+                println!("{} ${:02X}", location(engine, addr), value);
+            }
+        }
+
+        "cheat" => match args.first().copied() {
+            None | Some("list") => {
+                for (index, cheat) in engine.cheats().iter().enumerate() {
+                    println!("{} {} {}", index, if cheat.enabled { '+' } else { '-' }, cheat.name);
+                }
+            }
+            Some(toggle @ "on") | Some(toggle @ "off") => {
+                let index = args.get(1).and_then(|index| index.parse().ok()).ok_or("usage: cheat on|off <index>")?;
+                if !engine.set_cheat_enabled(index, toggle == "on") {
+                    return Err(format!("no such cheat {}", index));
+                }
+            }
+            Some("add") => {
+                let codes = args.get(1).ok_or("usage: cheat add <code>[,<code>...] [name]")?
+                    .split(',')
+                    .map(Code::parse)
+                    .collect::<Result<Vec<_>, _>>()?;
+                let index = engine.add_cheat(Cheat { name: args[2..].join(" "), codes, enabled: true });
+                println!("Cheat {} enabled", index);
+            }
+            Some(_) => return Err("usage: cheat [list|on|off|add] ...".to_string()),
+        },
+
+        _ => return Err(format!("unknown command `{}`, try `help`", command)),
+    }
+
+    Ok(Command::Prompt)
+}
+
+// A symbol of the ROM or a number
+fn parse_address(engine: &Engine, text: &str) -> Result<u16, String> {
+    match engine.symbols().find(text) {
+        Some((_, addr)) => Ok(addr),
+        None => parse_number(text),
+    }
+}
+
+fn parse_byte(text: &str) -> Result<u8, String> {
+    let value = parse_number(text)?;
+    u8::try_from(value).map_err(|_| format!("`{}` doesn't fit in a byte", text))
+}
+
+// `$0153 <Start+$3>`, or `$0153` without symbols
+fn location(engine: &Engine, addr: u16) -> String {
+    match engine.label(addr) {
+        Some(label) => format!("${:04X} <{}>", addr, label),
+        None => format!("${:04X}", addr),
+    }
+}
+
+// Returns the address of the next instruction
+fn print_instruction(engine: &Engine, addr: u16) -> u16 {
+    let size = instruction_size(engine.peek(addr));
+    let bytes: Vec<String> = (0..size).map(|offset| format!("{:02X}", engine.peek(addr.wrapping_add(offset)))).collect();
+    println!("{}: {:<9} {}", location(engine, addr), bytes.join(" "), engine.disassemble(addr));
+    addr.wrapping_add(size)
+}
+
+#[test]
+fn repl_memory_commands_test() {
+    use crate::emulator::test_fixture::TempFile;
+
+    let rom = TempFile::blank_rom("repl");
+    let mut engine = Engine::default();
+    engine.open_rom_file(rom.to_str());
+    let mut search = RamSearch::default();
+
+    // counts up to the whole address space wrap around it
+    assert!(execute(&mut engine, &mut search, "x 0 FFFF").is_ok());
+    assert!(execute(&mut engine, &mut search, "st FFFF").is_ok());
+    assert!(execute(&mut engine, &mut search, "poke C000 42").is_ok());
+    assert_eq!(0x42, engine.peek(0xC000));
+    assert!(execute(&mut engine, &mut search, "poke C000 100").is_err());
+}
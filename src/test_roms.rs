@@ -0,0 +1,252 @@
+//! Regression Suite over Public Test ROMs
+//!
+//! Runs freely licensed test ROMs through the `Engine` from power on, with
+//! the embedded boot ROM, and checks what they report:
+//! - Blargg's `cpu_instrs`, `instr_timing` and `mem_timing` print `Passed`
+//!   or `Failed` out of the serial port
+//! - mooneye-gb acceptance tests run `LD B,B` with 3 5 8 13 21 34 in BCDEHL
+//!   on success
+//! - dmg-acid2 runs `LD B,B` once its screen is drawn, the MD5 of the frame
+//!   buffer is compared to the one in `res/frame_hashes.md5`
+//!
+//! The ROMs aren't part of the repository, so these tests are ignored unless
+//! asked for with `cargo test -- --ignored`. They are read from
+//! `res/test-roms` (or `$KIWI_TEST_ROMS`) laid out as released:
+//!
+//! ```text
+//! res/test-roms/blargg/cpu_instrs/cpu_instrs.gb
+//! res/test-roms/blargg/instr_timing/instr_timing.gb
+//! res/test-roms/blargg/mem_timing/mem_timing.gb
+//! res/test-roms/mooneye/acceptance/...
+//! res/test-roms/dmg-acid2/dmg-acid2.gb
+//! ```
+//!
+//! - Blargg's ROMs come from <https://github.com/retrio/gb-test-roms>, the
+//!   `cpu_instrs`, `instr_timing` and `mem_timing` directories go in `blargg`
+//! - mooneye-gb's from the test suite releases at
+//!   <https://gekkio.fi/files/mooneye-test-suite/>, the `acceptance`
+//!   directory goes in `mooneye`
+//! - dmg-acid2 from <https://github.com/mattcurrie/dmg-acid2/releases>
+//!
+//! A missing ROM fails. Frame hashes are lines of `md5sum` output; a ROM
+//! without one fails with the hash it got, to be added once the screen is
+//! checked against the reference image.
+
+use std::path::{Path, PathBuf};
+
+use crate::emulator::debugger::Break;
+use crate::emulator::engine::Engine;
+use crate::emulator::serial::SerialSink;
+
+const TEST_ROMS_DIR: &str = "res/test-roms";
+const FRAME_HASHES_FILENAME: &str = "res/frame_hashes.md5";
+
+// Registers Mooneye test ROMs leave on success before `LD B,B`
+const MOONEYE_PASS: [u8; 6] = [3, 5, 8, 13, 21, 34];
+
+#[derive(Clone, Copy, Debug)]
+enum Expect {
+    /// Serial output containing the text passes, `Failed` fails
+    Serial(&'static str),
+
+    /// Mooneye registers at `LD B,B`
+    Mooneye,
+
+    /// Frame buffer hash at `LD B,B`
+    FrameHash,
+}
+
+// Blargg ROMs, frames to run at most
+const BLARGG: [(&str, u64); 3] = [
+    ("blargg/cpu_instrs/cpu_instrs.gb", 4000),
+    ("blargg/instr_timing/instr_timing.gb", 600),
+    ("blargg/mem_timing/mem_timing.gb", 600),
+];
+
+const MOONEYE_FRAMES: u64 = 1200;
+
+// Acceptance tests that apply to the DMG
+const MOONEYE_ACCEPTANCE: [&str; 57] = [
+    "add_sp_e_timing.gb",
+    "boot_div-dmgABCmgb.gb",
+    "boot_hwio-dmgABCmgb.gb",
+    "boot_regs-dmgABC.gb",
+    "call_cc_timing.gb",
+    "call_cc_timing2.gb",
+    "call_timing.gb",
+    "call_timing2.gb",
+    "di_timing-GS.gb",
+    "div_timing.gb",
+    "ei_sequence.gb",
+    "ei_timing.gb",
+    "halt_ime0_ei.gb",
+    "halt_ime0_nointr_timing.gb",
+    "halt_ime1_timing.gb",
+    "halt_ime1_timing2-GS.gb",
+    "if_ie_registers.gb",
+    "intr_timing.gb",
+    "jp_cc_timing.gb",
+    "jp_timing.gb",
+    "ld_hl_sp_e_timing.gb",
+    "oam_dma_restart.gb",
+    "oam_dma_start.gb",
+    "oam_dma_timing.gb",
+    "pop_timing.gb",
+    "push_timing.gb",
+    "rapid_di_ei.gb",
+    "ret_cc_timing.gb",
+    "ret_timing.gb",
+    "reti_intr_timing.gb",
+    "reti_timing.gb",
+    "rst_timing.gb",
+    "bits/mem_oam.gb",
+    "bits/reg_f.gb",
+    "bits/unused_hwio-GS.gb",
+    "instr/daa.gb",
+    "interrupts/ie_push.gb",
+    "oam_dma/basic.gb",
+    "oam_dma/reg_read.gb",
+    "oam_dma/sources-GS.gb",
+    "ppu/intr_2_0_timing.gb",
+    "ppu/stat_irq_blocking.gb",
+    "ppu/stat_lyc_onoff.gb",
+    "ppu/vblank_stat_intr-GS.gb",
+    "timer/div_write.gb",
+    "timer/rapid_toggle.gb",
+    "timer/tim00.gb",
+    "timer/tim00_div_trigger.gb",
+    "timer/tim01.gb",
+    "timer/tim01_div_trigger.gb",
+    "timer/tim10.gb",
+    "timer/tim10_div_trigger.gb",
+    "timer/tim11.gb",
+    "timer/tim11_div_trigger.gb",
+    "timer/tima_reload.gb",
+    "timer/tima_write_reloading.gb",
+    "timer/tma_write_reloading.gb",
+];
+
+const ACID2: (&str, u64) = ("dmg-acid2/dmg-acid2.gb", 600);
+
+fn test_roms_dir() -> PathBuf {
+    std::env::var_os("KIWI_TEST_ROMS").map_or(PathBuf::from(TEST_ROMS_DIR), PathBuf::from)
+}
+
+/// Expected frame hashes by ROM name, from `md5sum` style lines
+fn frame_hashes() -> Vec<(String, String)> {
+    let text = std::fs::read_to_string(FRAME_HASHES_FILENAME).unwrap_or_default();
+    text.lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once(char::is_whitespace))
+        .map(|(hash, name)| (name.trim_start_matches([' ', '*']).to_string(), hash.to_lowercase()))
+        .collect()
+}
+
+/// Run a test ROM until it reports, `Ok` with what passed or `Err` with what failed
+fn run_test_rom(filename: &Path, name: &str, expect: Expect, frames: u64) -> Result<String, String> {
+    let mut engine = Box::new(Engine::default());
+    engine.open_rom_file(filename.to_str().unwrap());
+    engine.set_serial_sink(Some(SerialSink::Buffer(Vec::new())));
+    let debugger = engine.debugger_mut();
+    debugger.set_enabled(!matches!(expect, Expect::Serial(_)));
+    debugger.set_break_on_ld_b_b(!matches!(expect, Expect::Serial(_)));
+
+    let mut clock = 0;
+    for frame in 0..frames {
+        engine.run_next_frame(&mut clock);
+
+        match (expect, engine.debugger().stopped()) {
+            (Expect::Serial(pass), _) => {
+                let output = String::from_utf8_lossy(engine.serial_output());
+                if output.contains("Failed") {
+                    return Err(format!("serial output {:?}", output));
+                }
+                if output.contains(pass) {
+                    return Ok(format!("`{}` in frame {}", pass, frame));
+                }
+            }
+            (Expect::Mooneye, Some(Break::SoftwareBreakpoint)) => {
+                let regs = engine.cpu_regs();
+                let registers = [regs.b(), regs.c(), regs.d(), regs.e(), regs.h(), regs.l()];
+                return match registers == MOONEYE_PASS {
+                    true => Ok(format!("LD B,B in frame {}", frame)),
+                    false => Err(format!("LD B,B in frame {} with BCDEHL {:?}", frame, registers)),
+                };
+            }
+            (Expect::FrameHash, Some(Break::SoftwareBreakpoint)) => {
+                let hash = format!("{:x}", md5::compute(engine.ppu().frame_buffer()));
+                return match frame_hashes().iter().find(|(rom, _)| rom == name) {
+                    Some((_, expected)) if *expected == hash => Ok(format!("frame hash {}", hash)),
+                    Some((_, expected)) => Err(format!("frame hash {}, expected {}", hash, expected)),
+                    None => Err(format!("frame hash {}, nothing to compare to in {}", hash, FRAME_HASHES_FILENAME)),
+                };
+            }
+            (_, Some(_)) => engine.debugger_mut().resume(),
+            (_, None) => {}
+        }
+    }
+    Err(format!("timed out after {} frames", frames))
+}
+
+/// Run the ROMs and fail with the list of the ones that failed or are missing
+fn run_test_roms(roms: &[(String, Expect, u64)]) {
+    let directory = test_roms_dir();
+    let mut failures = Vec::new();
+    for (name, expect, frames) in roms {
+        let filename = directory.join(name);
+        if !filename.exists() {
+            failures.push(format!("{}: not found in {}", name, directory.display()));
+            continue;
+        }
+        match run_test_rom(&filename, name, *expect, *frames) {
+            Ok(message) => println!("{}: passed, {}", name, message),
+            Err(message) => failures.push(format!("{}: {}", name, message)),
+        }
+    }
+    assert!(failures.is_empty(), "failed test ROMs:\n{}", failures.join("\n"));
+}
+
+#[test]
+#[ignore = "needs the test ROMs, run with --ignored"]
+fn blargg_test_roms_test() {
+    let roms: Vec<_> = BLARGG.iter()
+        .map(|(name, frames)| (name.to_string(), Expect::Serial("Passed"), *frames))
+        .collect();
+    run_test_roms(&roms);
+}
+
+#[test]
+#[ignore = "needs the test ROMs, run with --ignored"]
+fn mooneye_acceptance_test_roms_test() {
+    let roms: Vec<_> = MOONEYE_ACCEPTANCE.iter()
+        .map(|name| (format!("mooneye/acceptance/{}", name), Expect::Mooneye, MOONEYE_FRAMES))
+        .collect();
+    run_test_roms(&roms);
+}
+
+#[test]
+#[ignore = "needs the test ROMs, run with --ignored"]
+fn dmg_acid2_test_rom_test() {
+    run_test_roms(&[(ACID2.0.to_string(), Expect::FrameHash, ACID2.1)]);
+}
+
+#[test]
+fn test_roms_harness_test() {
+    use crate::emulator::test_fixture::{bootable_rom, TempFile};
+
+    // prints `Passed` and runs `LD B,B`
+    let mut rom = bootable_rom();
+    let mut code = Vec::new();
+    for byte in b"Passed" {
+        code.extend_from_slice(&[0x3E, *byte, 0xE0, 0x01, 0x3E, 0x81, 0xE0, 0x02]);   // serial transfer
+        code.extend_from_slice(&[0xF0, 0x02, 0x07, 0x38, 0xFB]);                       // wait for it
+    }
+    code.extend_from_slice(&[0x40, 0x18, 0xFE]);                                        // LD B,B and loop
+    rom[0x0150..0x0150 + code.len()].copy_from_slice(&code);
+    let rom = TempFile::rom("test-roms", &rom);
+
+    assert!(run_test_rom(rom.path(), "passed.gb", Expect::Serial("Passed"), 600).is_ok());
+    assert!(run_test_rom(rom.path(), "passed.gb", Expect::Serial("Other"), 200).is_err());
+    assert!(run_test_rom(rom.path(), "passed.gb", Expect::Mooneye, 600).unwrap_err().starts_with("LD B,B"));
+    assert!(run_test_rom(rom.path(), "passed.gb", Expect::FrameHash, 600).unwrap_err().contains("nothing to compare"));
+}
@@ -0,0 +1,176 @@
+use crate::emulator::ppu::Ppu;
+use crate::emulator::ppu::{SHADE, TILE_WIDTH};
+use crate::emulator::ppu::palette::Palette;
+use crate::emulator::ppu::viewer::{Image, TILES_VIEWER_HEIGHT, TILES_VIEWER_WIDTH, TILE_MAP_PIXEL_SIZE};
+
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::render::{Canvas, Texture};
+use sdl2::video::Window;
+use sdl2::VideoSubsystem;
+
+const SCALE: usize = 2;
+
+pub const BACKGROUND_COLOR: Color = Color::RGB(0x20, 0x20, 0x20);
+pub const TEXT_COLOR: Color = Color::RGB(0xE0, 0xE0, 0xE0);
+
+// 3x5 pixel font, 3 bits per row with the left column in bit 2
+const GLYPH_WIDTH: usize = 3;
+pub const GLYPH_HEIGHT: usize = 5;
+pub const GLYPH_ADVANCE: usize = GLYPH_WIDTH + 1;
+const GLYPHS: [(char, [u8; GLYPH_HEIGHT]); 32] = [
+    ('0', [7, 5, 5, 5, 7]), ('1', [2, 6, 2, 2, 7]), ('2', [7, 1, 7, 4, 7]), ('3', [7, 1, 3, 1, 7]),
+    ('4', [5, 5, 7, 1, 1]), ('5', [7, 4, 7, 1, 7]), ('6', [7, 4, 7, 5, 7]), ('7', [7, 1, 1, 2, 2]),
+    ('8', [7, 5, 7, 5, 7]), ('9', [7, 5, 7, 1, 7]), ('A', [2, 5, 7, 5, 5]), ('B', [6, 5, 6, 5, 6]),
+    ('C', [3, 4, 4, 4, 3]), ('D', [6, 5, 5, 5, 6]), ('E', [7, 4, 6, 4, 7]), ('F', [7, 4, 6, 4, 4]),
+    ('G', [3, 4, 5, 5, 3]), ('O', [2, 5, 5, 5, 2]), ('P', [6, 5, 6, 4, 4]), ('T', [7, 2, 2, 2, 2]),
+    ('X', [5, 5, 2, 5, 5]), ('Y', [5, 5, 2, 2, 2]), ('-', [0, 0, 7, 0, 0]), (' ', [0, 0, 0, 0, 0]),
+    ('H', [5, 5, 7, 5, 5]), ('I', [7, 2, 2, 2, 7]), ('M', [5, 7, 7, 5, 5]), ('R', [6, 5, 6, 5, 5]),
+    ('S', [3, 4, 2, 1, 6]), ('V', [5, 5, 5, 5, 2]), ('W', [5, 5, 7, 7, 5]), (':', [0, 2, 0, 2, 0]),
+];
+
+// Tiles window: the tiles above the BGP, OBP0 and OBP1 swatches
+const PALETTE_ROW_HEIGHT: usize = 12;
+const TILES_WINDOW_WIDTH: usize = TILES_VIEWER_WIDTH;
+const TILES_WINDOW_HEIGHT: usize = TILES_VIEWER_HEIGHT + 4 + 3 * PALETTE_ROW_HEIGHT;
+
+// Tile maps window: $9800 and $9C00 side by side under their address
+const MAP_GAP: usize = 8;
+const MAPS_WINDOW_WIDTH: usize = TILE_MAP_PIXEL_SIZE * 2 + MAP_GAP;
+const MAPS_WINDOW_HEIGHT: usize = TILE_MAP_PIXEL_SIZE + GLYPH_HEIGHT + 3;
+
+// OAM window: two columns of 20 entries, preview then `NN Y00 X00 T00 PYX1`
+const OAM_ROW_HEIGHT: usize = 18;
+const OAM_COLUMN_WIDTH: usize = TILE_WIDTH + 4 + 19 * GLYPH_ADVANCE + 4;
+const OAM_WINDOW_WIDTH: usize = OAM_COLUMN_WIDTH * 2;
+const OAM_WINDOW_HEIGHT: usize = OAM_ROW_HEIGHT * 20;
+
+/// VRAM Viewer Windows
+///
+/// Tile data with the palettes, both BG maps with the screen outlined, and
+/// the OAM entries. Redrawn from the PPU on every `update`.
+pub struct VramViewer {
+    tiles: ImageWindow,
+    maps: ImageWindow,
+    objects: ImageWindow,
+}
+
+impl VramViewer {
+    pub fn open(video_subsystem: &VideoSubsystem) -> Result<Self, String> {
+        Ok(Self {
+            tiles: ImageWindow::open(video_subsystem, "Kiwi Tiles", TILES_WINDOW_WIDTH, TILES_WINDOW_HEIGHT, SCALE)?,
+            maps: ImageWindow::open(video_subsystem, "Kiwi Tile Maps", MAPS_WINDOW_WIDTH, MAPS_WINDOW_HEIGHT, SCALE)?,
+            objects: ImageWindow::open(video_subsystem, "Kiwi OAM", OAM_WINDOW_WIDTH, OAM_WINDOW_HEIGHT, SCALE)?,
+        })
+    }
+
+    /// Whether a window event belongs to one of the viewers
+    pub fn owns_window(&self, window_id: u32) -> bool {
+        [&self.tiles, &self.maps, &self.objects].iter().any(|window| window.window_id() == window_id)
+    }
+
+    pub fn update(&mut self, ppu: &Ppu) -> Result<(), String> {
+        self.tiles.present(&tiles_image(ppu))?;
+        self.maps.present(&maps_image(ppu))?;
+        self.objects.present(&objects_image(ppu))
+    }
+}
+
+/// Debug Window
+///
+/// Shows images of a fixed size stretched over the window, through a texture
+/// made once when the window opens.
+pub struct ImageWindow {
+    canvas: Canvas<Window>,
+    texture: Texture,
+}
+
+impl ImageWindow {
+    pub fn open(video_subsystem: &VideoSubsystem, title: &str, width: usize, height: usize, scale: usize) -> Result<Self, String> {
+        let window = video_subsystem.window(title, (width * scale) as u32, (height * scale) as u32)
+            .build()
+            .map_err(|error| error.to_string())?;
+        let canvas = window.into_canvas().build().map_err(|error| error.to_string())?;
+        let texture = canvas.create_texture_static(PixelFormatEnum::ARGB32, width as u32, height as u32)
+            .map_err(|error| error.to_string())?;
+        Ok(Self { canvas, texture })
+    }
+
+    pub fn window_id(&self) -> u32 {
+        self.canvas.window().id()
+    }
+
+    /// Show an image the size given on opening
+    pub fn present(&mut self, image: &Image) -> Result<(), String> {
+        self.texture.update(None, &image.pixels, image.pitch()).map_err(|error| error.to_string())?;
+        self.canvas.clear();
+        self.canvas.copy(&self.texture, None, None)?;
+        self.canvas.present();
+        Ok(())
+    }
+}
+
+fn tiles_image(ppu: &Ppu) -> Image {
+    let mut image = Image::new(TILES_WINDOW_WIDTH, TILES_WINDOW_HEIGHT);
+    image.fill(0, 0, image.width, image.height, BACKGROUND_COLOR);
+    image.draw(&ppu.render_tiles(), 0, 0);
+
+    let palettes = [
+        ("BGP ", ppu.background_palette()),
+        ("OBP0", ppu.object_palette_0()),
+        ("OBP1", ppu.object_palette_1()),
+    ];
+    for (row, (name, palette)) in palettes.iter().enumerate() {
+        let y = TILES_VIEWER_HEIGHT + 4 + row * PALETTE_ROW_HEIGHT;
+        draw_text(&mut image, 2, y + 2, &format!("{} {:02X}", name, palette), TEXT_COLOR);
+
+        let palette = Palette::from(*palette);
+        for color in 0..4 {
+            let x = 2 + 8 * GLYPH_ADVANCE + color * 10;
+            image.fill(x, y, 9, 9, SHADE[palette.palette_color_index(color as u8) as usize]);
+        }
+    }
+    image
+}
+
+fn maps_image(ppu: &Ppu) -> Image {
+    let mut image = Image::new(MAPS_WINDOW_WIDTH, MAPS_WINDOW_HEIGHT);
+    image.fill(0, 0, image.width, image.height, BACKGROUND_COLOR);
+    for map in 0..2 {
+        let x = map * (TILE_MAP_PIXEL_SIZE + MAP_GAP);
+        draw_text(&mut image, x, 1, if map == 0 { "9800" } else { "9C00" }, TEXT_COLOR);
+        image.draw(&ppu.render_tile_map(map), x, GLYPH_HEIGHT + 3);
+    }
+    image
+}
+
+fn objects_image(ppu: &Ppu) -> Image {
+    let mut image = Image::new(OAM_WINDOW_WIDTH, OAM_WINDOW_HEIGHT);
+    image.fill(0, 0, image.width, image.height, BACKGROUND_COLOR);
+    for (index, sprite) in ppu.sprites().iter().enumerate() {
+        let x = index / 20 * OAM_COLUMN_WIDTH + 2;
+        let y = index % 20 * OAM_ROW_HEIGHT + 1;
+        image.draw(&ppu.render_sprite(index), x, y);
+
+        let flag = |set: bool, name: char| if set { name } else { '-' };
+        let text = format!("{:02} Y{:02X} X{:02X} T{:02X} {}{}{}{}",
+            index, sprite.y(), sprite.x(), sprite.tile(),
+            flag(sprite.priority(), 'P'), flag(sprite.vertical_flip(), 'Y'), flag(sprite.horizontal_flip(), 'X'),
+            sprite.palette_index());
+        draw_text(&mut image, x + TILE_WIDTH + 4, y + 5, &text, TEXT_COLOR);
+    }
+    image
+}
+
+/// Text in the 3x5 font, unknown characters are blank
+pub fn draw_text(image: &mut Image, x: usize, y: usize, text: &str, color: Color) {
+    for (index, character) in text.chars().enumerate() {
+        let rows = GLYPHS.iter().find(|(glyph, _)| *glyph == character).map_or([0; GLYPH_HEIGHT], |(_, rows)| *rows);
+        for (row, bits) in rows.iter().enumerate() {
+            for column in 0..GLYPH_WIDTH {
+                if bits & (4 >> column) != 0 {
+                    image.set_pixel(x + index * GLYPH_ADVANCE + column, y + row, color);
+                }
+            }
+        }
+    }
+}